@@ -1,19 +1,21 @@
 use serde::{Deserialize, Serialize};
+use schemars::JsonSchema;
 use async_trait::async_trait;
 use serde_json::{json, Value};
 use tokio::sync::RwLock;
 use std::{collections::HashMap, sync::Arc};
 use core_agent_architecture::{agent_traits_and_behavior_model::{Agent, AgentInput, AgentResult, AgentStatus}};
+use connecting_llm_api::{ChatMessage, ChatOptions, LlmClient, complete_structured};
 
 // structuring the planner interface
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct PlanStep {
     pub tool_name: String,
     pub args: Value,
     pub output_key: String,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Plan {
     pub goal: String,
     pub steps: Vec<PlanStep>,
@@ -68,6 +70,45 @@ impl Planner for KeywordPlanner {
     }
 }
 
+// an LLM backed planner.
+// the model returns a typed `Plan` directly (validated against its JSON
+// schema), so there is no free-text parsing between the LLM and the executor.
+pub struct LlmPlanner {
+    client: Arc<dyn LlmClient>,
+    tools: Vec<String>,
+    max_repairs: usize,
+}
+
+impl LlmPlanner {
+    pub fn new(client: Arc<dyn LlmClient>, tools: Vec<String>) -> Self {
+        LlmPlanner { client, tools, max_repairs: 2 }
+    }
+}
+
+#[async_trait]
+impl Planner for LlmPlanner {
+    async fn generate_plan(&self, goal: &str) -> Option<Plan> {
+        let messages = vec![
+            ChatMessage::system(format!(
+                "You are a planner. Break the user's goal into a sequence of tool steps. \
+                 Available tools: {}. A step can use the result of an earlier step by \
+                 passing \"${{output_key}}\" as an argument value.",
+                self.tools.join(", ")
+            )),
+            ChatMessage::user(goal),
+        ];
+        let options = ChatOptions::default().with_temperature(0.0);
+
+        match complete_structured::<Plan>(self.client.as_ref(), &messages, &options, self.max_repairs).await {
+            Ok(plan) => Some(plan),
+            Err(e) => {
+                tracing::warn!(goal, error = %e, "LLM planning failed");
+                None
+            }
+        }
+    }
+}
+
 // executing a plan with substitution logic
 fn resolve_args(template: &Value, outputs: &HashMap<String, Value>) -> Value {
     match template {
@@ -4,6 +4,8 @@ use async_trait::async_trait;
 use std::collections::HashMap;
 use tokio::sync::RwLock;
 use std::sync::Arc;
+use connecting_llm_api::embeddings::{cosine_similarity, EmbeddingClient, EmbeddingOptions};
+use connecting_llm_api::LlmResult;
 
 // structuring memory in agent system.
 // a well-designed memory system includes at least three layers:
@@ -35,6 +37,12 @@ pub struct InMemoryStore {
     memory: SharedMemory,
 }
 
+impl InMemoryStore {
+    pub fn new(memory: SharedMemory) -> Self {
+        InMemoryStore { memory }
+    }
+}
+
 #[async_trait]
 impl MemoryStore for InMemoryStore {
     async fn write(&self, entry: MemoryEntry) -> bool {
@@ -63,6 +71,78 @@ impl MemoryStore for InMemoryStore {
     }
 }
 
+// semantic recall on top of any store: entry values are embedded when written
+// and recalled by cosine similarity to a query, so "what did the user say
+// about deadlines" finds an entry even if it never uses that word.
+pub struct SemanticMemory {
+    store: Arc<dyn MemoryStore>,
+    embedder: Arc<dyn EmbeddingClient>,
+    options: EmbeddingOptions,
+    vectors: RwLock<HashMap<String, Vec<f32>>>,
+}
+
+impl SemanticMemory {
+    pub fn new(store: Arc<dyn MemoryStore>, embedder: Arc<dyn EmbeddingClient>) -> Self {
+        SemanticMemory {
+            store,
+            embedder,
+            options: EmbeddingOptions::default().normalized(),
+            vectors: RwLock::new(HashMap::new()),
+        }
+    }
+
+    pub fn with_options(mut self, options: EmbeddingOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub async fn remember(&self, entry: MemoryEntry) -> LlmResult<bool> {
+        Ok(self.remember_all(vec![entry]).await? == 1)
+    }
+
+    // embeds all values in batched requests, then writes the entries
+    pub async fn remember_all(&self, entries: Vec<MemoryEntry>) -> LlmResult<usize> {
+        let values: Vec<String> = entries.iter().map(|entry| entry.value.clone()).collect();
+        let embeddings = self.embedder.embed(&values, &self.options).await?;
+
+        let mut written = 0;
+        let mut vectors = self.vectors.write().await;
+        for (entry, vector) in entries.into_iter().zip(embeddings.vectors) {
+            let key = entry.key.clone();
+            if self.store.write(entry).await {
+                vectors.insert(key, vector);
+                written += 1;
+            }
+        }
+        Ok(written)
+    }
+
+    // the `limit` entries most similar to `query`, best first
+    pub async fn recall(&self, query: &str, limit: usize) -> LlmResult<Vec<(MemoryEntry, f32)>> {
+        let query = self.embedder.embed_one(query, &self.options).await?;
+        let mut scored: Vec<(String, f32)> = self
+            .vectors
+            .read()
+            .await
+            .iter()
+            .map(|(key, vector)| (key.clone(), cosine_similarity(&query, vector)))
+            .collect();
+        scored.sort_by(|a, b| b.1.total_cmp(&a.1));
+
+        let mut recalled = vec![];
+        for (key, score) in scored {
+            if recalled.len() == limit {
+                break;
+            }
+            // entries removed from the underlying store are skipped
+            if let Some(entry) = self.store.read_by_key(&key).await {
+                recalled.push((entry, score));
+            }
+        }
+        Ok(recalled)
+    }
+}
+
 // integrating memory into agent behavior
 // for writing:
 /*
@@ -83,4 +163,15 @@ if let Some(mem) = memory_store.read_be_key("summary_task_001").await {
 }
 */
 
+// for semantic recall:
+/*
+let embedder = Arc::new(OllamaClient::new(OllamaConfig::new("mistral"))?);
+let memory = SemanticMemory::new(Arc::new(InMemoryStore::new(SharedMemory::default())), embedder);
+memory.remember(entry).await?;
+
+for (mem, score) in memory.recall("what was decided about the deadline?", 3).await? {
+    println!("{:.2} {}", score, mem.value);
+}
+*/
+
 // the existing persisten storage can be extended with embedded database like SQLite.
\ No newline at end of file
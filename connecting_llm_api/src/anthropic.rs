@@ -0,0 +1,626 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use async_trait::async_trait;
+use std::{collections::BTreeMap, env, sync::Arc, time::Duration};
+
+use crate::client::{self, ChatCompletion, ChatMessage, ChatOptions, ContentPart, FileSource, LlmClient, ResponseFormat, Role, ToolCall, ToolChoice, ToolDefinition, Usage};
+use crate::cancel;
+use crate::cassette::Cassette;
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::{self, ChatStream, StreamEvent};
+use crate::transport::HttpTransport;
+
+const ANTHROPIC_BASE_URL: &str = "https://api.anthropic.com/v1";
+const ANTHROPIC_VERSION: &str = "2023-06-01";
+
+// wire format of the Messages API.
+// a message is a list of typed content blocks; tool calls are `tool_use`
+// blocks on assistant turns and their results are `tool_result` blocks on
+// the following user turn. there is no `system` or `tool` role.
+#[derive(Serialize, Deserialize)]
+struct AnthropicMessage {
+    role: &'static str,
+    content: Vec<ContentBlock>,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum ContentBlock {
+    Text {
+        text: String,
+    },
+    ToolUse {
+        id: String,
+        name: String,
+        input: Value,
+    },
+    ToolResult {
+        tool_use_id: String,
+        content: String,
+    },
+    Image {
+        source: Source,
+    },
+    Document {
+        source: Source,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        title: Option<String>,
+    },
+    // thinking, ... are not mapped onto `ChatMessage`
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum Source {
+    Base64 { media_type: String, data: String },
+    Url { url: String },
+    // plain-text documents
+    Text { media_type: String, data: String },
+}
+
+fn image_source(url: &str) -> Source {
+    match client::split_data_uri(url) {
+        Some((media_type, data)) => Source::Base64 {
+            media_type: media_type.to_string(),
+            data: data.to_string(),
+        },
+        None => Source::Url { url: url.to_string() },
+    }
+}
+
+// the message text followed by its parts. PDFs go as base64 documents and
+// text files as text documents; uploaded file ids need the beta Files API.
+fn user_blocks(message: &ChatMessage) -> LlmResult<Vec<ContentBlock>> {
+    let mut blocks = vec![];
+    if !message.content.is_empty() {
+        blocks.push(ContentBlock::Text { text: message.content.clone() });
+    }
+    for part in &message.parts {
+        blocks.push(match part {
+            ContentPart::Text { text } => ContentBlock::Text { text: text.clone() },
+            ContentPart::Image { url } => ContentBlock::Image { source: image_source(url) },
+            ContentPart::File {
+                filename,
+                source: FileSource::Data(data),
+            } => {
+                let source = match (client::data_uri_text(data), client::split_data_uri(data)) {
+                    (Some(text), _) => Source::Text {
+                        media_type: "text/plain".into(),
+                        data: text,
+                    },
+                    (None, Some((media_type @ "application/pdf", data))) => Source::Base64 {
+                        media_type: media_type.to_string(),
+                        data: data.to_string(),
+                    },
+                    _ => return Err(LlmError::Unsupported("anthropic documents must be PDF or text".into())),
+                };
+                ContentBlock::Document {
+                    source,
+                    title: filename.clone(),
+                }
+            }
+            ContentPart::File { .. } => {
+                return Err(LlmError::Unsupported("anthropic file ids are not supported; send the document inline".into()));
+            }
+        });
+    }
+    Ok(blocks)
+}
+
+// splits off the system prompt and merges consecutive turns of the same role,
+// since the API requires user and assistant turns to alternate
+fn to_wire(messages: &[ChatMessage]) -> LlmResult<(Option<String>, Vec<AnthropicMessage>)> {
+    let mut system: Vec<&str> = vec![];
+    let mut wire: Vec<AnthropicMessage> = vec![];
+
+    for message in messages {
+        let (role, blocks) = match message.role {
+            Role::System => {
+                system.push(&message.content);
+                continue;
+            }
+            Role::Assistant => {
+                let mut blocks = vec![];
+                if !message.content.is_empty() {
+                    blocks.push(ContentBlock::Text { text: message.content.clone() });
+                }
+                blocks.extend(message.tool_calls.iter().map(|call| ContentBlock::ToolUse {
+                    id: call.id.clone(),
+                    name: call.name.clone(),
+                    input: call.arguments.clone(),
+                }));
+                ("assistant", blocks)
+            }
+            Role::Tool => {
+                let Some(tool_use_id) = message.tool_call_id.clone() else {
+                    return Err(LlmError::InvalidRequest("tool message without a `tool_call_id`".into()));
+                };
+                (
+                    "user",
+                    vec![ContentBlock::ToolResult {
+                        tool_use_id,
+                        content: message.content.clone(),
+                    }],
+                )
+            }
+            Role::User => ("user", user_blocks(message)?),
+        };
+        // the API rejects turns without content, e.g. an assistant reply that was empty
+        if blocks.is_empty() {
+            continue;
+        }
+
+        match wire.last_mut() {
+            Some(last) if last.role == role => last.content.extend(blocks),
+            _ => wire.push(AnthropicMessage { role, content: blocks }),
+        }
+    }
+
+    let system = (!system.is_empty()).then(|| system.join("\n\n"));
+    Ok((system, wire))
+}
+
+fn from_wire(content: Vec<ContentBlock>) -> ChatMessage {
+    let mut message = ChatMessage::assistant("");
+    for block in content {
+        match block {
+            ContentBlock::Text { text } => message.content.push_str(&text),
+            ContentBlock::ToolUse { id, name, input } => message.tool_calls.push(ToolCall { id, name, arguments: input }),
+            ContentBlock::ToolResult { .. } | ContentBlock::Image { .. } | ContentBlock::Document { .. } | ContentBlock::Other => {}
+        }
+    }
+    message
+}
+
+// stop reasons in the vocabulary the other providers use
+fn finish_reason(stop_reason: Option<String>) -> Option<String> {
+    stop_reason.map(|reason| match reason.as_str() {
+        "end_turn" | "stop_sequence" => "stop".to_string(),
+        "tool_use" => "tool_calls".to_string(),
+        "max_tokens" => "length".to_string(),
+        _ => reason,
+    })
+}
+
+#[derive(Serialize)]
+struct AnthropicTool<'a> {
+    name: &'a str,
+    description: &'a str,
+    input_schema: &'a Value,
+}
+
+impl<'a> From<&'a ToolDefinition> for AnthropicTool<'a> {
+    fn from(tool: &'a ToolDefinition) -> Self {
+        AnthropicTool {
+            name: &tool.name,
+            description: &tool.description,
+            input_schema: &tool.parameters,
+        }
+    }
+}
+
+fn tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!({"type": "auto"}),
+        ToolChoice::None => json!({"type": "none"}),
+        ToolChoice::Required => json!({"type": "any"}),
+        ToolChoice::Tool(name) => json!({"type": "tool", "name": name}),
+    }
+}
+
+// the Messages API has no JSON mode, so the format becomes an instruction;
+// `complete_structured` validates and repairs whatever comes back
+fn format_instruction(format: &ResponseFormat) -> String {
+    match format {
+        ResponseFormat::Json => "Respond with a single JSON object and nothing else.".to_string(),
+        ResponseFormat::JsonSchema { schema, .. } => format!(
+            "Respond with a single JSON object matching this JSON schema and nothing else:\n{}",
+            schema
+        ),
+    }
+}
+
+#[derive(Serialize)]
+struct MessagesRequest<'a> {
+    model: &'a str,
+    max_tokens: u32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<String>,
+    messages: Vec<AnthropicMessage>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<AnthropicTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+}
+
+#[derive(Deserialize)]
+struct MessagesResponse {
+    model: String,
+    content: Vec<ContentBlock>,
+    stop_reason: Option<String>,
+    usage: AnthropicUsage,
+}
+
+#[derive(Deserialize, Default)]
+struct AnthropicUsage {
+    #[serde(default)]
+    input_tokens: u32,
+    #[serde(default)]
+    output_tokens: u32,
+}
+
+impl From<AnthropicUsage> for Usage {
+    fn from(usage: AnthropicUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.input_tokens,
+            completion_tokens: usage.output_tokens,
+        }
+    }
+}
+
+// the `data:` payload of a streamed event
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum StreamPayload {
+    MessageStart { message: StartedMessage },
+    ContentBlockStart { index: usize, content_block: ContentBlock },
+    ContentBlockDelta { index: usize, delta: BlockDelta },
+    MessageDelta {
+        delta: MessageDelta,
+        #[serde(default)]
+        usage: AnthropicUsage,
+    },
+    MessageStop,
+    Error { error: StreamError },
+    // ping, content_block_stop
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct StartedMessage {
+    #[serde(default)]
+    usage: AnthropicUsage,
+    model: Option<String>,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum BlockDelta {
+    TextDelta { text: String },
+    InputJsonDelta { partial_json: String },
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Deserialize)]
+struct MessageDelta {
+    stop_reason: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StreamError {
+    #[serde(rename = "type")]
+    kind: String,
+    message: String,
+}
+
+#[derive(Default)]
+struct PartialToolUse {
+    id: String,
+    name: String,
+    input: String,
+}
+
+// everything needed to build an Anthropic client.
+// ensure you load the API key from .env file or secure secret manager.
+#[derive(Debug, Clone)]
+pub struct AnthropicConfig {
+    pub api_key: String,
+    pub model: String,
+    // required by the API on every request
+    pub max_tokens: u32,
+    pub timeout: Duration,
+    pub base_url: String,
+}
+
+impl AnthropicConfig {
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        AnthropicConfig {
+            api_key: api_key.into(),
+            model: model.into(),
+            max_tokens: 4096,
+            timeout: Duration::from_secs(60),
+            base_url: ANTHROPIC_BASE_URL.to_string(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    // reads ANTHROPIC_API_KEY from the environment (or a .env file)
+    pub fn from_env(model: impl Into<String>) -> Result<Self, env::VarError> {
+        dotenv::dotenv().ok();
+        let api_key = env::var("ANTHROPIC_API_KEY")?;
+        Ok(Self::new(api_key, model))
+    }
+}
+
+pub struct AnthropicClient {
+    config: AnthropicConfig,
+    http: HttpTransport,
+}
+
+impl AnthropicClient {
+    pub fn new(config: AnthropicConfig) -> LlmResult<Self> {
+        let http = HttpTransport::new(config.timeout)?;
+        Ok(AnthropicClient { config, http })
+    }
+
+    // records to or replays from `cassette` instead of the LLM_CASSETTE one
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.http = self.http.with_cassette(cassette);
+        self
+    }
+
+    fn request<'a>(&'a self, messages: &'a [ChatMessage], options: &'a ChatOptions, stream: bool) -> LlmResult<MessagesRequest<'a>> {
+        let (mut system, messages) = to_wire(messages)?;
+        if let Some(format) = &options.response_format {
+            let instruction = format_instruction(format);
+            system = Some(match system {
+                Some(system) => format!("{}\n\n{}", system, instruction),
+                None => instruction,
+            });
+        }
+
+        Ok(MessagesRequest {
+            model: options.model.as_deref().unwrap_or(&self.config.model),
+            max_tokens: options.max_tokens.unwrap_or(self.config.max_tokens),
+            system,
+            messages,
+            temperature: options.temperature,
+            stop_sequences: &options.stop,
+            tools: options.tools.iter().map(AnthropicTool::from).collect(),
+            tool_choice: options.tool_choice.as_ref().map(tool_choice),
+            stream,
+        })
+    }
+
+    async fn post(&self, body: &MessagesRequest<'_>) -> LlmResult<reqwest::Response> {
+        let request = self.http
+            .client()
+            .post(format!("{}/messages", self.config.base_url.trim_end_matches('/')))
+            .header("x-api-key", &self.config.api_key)
+            .header("anthropic-version", ANTHROPIC_VERSION)
+            .json(body);
+        if body.stream {
+            self.http.send_streaming(request).await
+        } else {
+            self.http.send(request).await
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for AnthropicClient {
+    fn provider(&self) -> &'static str {
+        "anthropic"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.config.model
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        Some(&self.config.base_url)
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let request_body = self.request(messages, options, false)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), async {
+            Ok(self.post(&request_body).await?.json::<MessagesResponse>().await?)
+        })
+        .await?;
+
+        let message = from_wire(response.content);
+        if response.stop_reason.as_deref() == Some("refusal") && message.content.is_empty() {
+            return Err(LlmError::ContentFiltered("the model refused to answer".into()));
+        }
+
+        Ok(ChatCompletion {
+            message,
+            model: response.model,
+            finish_reason: finish_reason(response.stop_reason),
+            usage: Some(response.usage.into()),
+        })
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let request_body = self.request(messages, options, true)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), self.post(&request_body)).await?;
+
+        let mut usage = Usage::default();
+        let mut model = None;
+        let mut stop_reason = None;
+        let mut tool_uses: BTreeMap<usize, PartialToolUse> = BTreeMap::new();
+        let lines = streaming::lines(response.bytes_stream());
+
+        let events = streaming::events(lines, move |line| {
+            // server-sent events: the `event:` line repeats the payload's type
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(vec![]);
+            };
+
+            match serde_json::from_str::<StreamPayload>(data)? {
+                StreamPayload::MessageStart { message } => {
+                    usage.prompt_tokens = message.usage.input_tokens;
+                    model = message.model;
+                }
+                StreamPayload::ContentBlockStart { index, content_block: ContentBlock::ToolUse { id, name, .. } } => {
+                    tool_uses.insert(index, PartialToolUse { id, name, input: String::new() });
+                }
+                StreamPayload::ContentBlockStart { content_block: ContentBlock::Text { text }, .. } if !text.is_empty() => {
+                    return Ok(vec![StreamEvent::Delta(text)]);
+                }
+                StreamPayload::ContentBlockDelta { delta: BlockDelta::TextDelta { text }, .. } => {
+                    return Ok(vec![StreamEvent::Delta(text)]);
+                }
+                StreamPayload::ContentBlockDelta { index, delta: BlockDelta::InputJsonDelta { partial_json } } => {
+                    tool_uses.entry(index).or_default().input.push_str(&partial_json);
+                }
+                StreamPayload::MessageDelta { delta, usage: delta_usage } => {
+                    stop_reason = delta.stop_reason;
+                    usage.completion_tokens = delta_usage.output_tokens;
+                }
+                StreamPayload::MessageStop => {
+                    let mut events = vec![];
+                    if !tool_uses.is_empty() {
+                        let calls = std::mem::take(&mut tool_uses)
+                            .into_values()
+                            .map(|call| ToolCall {
+                                id: call.id,
+                                name: call.name,
+                                // a tool without parameters streams no input at all
+                                arguments: if call.input.is_empty() {
+                                    json!({})
+                                } else {
+                                    serde_json::from_str(&call.input).unwrap_or(Value::String(call.input))
+                                },
+                            })
+                            .collect();
+                        events.push(StreamEvent::ToolCalls(calls));
+                    }
+                    events.push(StreamEvent::Done {
+                        finish_reason: finish_reason(stop_reason.take()),
+                        usage: Some(usage),
+                        model: model.take(),
+                    });
+                    return Ok(events);
+                }
+                // errors after the 200, e.g. the model becoming overloaded mid-stream
+                StreamPayload::Error { error } => {
+                    return Err(match error.kind.as_str() {
+                        "overloaded_error" => LlmError::Unavailable { retry_after: None, message: error.message },
+                        "rate_limit_error" => LlmError::RateLimited { retry_after: None, message: error.message },
+                        _ => LlmError::Api { status: 500, message: error.message },
+                    });
+                }
+                _ => {}
+            }
+            Ok(vec![])
+        });
+        Ok(cancel::guard(events, options.cancel.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn calling(ids: &[&str]) -> ChatMessage {
+        ChatMessage {
+            tool_calls: ids
+                .iter()
+                .map(|id| ToolCall { id: id.to_string(), name: "get_weather".into(), arguments: json!({"city": id}) })
+                .collect(),
+            ..ChatMessage::assistant("checking")
+        }
+    }
+
+    #[test]
+    fn system_prompts_are_split_off_and_turns_alternate() {
+        let messages = [
+            ChatMessage::system("be brief"),
+            ChatMessage::user("weather?"),
+            calling(&["a", "b"]),
+            ChatMessage::tool("a", "sunny"),
+            ChatMessage::tool("b", "rain"),
+            ChatMessage::system("answer in French"),
+            ChatMessage::user("and now?"),
+            ChatMessage::assistant(""),
+        ];
+        let (system, wire) = to_wire(&messages).unwrap();
+        assert_eq!(system.as_deref(), Some("be brief\n\nanswer in French"));
+
+        // both results and the next question share one user turn; the empty
+        // assistant reply is dropped
+        let wire = serde_json::to_value(&wire).unwrap();
+        assert_eq!(
+            wire,
+            json!([
+                {"role": "user", "content": [{"type": "text", "text": "weather?"}]},
+                {"role": "assistant", "content": [
+                    {"type": "text", "text": "checking"},
+                    {"type": "tool_use", "id": "a", "name": "get_weather", "input": {"city": "a"}},
+                    {"type": "tool_use", "id": "b", "name": "get_weather", "input": {"city": "b"}},
+                ]},
+                {"role": "user", "content": [
+                    {"type": "tool_result", "tool_use_id": "a", "content": "sunny"},
+                    {"type": "tool_result", "tool_use_id": "b", "content": "rain"},
+                    {"type": "text", "text": "and now?"},
+                ]},
+            ])
+        );
+    }
+
+    #[test]
+    fn attachments_become_image_and_document_blocks() {
+        let message = ChatMessage::user("")
+            .with_part(ContentPart::image_bytes(b"GIF8", "image/gif"))
+            .with_part(ContentPart::image_url("https://example.com/cat.png"))
+            .with_part(ContentPart::file_bytes("report.pdf", b"%PDF", "application/pdf"))
+            .with_part(ContentPart::file_bytes("notes.txt", b"hi", "text/plain"));
+        let blocks = serde_json::to_value(user_blocks(&message).unwrap()).unwrap();
+        assert_eq!(
+            blocks,
+            json!([
+                {"type": "image", "source": {"type": "base64", "media_type": "image/gif", "data": "R0lGOA=="}},
+                {"type": "image", "source": {"type": "url", "url": "https://example.com/cat.png"}},
+                {"type": "document", "source": {"type": "base64", "media_type": "application/pdf", "data": "JVBERg=="}, "title": "report.pdf"},
+                {"type": "document", "source": {"type": "text", "media_type": "text/plain", "data": "hi"}, "title": "notes.txt"},
+            ])
+        );
+
+        for unsupported in [ContentPart::file_id("file-123"), ContentPart::file_bytes("sheet.xlsx", b"PK", "application/zip")] {
+            let message = ChatMessage::user("read this").with_part(unsupported);
+            assert!(matches!(user_blocks(&message), Err(LlmError::Unsupported(_))));
+        }
+    }
+
+    #[test]
+    fn tool_results_need_their_call_id() {
+        let orphan = ChatMessage { tool_call_id: None, ..ChatMessage::tool("", "sunny") };
+        assert!(matches!(to_wire(&[orphan]), Err(LlmError::InvalidRequest(_))));
+    }
+
+    #[test]
+    fn replies_keep_text_and_tool_uses() {
+        let content: Vec<ContentBlock> = serde_json::from_value(json!([
+            {"type": "thinking", "thinking": "hmm"},
+            {"type": "text", "text": "let me "},
+            {"type": "text", "text": "check"},
+            {"type": "tool_use", "id": "toolu_1", "name": "get_weather", "input": {"city": "Paris"}},
+        ]))
+        .unwrap();
+        let message = from_wire(content);
+        assert_eq!(message.content, "let me check");
+        assert_eq!(message.tool_calls, [ToolCall { id: "toolu_1".into(), name: "get_weather".into(), arguments: json!({"city": "Paris"}) }]);
+    }
+
+    #[test]
+    fn stop_reasons_use_the_shared_vocabulary() {
+        let mapped = ["end_turn", "stop_sequence", "tool_use", "max_tokens", "refusal"].map(|reason| finish_reason(Some(reason.into())).unwrap());
+        assert_eq!(mapped, ["stop", "stop", "tool_calls", "length", "refusal"]);
+        assert_eq!(finish_reason(None), None);
+    }
+}
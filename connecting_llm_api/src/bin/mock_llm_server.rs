@@ -0,0 +1,31 @@
+// standalone mock LLM server.
+//
+//   cargo run --features mock-server --bin mock_llm_server -- --addr 127.0.0.1:11435 --config mock.json
+//
+// the config file is a JSON `MockConfig` with a `script` of replies served in
+// order and `rules` matched against the last message of each request.
+
+use connecting_llm_api::mock_server::{MockConfig, MockServer};
+use std::{env, fs, net::SocketAddr};
+
+#[tokio::main]
+async fn main() -> Result<(), Box<dyn std::error::Error>> {
+    let mut addr: SocketAddr = "127.0.0.1:11435".parse()?;
+    let mut config = MockConfig::default();
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match (arg.as_str(), args.next()) {
+            ("--addr", Some(value)) => addr = value.parse()?,
+            ("--config", Some(path)) => config = serde_json::from_str(&fs::read_to_string(path)?)?,
+            _ => return Err(format!("usage: mock_llm_server [--addr HOST:PORT] [--config FILE], got {}", arg).into()),
+        }
+    }
+
+    let server = MockServer::bind(addr, config).await?;
+    println!("mock LLM server listening on {}", server.url());
+    println!("  OpenAI: {}/chat/completions", server.openai_base_url());
+    println!("  ollama: {}/api/chat", server.url());
+    server.wait().await;
+    Ok(())
+}
@@ -0,0 +1,386 @@
+use async_trait::async_trait;
+use futures::stream;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    path::PathBuf,
+    sync::Mutex,
+    time::{Duration, SystemTime, UNIX_EPOCH},
+};
+
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient, ResponseFormat, ToolChoice, ToolDefinition};
+use crate::error::LlmResult;
+use crate::ollama::OllamaOptions;
+use crate::streaming::{ChatStream, StreamEvent};
+
+// everything that influences a reply; the cache key is the SHA-256 of its JSON
+#[derive(Serialize)]
+struct KeyMaterial<'a> {
+    provider: &'a str,
+    // OpenAI and a local OpenAI-compatible server may both serve "gpt-4o"
+    endpoint: Option<&'a str>,
+    model: &'a str,
+    messages: &'a [ChatMessage],
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    stop: &'a [String],
+    tools: &'a [ToolDefinition],
+    tool_choice: &'a Option<ToolChoice>,
+    response_format: &'a Option<ResponseFormat>,
+    // a different seed or top_p gives a different answer
+    ollama_options: &'a Option<OllamaOptions>,
+}
+
+pub fn cache_key(provider: &str, endpoint: Option<&str>, model: &str, messages: &[ChatMessage], options: &ChatOptions) -> String {
+    let material = KeyMaterial {
+        provider,
+        endpoint,
+        model,
+        messages,
+        temperature: options.temperature,
+        max_tokens: options.max_tokens,
+        stop: &options.stop,
+        tools: &options.tools,
+        tool_choice: &options.tool_choice,
+        response_format: &options.response_format,
+        ollama_options: &options.ollama_options,
+    };
+    let json = serde_json::to_vec(&material).unwrap_or_default();
+    hex::encode(Sha256::digest(json))
+}
+
+// a stored reply with its expiry as unix milliseconds (None = never expires)
+#[derive(Clone, Serialize, Deserialize)]
+struct CacheEntry {
+    completion: ChatCompletion,
+    expires_at: Option<u64>,
+}
+
+impl CacheEntry {
+    fn new(completion: ChatCompletion, ttl: Option<Duration>) -> Self {
+        CacheEntry {
+            completion,
+            expires_at: ttl.map(|ttl| unix_now().saturating_add(ttl.as_millis().try_into().unwrap_or(u64::MAX))),
+        }
+    }
+
+    fn is_fresh(&self) -> bool {
+        self.expires_at.is_none_or(|at| unix_now() < at)
+    }
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now().duration_since(UNIX_EPOCH).map(|d| d.as_millis() as u64).unwrap_or(0)
+}
+
+// where cached replies live
+#[async_trait]
+pub trait CacheBackend: Send + Sync {
+    async fn get(&self, key: &str) -> Option<ChatCompletion>;
+    async fn put(&self, key: &str, completion: &ChatCompletion, ttl: Option<Duration>);
+}
+
+// process-local cache, lost on restart
+#[derive(Default)]
+pub struct InMemoryCache {
+    entries: Mutex<HashMap<String, CacheEntry>>,
+}
+
+impl InMemoryCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+}
+
+#[async_trait]
+impl CacheBackend for InMemoryCache {
+    async fn get(&self, key: &str) -> Option<ChatCompletion> {
+        let mut entries = self.entries.lock().unwrap();
+        match entries.get(key) {
+            Some(entry) if entry.is_fresh() => Some(entry.completion.clone()),
+            Some(_) => {
+                entries.remove(key);
+                None
+            }
+            None => None,
+        }
+    }
+
+    async fn put(&self, key: &str, completion: &ChatCompletion, ttl: Option<Duration>) {
+        let entry = CacheEntry::new(completion.clone(), ttl);
+        self.entries.lock().unwrap().insert(key.to_string(), entry);
+    }
+}
+
+// one JSON file per key in a directory, so it survives restarts and can be
+// shared between developers running the same prompts
+pub struct DiskCache {
+    dir: PathBuf,
+}
+
+impl DiskCache {
+    pub fn new(dir: impl Into<PathBuf>) -> Self {
+        DiskCache { dir: dir.into() }
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        self.dir.join(format!("{}.json", key))
+    }
+}
+
+#[async_trait]
+impl CacheBackend for DiskCache {
+    async fn get(&self, key: &str) -> Option<ChatCompletion> {
+        let bytes = tokio::fs::read(self.path(key)).await.ok()?;
+        let entry: CacheEntry = serde_json::from_slice(&bytes).ok()?;
+        if entry.is_fresh() {
+            Some(entry.completion)
+        } else {
+            let _ = tokio::fs::remove_file(self.path(key)).await;
+            None
+        }
+    }
+
+    // a cache write failing must never fail the request, so errors are only logged
+    async fn put(&self, key: &str, completion: &ChatCompletion, ttl: Option<Duration>) {
+        let entry = CacheEntry::new(completion.clone(), ttl);
+        let written = async {
+            tokio::fs::create_dir_all(&self.dir).await?;
+            let json = serde_json::to_vec_pretty(&entry)?;
+            tokio::fs::write(self.path(key), json).await
+        };
+        if let Err(e) = written.await {
+            tracing::warn!(target: "llm::cache", key, error = %e, "failed to write cache entry");
+        }
+    }
+}
+
+// serves repeated requests from a cache instead of the provider.
+// by default only deterministic requests (temperature 0, set on the request or
+// by the client's config) are cached, since replaying a sampled reply would
+// hide the model's variance.
+pub struct CachedClient<C, B> {
+    inner: C,
+    backend: B,
+    ttl: Option<Duration>,
+    deterministic_only: bool,
+}
+
+impl<C: LlmClient, B: CacheBackend> CachedClient<C, B> {
+    pub fn new(inner: C, backend: B) -> Self {
+        CachedClient {
+            inner,
+            backend,
+            ttl: None,
+            deterministic_only: true,
+        }
+    }
+
+    pub fn with_ttl(mut self, ttl: Duration) -> Self {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    // also cache requests sampled with a non-zero temperature
+    pub fn cache_all(mut self) -> Self {
+        self.deterministic_only = false;
+        self
+    }
+
+    fn key_for(&self, messages: &[ChatMessage], options: &ChatOptions) -> Option<String> {
+        let temperature = options.temperature.or(self.inner.default_temperature());
+        if options.no_cache || (self.deterministic_only && temperature != Some(0.0)) {
+            return None;
+        }
+        let model = options.model.as_deref().unwrap_or(self.inner.default_model());
+        let options = ChatOptions { temperature, ..options.clone() };
+        Some(cache_key(self.inner.provider(), self.inner.endpoint(), model, messages, &options))
+    }
+}
+
+#[async_trait]
+impl<C: LlmClient, B: CacheBackend> LlmClient for CachedClient<C, B> {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        self.inner.endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.inner.default_temperature()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let Some(key) = self.key_for(messages, options) else {
+            return self.inner.chat(messages, options).await;
+        };
+
+        if let Some(completion) = self.backend.get(&key).await {
+            tracing::debug!(target: "llm::cache", key, "cache hit");
+            return Ok(completion);
+        }
+
+        let completion = self.inner.chat(messages, options).await?;
+        self.backend.put(&key, &completion, self.ttl).await;
+        Ok(completion)
+    }
+
+    // a cached reply is replayed as a single-delta stream; misses are streamed
+    // from the provider and not cached
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        if let Some(key) = self.key_for(messages, options)
+            && let Some(completion) = self.backend.get(&key).await
+        {
+            tracing::debug!(target: "llm::cache", key, "cache hit");
+            let mut events = vec![];
+            if !completion.message.content.is_empty() {
+                events.push(Ok(StreamEvent::Delta(completion.message.content)));
+            }
+            if !completion.message.tool_calls.is_empty() {
+                events.push(Ok(StreamEvent::ToolCalls(completion.message.tool_calls)));
+            }
+            events.push(Ok(StreamEvent::Done {
+                finish_reason: completion.finish_reason,
+                usage: completion.usage,
+                model: Some(completion.model),
+            }));
+            return Ok(Box::pin(stream::iter(events)));
+        }
+
+        self.inner.chat_stream(messages, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::openai::{OpenAiClient, OpenAiConfig};
+    use futures::StreamExt;
+    use std::sync::Arc;
+
+    #[test]
+    fn endpoints_serving_the_same_model_get_different_keys() {
+        let openai = OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o")).unwrap();
+        let local = OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url("http://localhost:8000/v1")).unwrap();
+        let messages = [ChatMessage::user("hello")];
+        let options = ChatOptions::default();
+
+        let key = |client: &OpenAiClient| cache_key(client.provider(), client.endpoint(), client.default_model(), &messages, &options);
+        assert_ne!(key(&openai), key(&local));
+        assert_eq!(key(&local), key(&local));
+    }
+
+    // counts the calls that reach the provider
+    struct Counting {
+        temperature: Option<f32>,
+        calls: std::sync::atomic::AtomicUsize,
+    }
+
+    impl Counting {
+        fn new(temperature: Option<f32>) -> Self {
+            Counting { temperature, calls: Default::default() }
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(std::sync::atomic::Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl LlmClient for Counting {
+        fn provider(&self) -> &'static str {
+            "openai"
+        }
+
+        fn default_model(&self) -> &str {
+            "gpt-4o"
+        }
+
+        fn default_temperature(&self) -> Option<f32> {
+            self.temperature
+        }
+
+        async fn chat(&self, messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            let call = self.calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            Ok(ChatCompletion {
+                message: ChatMessage::assistant(format!("reply {} to {}", call, messages[0].content)),
+                model: "gpt-4o".into(),
+                finish_reason: Some("stop".into()),
+                usage: None,
+            })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            unimplemented!("not streamed in these tests")
+        }
+    }
+
+    #[tokio::test]
+    async fn deterministic_requests_hit_the_cache() {
+        let client = CachedClient::new(Arc::new(Counting::new(None)), InMemoryCache::new());
+        let deterministic = ChatOptions::default().with_temperature(0.0);
+        let first = client.chat(&[ChatMessage::user("a")], &deterministic).await.unwrap();
+        let again = client.chat(&[ChatMessage::user("a")], &deterministic).await.unwrap();
+        assert_eq!(first.content(), again.content());
+        assert_eq!(client.inner.calls(), 1);
+
+        // another prompt, a sampled request and an opted-out one all miss
+        client.chat(&[ChatMessage::user("b")], &deterministic).await.unwrap();
+        client.chat(&[ChatMessage::user("a")], &ChatOptions::default().with_temperature(0.7)).await.unwrap();
+        client.chat(&[ChatMessage::user("a")], &deterministic.clone().without_cache()).await.unwrap();
+        assert_eq!(client.inner.calls(), 4);
+
+        // the stream of a cached reply is replayed
+        let events: Vec<_> = client.chat_stream(&[ChatMessage::user("a")], &deterministic).await.unwrap().collect().await;
+        assert!(matches!(&events[0], Ok(StreamEvent::Delta(text)) if text == first.content()));
+        assert_eq!(client.inner.calls(), 4);
+    }
+
+    #[tokio::test]
+    async fn a_client_configured_at_temperature_zero_is_cached() {
+        let client = CachedClient::new(Arc::new(Counting::new(Some(0.0))), InMemoryCache::new());
+        for _ in 0..2 {
+            client.chat(&[ChatMessage::user("a")], &ChatOptions::default()).await.unwrap();
+        }
+        assert_eq!(client.inner.calls(), 1);
+    }
+
+    #[tokio::test]
+    async fn sub_second_ttls_expire() {
+        let client = CachedClient::new(Arc::new(Counting::new(Some(0.0))), InMemoryCache::new()).with_ttl(Duration::from_millis(200));
+        client.chat(&[ChatMessage::user("a")], &ChatOptions::default()).await.unwrap();
+        client.chat(&[ChatMessage::user("a")], &ChatOptions::default()).await.unwrap();
+        assert_eq!(client.inner.calls(), 1);
+        tokio::time::sleep(Duration::from_millis(300)).await;
+        client.chat(&[ChatMessage::user("a")], &ChatOptions::default()).await.unwrap();
+        assert_eq!(client.inner.calls(), 2);
+    }
+
+    #[tokio::test]
+    async fn disk_entries_survive_a_new_client() {
+        let dir = std::env::temp_dir().join(format!("llm-cache-test-{}", std::process::id()));
+        async fn ask(client: &CachedClient<Arc<Counting>, DiskCache>) -> ChatCompletion {
+            client.chat(&[ChatMessage::user("a")], &ChatOptions::default()).await.unwrap()
+        }
+
+        let inner = Arc::new(Counting::new(Some(0.0)));
+        let first = ask(&CachedClient::new(inner.clone(), DiskCache::new(&dir))).await;
+        let second = ask(&CachedClient::new(inner.clone(), DiskCache::new(&dir))).await;
+        assert_eq!(first.content(), second.content());
+        assert_eq!(inner.calls(), 1);
+
+        // an expired file is dropped and refetched
+        let expiring = CachedClient::new(inner.clone(), DiskCache::new(&dir)).with_ttl(Duration::ZERO);
+        expiring.chat(&[ChatMessage::user("b")], &ChatOptions::default()).await.unwrap();
+        expiring.chat(&[ChatMessage::user("b")], &ChatOptions::default()).await.unwrap();
+        assert_eq!(inner.calls(), 3);
+        let _ = std::fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,49 @@
+use futures::{StreamExt, stream};
+use std::future::Future;
+
+pub use tokio_util::sync::CancellationToken;
+
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::ChatStream;
+
+// cancellation of in-flight calls.
+// a request carries an optional `CancellationToken` in `ChatOptions::cancel`.
+// once the token is cancelled, whatever the call is waiting on (the HTTP
+// response, a retry backoff, a rate limit slot) is dropped, which closes the
+// connection, and the call returns `LlmError::Cancelled`. agents hand out child
+// tokens of their shutdown token, so shutting an agent down aborts its calls.
+
+// runs `future` unless `token` is cancelled first
+pub(crate) async fn cancellable<T>(token: Option<&CancellationToken>, future: impl Future<Output = LlmResult<T>>) -> LlmResult<T> {
+    let Some(token) = token else {
+        return future.await;
+    };
+    tokio::select! {
+        // an already cancelled token must not start the request at all
+        biased;
+        _ = token.cancelled() => {
+            tracing::debug!(target: "llm::cancel", "LLM request cancelled");
+            Err(LlmError::Cancelled)
+        }
+        result = future => result,
+    }
+}
+
+// ends `events` with `LlmError::Cancelled` once `token` is cancelled,
+// dropping the underlying response
+pub(crate) fn guard(events: ChatStream, token: Option<CancellationToken>) -> ChatStream {
+    let Some(token) = token else {
+        return events;
+    };
+    Box::pin(stream::unfold(Some((events, token)), |state| async move {
+        let (mut events, token) = state?;
+        tokio::select! {
+            biased;
+            _ = token.cancelled() => {
+                tracing::debug!(target: "llm::cancel", "LLM stream cancelled");
+                Some((Err(LlmError::Cancelled), None))
+            }
+            event = events.next() => event.map(|event| (event, Some((events, token)))),
+        }
+    }))
+}
@@ -0,0 +1,279 @@
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{
+    collections::BTreeMap,
+    env, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use crate::error::{LlmError, LlmResult};
+
+// record/replay of LLM HTTP traffic.
+// in `Record` mode every request goes to the real provider and the exchange is
+// appended to a cassette file; in `Replay` mode requests are answered from the
+// file and never leave the process, so CI can run without API keys or a
+// local ollama. streamed bodies are stored line by line and replayed as
+// separate chunks, so SSE/NDJSON parsing is exercised exactly as in production.
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CassetteMode {
+    Record,
+    Replay,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RecordedRequest {
+    pub method: String,
+    pub url: String,
+    // JSON bodies are stored parsed so cassettes diff nicely; Null when empty
+    pub body: Value,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RecordedResponse {
+    pub status: u16,
+    pub headers: BTreeMap<String, String>,
+    pub chunks: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Interaction {
+    pub request: RecordedRequest,
+    pub response: RecordedResponse,
+}
+
+// decides which recorded request answers an incoming one.
+// identical requests are replayed in the order they were recorded.
+#[derive(Debug, Clone)]
+pub struct MatchRules {
+    pub method: bool,
+    // compare only path and query, so a cassette recorded against one host
+    // can be replayed against another
+    pub path_only: bool,
+    pub body: bool,
+    // top-level body fields left out of the comparison (e.g. "stream", "seed")
+    pub ignore_body_fields: Vec<String>,
+}
+
+impl Default for MatchRules {
+    fn default() -> Self {
+        MatchRules {
+            method: true,
+            path_only: true,
+            body: true,
+            ignore_body_fields: vec![],
+        }
+    }
+}
+
+impl MatchRules {
+    pub fn ignoring_body_field(mut self, field: impl Into<String>) -> Self {
+        self.ignore_body_fields.push(field.into());
+        self
+    }
+
+    pub fn matches(&self, recorded: &RecordedRequest, incoming: &RecordedRequest) -> bool {
+        if self.method && recorded.method != incoming.method {
+            return false;
+        }
+        let same_url = if self.path_only {
+            path_and_query(&recorded.url) == path_and_query(&incoming.url)
+        } else {
+            recorded.url == incoming.url
+        };
+        if !same_url {
+            return false;
+        }
+        !self.body || self.strip(&recorded.body) == self.strip(&incoming.body)
+    }
+
+    fn strip(&self, body: &Value) -> Value {
+        let mut body = body.clone();
+        if let Value::Object(map) = &mut body {
+            for field in &self.ignore_body_fields {
+                map.remove(field);
+            }
+        }
+        body
+    }
+}
+
+fn path_and_query(url: &str) -> &str {
+    let without_scheme = url.split_once("://").map(|(_, rest)| rest).unwrap_or(url);
+    without_scheme.find('/').map(|i| &without_scheme[i..]).unwrap_or("/")
+}
+
+pub struct Cassette {
+    path: PathBuf,
+    mode: CassetteMode,
+    rules: MatchRules,
+    interactions: Mutex<Vec<Interaction>>,
+    replayed: Mutex<Vec<bool>>,
+    // why the file could not be loaded; every request then fails with it
+    load_error: Option<String>,
+}
+
+impl Cassette {
+    // starts a fresh recording, overwriting `path` on the first interaction
+    pub fn record(path: impl Into<PathBuf>) -> Self {
+        Cassette {
+            path: path.into(),
+            mode: CassetteMode::Record,
+            rules: MatchRules::default(),
+            interactions: Mutex::new(vec![]),
+            replayed: Mutex::new(vec![]),
+            load_error: None,
+        }
+    }
+
+    // a replay cassette that answers nothing. used when LLM_CASSETTE names a
+    // file that cannot be loaded: replay was asked for, so falling back to the
+    // network would run tests live (and without keys).
+    fn unusable(path: impl Into<PathBuf>, error: impl ToString) -> Self {
+        Cassette {
+            path: path.into(),
+            mode: CassetteMode::Replay,
+            rules: MatchRules::default(),
+            interactions: Mutex::new(vec![]),
+            replayed: Mutex::new(vec![]),
+            load_error: Some(error.to_string()),
+        }
+    }
+
+    pub fn replay(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = fs::read_to_string(path.as_ref())?;
+        let interactions: Vec<Interaction> = serde_json::from_str(&json)?;
+        Ok(Cassette {
+            path: path.as_ref().to_path_buf(),
+            mode: CassetteMode::Replay,
+            rules: MatchRules::default(),
+            replayed: Mutex::new(vec![false; interactions.len()]),
+            interactions: Mutex::new(interactions),
+            load_error: None,
+        })
+    }
+
+    pub fn with_rules(mut self, rules: MatchRules) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    pub fn mode(&self) -> CassetteMode {
+        self.mode
+    }
+
+    pub fn interactions(&self) -> Vec<Interaction> {
+        self.interactions.lock().unwrap().clone()
+    }
+
+    // the process-wide cassette configured through the environment:
+    //   LLM_CASSETTE=tests/cassettes/chat.json
+    //   LLM_CASSETTE_MODE=record | replay   (default: replay)
+    // every client built without an explicit cassette picks this one up, which
+    // is how `send_to_openai` / `send_to_ollama` become testable offline.
+    pub fn from_env() -> Option<Arc<Cassette>> {
+        static GLOBAL: OnceLock<Option<Arc<Cassette>>> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| {
+                let path = env::var("LLM_CASSETTE").ok()?;
+                let cassette = match env::var("LLM_CASSETTE_MODE").as_deref() {
+                    Ok("record") => Cassette::record(&path),
+                    _ => Cassette::replay(&path).unwrap_or_else(|e| {
+                        tracing::error!(target: "llm::cassette", path, error = %e, "failed to load cassette");
+                        Cassette::unusable(&path, e)
+                    }),
+                };
+                Some(Arc::new(cassette))
+            })
+            .clone()
+    }
+
+    // the next unused interaction matching `request`
+    pub fn find(&self, request: &RecordedRequest) -> LlmResult<RecordedResponse> {
+        if let Some(error) = &self.load_error {
+            return Err(LlmError::Replay(format!("cannot load {}: {}", self.path.display(), error)));
+        }
+        let interactions = self.interactions.lock().unwrap();
+        let mut replayed = self.replayed.lock().unwrap();
+        let position = interactions
+            .iter()
+            .enumerate()
+            .position(|(i, interaction)| !replayed[i] && self.rules.matches(&interaction.request, request))
+            .ok_or_else(|| {
+                LlmError::Replay(format!(
+                    "no interaction in {} matches {} {}",
+                    self.path.display(),
+                    request.method,
+                    request.url
+                ))
+            })?;
+        replayed[position] = true;
+        Ok(interactions[position].response.clone())
+    }
+
+    // appends an interaction and rewrites the cassette file
+    pub fn store(&self, interaction: Interaction) -> io::Result<()> {
+        let mut interactions = self.interactions.lock().unwrap();
+        interactions.push(interaction);
+        if let Some(dir) = self.path.parent().filter(|p| !p.as_os_str().is_empty()) {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_string_pretty(&*interactions)?)
+    }
+}
+
+// headers that describe the wire encoding rather than the content; the stored
+// body is already decoded, so replaying them would confuse the client
+const SKIPPED_HEADERS: &[&str] = &["content-encoding", "content-length", "transfer-encoding", "set-cookie"];
+
+pub(crate) fn recorded_request(request: &reqwest::Request) -> RecordedRequest {
+    let body = request
+        .body()
+        .and_then(|b| b.as_bytes())
+        .map(|bytes| serde_json::from_slice(bytes).unwrap_or_else(|_| Value::String(String::from_utf8_lossy(bytes).into_owned())))
+        .unwrap_or(Value::Null);
+    RecordedRequest {
+        method: request.method().to_string(),
+        url: request.url().to_string(),
+        body,
+    }
+}
+
+// reads a live response completely and splits it into line chunks
+pub(crate) async fn recorded_response(response: reqwest::Response) -> LlmResult<RecordedResponse> {
+    let status = response.status().as_u16();
+    let headers = response
+        .headers()
+        .iter()
+        .filter(|(name, _)| !SKIPPED_HEADERS.contains(&name.as_str()))
+        .filter_map(|(name, value)| Some((name.to_string(), value.to_str().ok()?.to_string())))
+        .collect();
+
+    let mut body = Vec::new();
+    let mut chunks = response.bytes_stream();
+    while let Some(chunk) = chunks.next().await {
+        body.extend_from_slice(&chunk?);
+    }
+    let body = String::from_utf8_lossy(&body);
+
+    Ok(RecordedResponse {
+        status,
+        headers,
+        chunks: body.split_inclusive('\n').map(str::to_string).collect(),
+    })
+}
+
+// rebuilds a `reqwest::Response` that streams the recorded chunks
+pub(crate) fn into_response(recorded: RecordedResponse) -> LlmResult<reqwest::Response> {
+    let mut builder = http::Response::builder().status(recorded.status);
+    for (name, value) in &recorded.headers {
+        builder = builder.header(name, value);
+    }
+    let chunks = stream::iter(recorded.chunks.into_iter().map(Ok::<_, io::Error>));
+    let response = builder
+        .body(reqwest::Body::wrap_stream(chunks))
+        .map_err(|e| LlmError::Replay(e.to_string()))?;
+    Ok(reqwest::Response::from(response))
+}
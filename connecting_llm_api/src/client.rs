@@ -0,0 +1,439 @@
+use async_trait::async_trait;
+use base64::{Engine, engine::general_purpose::STANDARD as BASE64};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::{fmt, fs, io, path::Path};
+
+use crate::cancel::CancellationToken;
+use crate::ollama::{KeepAlive, OllamaOptions};
+pub use crate::error::LlmResult;
+use crate::streaming::ChatStream;
+
+// who a message is from
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Role {
+    // OpenAI's newer models call the system prompt `developer`
+    #[serde(alias = "developer")]
+    System,
+    #[default]
+    User,
+    Assistant,
+    Tool,
+}
+
+impl Role {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Role::System => "system",
+            Role::User => "user",
+            Role::Assistant => "assistant",
+            Role::Tool => "tool",
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+// content sent after a message's text: images, documents or more text
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum ContentPart {
+    Text {
+        text: String,
+    },
+    // a remote http(s) URL or a `data:` URI holding the base64 bytes
+    Image {
+        url: String,
+    },
+    File {
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        filename: Option<String>,
+        source: FileSource,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FileSource {
+    // a file already uploaded to the provider (OpenAI file id, Gemini file URI)
+    Id(String),
+    // a `data:` URI holding the base64 bytes
+    Data(String),
+}
+
+impl ContentPart {
+    pub fn text(text: impl Into<String>) -> Self {
+        ContentPart::Text { text: text.into() }
+    }
+
+    pub fn image_url(url: impl Into<String>) -> Self {
+        ContentPart::Image { url: url.into() }
+    }
+
+    pub fn image_bytes(bytes: &[u8], media_type: &str) -> Self {
+        ContentPart::Image {
+            url: data_uri(media_type, bytes),
+        }
+    }
+
+    // e.g. a screenshot; the media type follows the extension
+    pub fn image_file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let media_type = media_type_of(path);
+        if !media_type.starts_with("image/") {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, format!("{} is not an image", path.display())));
+        }
+        Ok(Self::image_bytes(&fs::read(path)?, media_type))
+    }
+
+    pub fn file_id(id: impl Into<String>) -> Self {
+        ContentPart::File {
+            filename: None,
+            source: FileSource::Id(id.into()),
+        }
+    }
+
+    pub fn file_bytes(filename: impl Into<String>, bytes: &[u8], media_type: &str) -> Self {
+        ContentPart::File {
+            filename: Some(filename.into()),
+            source: FileSource::Data(data_uri(media_type, bytes)),
+        }
+    }
+
+    // e.g. a PDF or text document; the media type follows the extension
+    pub fn file(path: impl AsRef<Path>) -> io::Result<Self> {
+        let path = path.as_ref();
+        let filename = path.file_name().map(|name| name.to_string_lossy().into_owned()).unwrap_or_default();
+        Ok(Self::file_bytes(filename, &fs::read(path)?, media_type_of(path)))
+    }
+}
+
+pub fn data_uri(media_type: &str, bytes: &[u8]) -> String {
+    format!("data:{};base64,{}", media_type, BASE64.encode(bytes))
+}
+
+// (media type, base64 payload) of a `data:` URI
+pub(crate) fn split_data_uri(uri: &str) -> Option<(&str, &str)> {
+    uri.strip_prefix("data:")?.split_once(";base64,")
+}
+
+// the decoded text of a `data:` URI, for text documents sent to text-only APIs
+pub(crate) fn data_uri_text(uri: &str) -> Option<String> {
+    let (media_type, data) = split_data_uri(uri)?;
+    let textual = media_type.starts_with("text/") || matches!(media_type, "application/json" | "application/xml");
+    textual.then(|| String::from_utf8(BASE64.decode(data).ok()?).ok())?
+}
+
+fn media_type_of(path: &Path) -> &'static str {
+    known_media_type(path).unwrap_or("application/octet-stream")
+}
+
+// None for an extension we don't recognize
+pub(crate) fn known_media_type(path: &Path) -> Option<&'static str> {
+    let extension = path.extension().and_then(|ext| ext.to_str()).unwrap_or_default().to_ascii_lowercase();
+    let media_type = match extension.as_str() {
+        "png" => "image/png",
+        "jpg" | "jpeg" => "image/jpeg",
+        "gif" => "image/gif",
+        "webp" => "image/webp",
+        "pdf" => "application/pdf",
+        "txt" | "log" => "text/plain",
+        "md" => "text/markdown",
+        "csv" => "text/csv",
+        "html" | "htm" => "text/html",
+        "json" => "application/json",
+        "xml" => "application/xml",
+        _ => return None,
+    };
+    Some(media_type)
+}
+
+// the message model shared by every backend.
+// `content` is the text of the message; `parts` carry images, documents or
+// more text sent after it. an assistant turn may request tool calls, and every
+// `tool` turn answers exactly one of them through `tool_call_id`.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct ChatMessage {
+    pub role: Role,
+    pub content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub parts: Vec<ContentPart>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tool_calls: Vec<ToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tool_call_id: Option<String>,
+}
+
+impl ChatMessage {
+    pub fn new(role: Role, content: impl Into<String>) -> Self {
+        ChatMessage {
+            role,
+            content: content.into(),
+            ..Default::default()
+        }
+    }
+
+    pub fn system(content: impl Into<String>) -> Self {
+        Self::new(Role::System, content)
+    }
+
+    pub fn user(content: impl Into<String>) -> Self {
+        Self::new(Role::User, content)
+    }
+
+    pub fn assistant(content: impl Into<String>) -> Self {
+        Self::new(Role::Assistant, content)
+    }
+
+    // the reply to a single tool call requested by the assistant
+    pub fn tool(tool_call_id: impl Into<String>, content: impl Into<String>) -> Self {
+        ChatMessage {
+            tool_call_id: Some(tool_call_id.into()),
+            ..Self::new(Role::Tool, content)
+        }
+    }
+
+    // e.g. `ChatMessage::user("what is wrong here?").with_part(ContentPart::image_file("screenshot.png")?)`
+    pub fn with_part(mut self, part: ContentPart) -> Self {
+        self.parts.push(part);
+        self
+    }
+}
+
+// a function invocation requested by the model.
+// arguments are already decoded from the provider's wire format.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ToolCall {
+    pub id: String,
+    pub name: String,
+    pub arguments: Value,
+}
+
+// a function advertised to the model; `parameters` is a JSON schema object
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ToolDefinition {
+    pub name: String,
+    pub description: String,
+    pub parameters: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ToolChoice {
+    Auto,
+    None,
+    Required,
+    Tool(String),
+}
+
+// constrains the reply to JSON, optionally matching a JSON schema
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum ResponseFormat {
+    Json,
+    JsonSchema { name: String, schema: Value },
+}
+
+// per-request generation parameters.
+// anything left as None falls back to the defaults of the client's config.
+#[derive(Debug, Clone, Default)]
+pub struct ChatOptions {
+    pub model: Option<String>,
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    pub stop: Vec<String>,
+    pub tools: Vec<ToolDefinition>,
+    pub tool_choice: Option<ToolChoice>,
+    pub response_format: Option<ResponseFormat>,
+    // skip any response cache for this request (see `cache::CachedClient`)
+    pub no_cache: bool,
+    // aborts the request, including a stream in progress (see `cancel`)
+    pub cancel: Option<CancellationToken>,
+    // ollama only: overlaid on the configured options and keep_alive
+    pub ollama_options: Option<OllamaOptions>,
+    pub keep_alive: Option<KeepAlive>,
+}
+
+impl ChatOptions {
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_max_tokens(mut self, max_tokens: u32) -> Self {
+        self.max_tokens = Some(max_tokens);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: impl Into<String>) -> Self {
+        self.stop.push(stop.into());
+        self
+    }
+
+    pub fn with_tools(mut self, tools: Vec<ToolDefinition>) -> Self {
+        self.tools = tools;
+        self
+    }
+
+    pub fn with_tool_choice(mut self, tool_choice: ToolChoice) -> Self {
+        self.tool_choice = Some(tool_choice);
+        self
+    }
+
+    pub fn with_response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn without_cache(mut self) -> Self {
+        self.no_cache = true;
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+
+    pub fn with_ollama_options(mut self, options: OllamaOptions) -> Self {
+        self.ollama_options = Some(options);
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+}
+
+// token accounting reported by the provider
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Usage {
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+}
+
+impl Usage {
+    pub fn total_tokens(&self) -> u32 {
+        self.prompt_tokens + self.completion_tokens
+    }
+}
+
+// what a backend hands back for a single (non-streaming) chat call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ChatCompletion {
+    pub message: ChatMessage,
+    pub model: String,
+    pub finish_reason: Option<String>,
+    pub usage: Option<Usage>,
+}
+
+impl ChatCompletion {
+    pub fn content(&self) -> &str {
+        &self.message.content
+    }
+
+    pub fn tool_calls(&self) -> &[ToolCall] {
+        &self.message.tool_calls
+    }
+}
+
+// the common interface for every LLM backend.
+// agents should depend on `dyn LlmClient` so the provider can be swapped by
+// configuration instead of code changes.
+#[async_trait]
+pub trait LlmClient: Send + Sync {
+    fn provider(&self) -> &'static str;
+    fn default_model(&self) -> &str;
+
+    // the server requests go to, e.g. the base URL or Azure resource. clients
+    // that spread requests over several servers (a router) have none.
+    fn endpoint(&self) -> Option<&str> {
+        None
+    }
+
+    // the temperature a request that leaves it unset is sampled with, when
+    // the client sets one (None = the provider's default)
+    fn default_temperature(&self) -> Option<f32> {
+        None
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion>;
+
+    // same as `chat`, but yields the reply incrementally as it is generated
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream>;
+}
+
+// lets a shared client be handed to the wrappers (`RetryingClient`,
+// `MeteredClient`, ...) that take their inner client by value
+#[async_trait]
+impl<T: LlmClient + ?Sized> LlmClient for std::sync::Arc<T> {
+    fn provider(&self) -> &'static str {
+        (**self).provider()
+    }
+
+    fn default_model(&self) -> &str {
+        (**self).default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        (**self).endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        (**self).default_temperature()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        (**self).chat(messages, options).await
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        (**self).chat_stream(messages, options).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn data_uris_round_trip() {
+        let uri = data_uri("text/plain", b"hello");
+        assert_eq!(uri, "data:text/plain;base64,aGVsbG8=");
+        assert_eq!(split_data_uri(&uri), Some(("text/plain", "aGVsbG8=")));
+        assert_eq!(data_uri_text(&uri).as_deref(), Some("hello"));
+
+        // binary documents have no text to offer
+        assert_eq!(data_uri_text(&data_uri("application/pdf", b"%PDF")), None);
+        assert_eq!(split_data_uri("https://example.com/cat.png"), None);
+    }
+
+    #[test]
+    fn attachments_take_their_media_type_from_the_extension() {
+        let dir = std::env::temp_dir().join(format!("content-parts-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        for (name, bytes) in [("shot.PNG", &b"\x89PNG"[..]), ("notes.md", b"# notes"), ("blob.bin", b"\0")] {
+            fs::write(dir.join(name), bytes).unwrap();
+        }
+
+        assert_eq!(ContentPart::image_file(dir.join("shot.PNG")).unwrap(), ContentPart::image_bytes(b"\x89PNG", "image/png"));
+        let error = ContentPart::image_file(dir.join("notes.md")).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+
+        assert_eq!(ContentPart::file(dir.join("notes.md")).unwrap(), ContentPart::file_bytes("notes.md", b"# notes", "text/markdown"));
+        assert!(matches!(
+            ContentPart::file(dir.join("blob.bin")).unwrap(),
+            ContentPart::File { source: FileSource::Data(data), .. } if data.starts_with("data:application/octet-stream;")
+        ));
+        let _ = fs::remove_dir_all(&dir);
+    }
+}
@@ -0,0 +1,455 @@
+use serde::{Deserialize, Serialize};
+use std::{fs, io, path::Path};
+use tool_using_agents::secure_tool_functions::ToolRegistery;
+
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient, Role};
+use crate::error::LlmResult;
+use crate::tokens::{TokenCounter, counter_for, prompt_budget};
+use crate::tool_calling::run_with_tools;
+
+const SUMMARY_PROMPT: &str = "Summarize the following conversation between a user and an assistant. \
+Keep every fact, decision and open question needed to continue it. Reply with the summary only.";
+
+// how a conversation is shrunk once it no longer fits the model's context window.
+// the system prompt is never trimmed.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum TrimStrategy {
+    // drop the oldest messages until the prompt fits
+    DropOldest,
+    // keep only the last `n` messages, then drop more if it still doesn't fit
+    KeepLast(usize),
+    // ask the model to summarize everything but the last `keep_last` messages
+    // and replace them with that summary
+    Summarize { keep_last: usize },
+}
+
+// a multi-turn dialogue.
+// owns the system prompt and the message history, and appends the
+// assistant's replies automatically. the whole thing is plain serde data, so
+// a long-running agent session can be persisted and restored across restarts.
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct Conversation {
+    pub system_prompt: Option<String>,
+    messages: Vec<ChatMessage>,
+    // applied before every request; None sends the history as-is
+    #[serde(default)]
+    pub trim: Option<TrimStrategy>,
+}
+
+impl Conversation {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn with_system_prompt(system_prompt: impl Into<String>) -> Self {
+        Conversation {
+            system_prompt: Some(system_prompt.into()),
+            ..Self::default()
+        }
+    }
+
+    pub fn with_trim(mut self, strategy: TrimStrategy) -> Self {
+        self.trim = Some(strategy);
+        self
+    }
+
+    // the history, without the system prompt
+    pub fn messages(&self) -> &[ChatMessage] {
+        &self.messages
+    }
+
+    pub fn len(&self) -> usize {
+        self.messages.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.messages.is_empty()
+    }
+
+    pub fn last(&self) -> Option<&ChatMessage> {
+        self.messages.last()
+    }
+
+    pub fn push(&mut self, message: ChatMessage) {
+        self.messages.push(message);
+    }
+
+    // the full message list as it is sent to the provider
+    pub fn to_messages(&self) -> Vec<ChatMessage> {
+        self.system_prompt
+            .iter()
+            .map(ChatMessage::system)
+            .chain(self.messages.iter().cloned())
+            .collect()
+    }
+
+    // sends a user turn and records the reply.
+    // if the call fails the user turn is dropped again, so a retry doesn't
+    // leave two identical questions in the history.
+    pub async fn send(&mut self, client: &dyn LlmClient, prompt: impl Into<String>, options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        // trimming works on a copy, so a failed call leaves the history as it was
+        let mut next = self.clone();
+        next.messages.push(ChatMessage::user(prompt));
+        next.fit_to_context(client, options).await?;
+        let completion = client.chat(&next.to_messages(), options).await?;
+        next.messages.push(completion.message.clone());
+        *self = next;
+        Ok(completion)
+    }
+
+    // same as `send`, but lets the model call tools from the registry.
+    // tool calls and their results become part of the history.
+    pub async fn send_with_tools(
+        &mut self,
+        client: &dyn LlmClient,
+        prompt: impl Into<String>,
+        options: &ChatOptions,
+        registry: &ToolRegistery,
+        max_rounds: usize,
+    ) -> LlmResult<ChatCompletion> {
+        let mut next = self.clone();
+        next.messages.push(ChatMessage::user(prompt));
+        next.fit_to_context(client, options).await?;
+
+        let mut messages = next.to_messages();
+        let sent = messages.len();
+        let completion = run_with_tools(client, &mut messages, options, registry, max_rounds).await?;
+        next.messages.extend(messages.drain(sent..));
+        *self = next;
+        Ok(completion)
+    }
+
+    // applies the conversation's trim strategy against the context window of
+    // the model the request is about to go to
+    pub async fn fit_to_context(&mut self, client: &dyn LlmClient, options: &ChatOptions) -> LlmResult<()> {
+        let Some(strategy) = self.trim.clone() else {
+            return Ok(());
+        };
+        let model = options.model.as_deref().unwrap_or(client.default_model());
+        let counter = counter_for(client.provider(), model);
+        let budget = prompt_budget(model, options.max_tokens);
+        self.trim_to(&strategy, budget, counter.as_ref(), client, options).await
+    }
+
+    pub fn token_count(&self, counter: &dyn TokenCounter) -> usize {
+        counter.count_messages(&self.to_messages())
+    }
+
+    // shrinks the history until it fits into `budget` prompt tokens.
+    // `client` and `options` are only used by the summarizing strategy.
+    pub async fn trim_to(
+        &mut self,
+        strategy: &TrimStrategy,
+        budget: usize,
+        counter: &dyn TokenCounter,
+        client: &dyn LlmClient,
+        options: &ChatOptions,
+    ) -> LlmResult<()> {
+        if self.token_count(counter) <= budget {
+            return Ok(());
+        }
+
+        match strategy {
+            TrimStrategy::DropOldest => {}
+            TrimStrategy::KeepLast(n) => {
+                let excess = self.messages.len().saturating_sub(*n);
+                self.drop_front(excess);
+            }
+            TrimStrategy::Summarize { keep_last } => {
+                let mut split = self.messages.len().saturating_sub(*keep_last);
+                // never separate tool results from the call that requested them
+                while split > 0 && self.messages.get(split).is_some_and(|m| m.role == Role::Tool) {
+                    split -= 1;
+                }
+                if split > 0 {
+                    let summary = self.summarize(client, split, options).await?;
+                    self.messages.splice(..split, [ChatMessage::system(format!("Summary of the earlier conversation: {}", summary))]);
+                }
+            }
+        }
+
+        // whatever the strategy, the latest message is always sent
+        while self.messages.len() > 1 && self.token_count(counter) > budget {
+            self.drop_front(1);
+        }
+        Ok(())
+    }
+
+    // goes to the caller's model and honors its cancellation, but the summary
+    // is plain text, so tools and response formats are left out
+    async fn summarize(&self, client: &dyn LlmClient, upto: usize, options: &ChatOptions) -> LlmResult<String> {
+        let transcript: String = self.messages[..upto]
+            .iter()
+            .map(|m| format!("{}: {}\n", m.role, m.content))
+            .collect();
+        let request = [ChatMessage::system(SUMMARY_PROMPT), ChatMessage::user(transcript)];
+        let options = ChatOptions {
+            tools: vec![],
+            tool_choice: None,
+            response_format: None,
+            ..options.clone()
+        };
+        let completion = client.chat(&request, &options).await?;
+        Ok(completion.message.content)
+    }
+
+    // removes `count` messages from the front plus any tool results left
+    // without the assistant turn that requested them
+    fn drop_front(&mut self, count: usize) {
+        let count = count.min(self.messages.len().saturating_sub(1));
+        self.messages.drain(..count);
+        while self.messages.len() > 1 && self.messages[0].role == Role::Tool {
+            self.messages.remove(0);
+        }
+    }
+
+    // branching: an independent copy of the conversation holding only the
+    // first `turn` messages, to explore an alternative continuation.
+    pub fn fork_at(&self, turn: usize) -> Conversation {
+        Conversation {
+            system_prompt: self.system_prompt.clone(),
+            messages: self.messages[..turn.min(self.messages.len())].to_vec(),
+            trim: self.trim.clone(),
+        }
+    }
+
+    // drops everything after the first `turn` messages
+    pub fn rewind(&mut self, turn: usize) {
+        self.messages.truncate(turn);
+    }
+
+    pub fn to_json(&self) -> serde_json::Result<String> {
+        serde_json::to_string_pretty(self)
+    }
+
+    pub fn from_json(json: &str) -> serde_json::Result<Self> {
+        serde_json::from_str(json)
+    }
+
+    pub fn save(&self, path: impl AsRef<Path>) -> io::Result<()> {
+        fs::write(path, self.to_json()?)
+    }
+
+    pub fn load(path: impl AsRef<Path>) -> io::Result<Self> {
+        let json = fs::read_to_string(path)?;
+        Ok(Self::from_json(&json)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LlmError;
+    use crate::streaming::ChatStream;
+    use async_trait::async_trait;
+    use std::sync::Mutex;
+
+    // replies "reply N" and keeps every request it was sent; fails while
+    // `failing` is set
+    #[derive(Default)]
+    struct Recording {
+        requests: Mutex<Vec<Vec<ChatMessage>>>,
+        failing: std::sync::atomic::AtomicBool,
+    }
+
+    #[async_trait]
+    impl LlmClient for Recording {
+        fn provider(&self) -> &'static str {
+            "ollama"
+        }
+
+        fn default_model(&self) -> &str {
+            "mistral"
+        }
+
+        async fn chat(&self, messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            if self.failing.load(std::sync::atomic::Ordering::SeqCst) {
+                return Err(LlmError::Timeout);
+            }
+            let mut requests = self.requests.lock().unwrap();
+            requests.push(messages.to_vec());
+            Ok(ChatCompletion {
+                message: ChatMessage::assistant(format!("reply {}", requests.len())),
+                model: "mistral".into(),
+                finish_reason: Some("stop".into()),
+                usage: None,
+            })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            unimplemented!("not streamed in these tests")
+        }
+    }
+
+    #[tokio::test]
+    async fn turns_build_up_the_history() {
+        let client = Recording::default();
+        let mut conversation = Conversation::with_system_prompt("be brief");
+        conversation.send(&client, "hi", &ChatOptions::default()).await.unwrap();
+        let reply = conversation.send(&client, "again", &ChatOptions::default()).await.unwrap();
+        assert_eq!(reply.content(), "reply 2");
+
+        // the system prompt leads every request but is not part of the history
+        let requests = client.requests.lock().unwrap();
+        let contents: Vec<&str> = requests[1].iter().map(|m| m.content.as_str()).collect();
+        assert_eq!(contents, ["be brief", "hi", "reply 1", "again"]);
+        assert_eq!(requests[1][0].role, Role::System);
+        assert_eq!(conversation.len(), 4);
+        assert_eq!(conversation.last().unwrap().content, "reply 2");
+    }
+
+    #[tokio::test]
+    async fn a_failed_turn_is_not_recorded() {
+        let client = Recording::default();
+        let mut conversation = Conversation::new();
+        conversation.send(&client, "hi", &ChatOptions::default()).await.unwrap();
+
+        client.failing.store(true, std::sync::atomic::Ordering::SeqCst);
+        assert!(conversation.send(&client, "lost", &ChatOptions::default()).await.is_err());
+        assert_eq!(conversation.len(), 2);
+    }
+
+    #[test]
+    fn forks_and_rewinds_keep_the_earlier_turns() {
+        let mut conversation = Conversation::with_system_prompt("sys").with_trim(TrimStrategy::KeepLast(4));
+        for text in ["a", "b", "c", "d"] {
+            conversation.push(ChatMessage::user(text));
+        }
+
+        let fork = conversation.fork_at(2);
+        assert_eq!(fork.messages().iter().map(|m| m.content.as_str()).collect::<Vec<_>>(), ["a", "b"]);
+        assert_eq!(fork.system_prompt.as_deref(), Some("sys"));
+        assert_eq!(fork.trim, Some(TrimStrategy::KeepLast(4)));
+        assert_eq!(conversation.fork_at(10).len(), 4);
+
+        conversation.rewind(1);
+        assert_eq!(conversation.len(), 1);
+        // the fork is independent of the original
+        assert_eq!(fork.len(), 2);
+    }
+
+    #[test]
+    fn conversations_survive_a_save_and_load() {
+        let mut conversation = Conversation::with_system_prompt("sys").with_trim(TrimStrategy::Summarize { keep_last: 2 });
+        conversation.push(ChatMessage::user("question"));
+        conversation.push(ChatMessage::assistant("answer"));
+
+        let path = std::env::temp_dir().join(format!("conversation-{}.json", std::process::id()));
+        conversation.save(&path).unwrap();
+        let loaded = Conversation::load(&path).unwrap();
+        let _ = fs::remove_file(&path);
+
+        assert_eq!(loaded.system_prompt, conversation.system_prompt);
+        assert_eq!(loaded.trim, conversation.trim);
+        assert_eq!(loaded.to_messages().len(), 3);
+        assert_eq!(loaded.messages()[1].content, "answer");
+        assert!(Conversation::from_json("{\"messages\": 3}").is_err());
+    }
+
+    // one token per word, so budgets are easy to follow
+    struct Words;
+
+    impl TokenCounter for Words {
+        fn count(&self, text: &str) -> usize {
+            text.split_whitespace().count()
+        }
+    }
+
+    fn contents(conversation: &Conversation) -> Vec<&str> {
+        conversation.messages().iter().map(|m| m.content.as_str()).collect()
+    }
+
+    // a user/assistant exchange of one-word messages, each costing 6 tokens
+    fn chat(turns: &[&str]) -> Conversation {
+        let mut conversation = Conversation::with_system_prompt("sys");
+        for (i, text) in turns.iter().enumerate() {
+            conversation.push(if i % 2 == 0 { ChatMessage::user(*text) } else { ChatMessage::assistant(*text) });
+        }
+        conversation
+    }
+
+    fn calling(id: &str) -> ChatMessage {
+        ChatMessage {
+            tool_calls: vec![crate::client::ToolCall { id: id.into(), name: "f".into(), arguments: serde_json::json!({}) }],
+            ..ChatMessage::assistant("")
+        }
+    }
+
+    async fn trim(conversation: &mut Conversation, strategy: TrimStrategy, budget: usize) -> Recording {
+        let client = Recording::default();
+        conversation.trim_to(&strategy, budget, &Words, &client, &ChatOptions::default()).await.unwrap();
+        client
+    }
+
+    #[tokio::test]
+    async fn conversations_that_fit_are_left_alone() {
+        let mut conversation = chat(&["a", "b", "c"]);
+        let client = trim(&mut conversation, TrimStrategy::Summarize { keep_last: 1 }, 27).await;
+        assert_eq!(contents(&conversation), ["a", "b", "c"]);
+        assert!(client.requests.lock().unwrap().is_empty());
+    }
+
+    #[tokio::test]
+    async fn the_oldest_messages_are_dropped_first() {
+        let mut conversation = chat(&["a", "b", "c", "d"]);
+        assert_eq!(conversation.token_count(&Words), 33);
+        trim(&mut conversation, TrimStrategy::DropOldest, 21).await;
+        assert_eq!(contents(&conversation), ["c", "d"]);
+        assert_eq!(conversation.system_prompt.as_deref(), Some("sys"));
+    }
+
+    #[tokio::test]
+    async fn tool_results_go_with_their_call() {
+        let mut conversation = chat(&["a"]);
+        conversation.push(calling("c1"));
+        conversation.push(ChatMessage::tool("c1", "r"));
+        conversation.push(ChatMessage::assistant("b"));
+        conversation.push(ChatMessage::user("c"));
+
+        // dropping the call leaves its result orphaned, so it goes too
+        trim(&mut conversation, TrimStrategy::DropOldest, 28).await;
+        assert_eq!(contents(&conversation), ["b", "c"]);
+    }
+
+    #[tokio::test]
+    async fn keep_last_still_sends_the_latest_message() {
+        let mut conversation = chat(&["a", "b", "c", "d"]);
+        trim(&mut conversation, TrimStrategy::KeepLast(3), 28).await;
+        assert_eq!(contents(&conversation), ["b", "c", "d"]);
+
+        // even when it alone is over budget
+        trim(&mut conversation, TrimStrategy::KeepLast(3), 1).await;
+        assert_eq!(contents(&conversation), ["d"]);
+    }
+
+    #[tokio::test]
+    async fn older_turns_are_replaced_by_a_summary() {
+        let mut conversation = chat(&["a", "b", "c", "d", "e"]);
+        let client = trim(&mut conversation, TrimStrategy::Summarize { keep_last: 2 }, 35).await;
+
+        let requests = client.requests.lock().unwrap();
+        assert_eq!(requests.len(), 1);
+        assert_eq!(requests[0][0].content, SUMMARY_PROMPT);
+        assert_eq!(requests[0][1].content, "user: a\nassistant: b\nuser: c\n");
+
+        assert_eq!(contents(&conversation), ["Summary of the earlier conversation: reply 1", "d", "e"]);
+        assert_eq!(conversation.messages()[0].role, Role::System);
+    }
+
+    #[tokio::test]
+    async fn summaries_never_split_a_call_from_its_results() {
+        let long = "many words ".repeat(10);
+        let mut conversation = chat(&[long.trim()]);
+        conversation.push(calling("c1"));
+        conversation.push(ChatMessage::tool("c1", "r"));
+        conversation.push(ChatMessage::assistant("b"));
+        conversation.push(ChatMessage::user("c"));
+
+        // keeping the last 3 would start at the tool result, so the call is kept too
+        let client = trim(&mut conversation, TrimStrategy::Summarize { keep_last: 3 }, 50).await;
+        assert!(client.requests.lock().unwrap()[0][1].content.starts_with("user: many words"));
+        assert_eq!(conversation.len(), 5);
+        assert_eq!(conversation.messages()[1].tool_calls[0].id, "c1");
+        assert_eq!(conversation.messages()[2].role, Role::Tool);
+    }
+}
@@ -0,0 +1,222 @@
+use async_trait::async_trait;
+use serde::{Deserialize, Serialize};
+
+use crate::client::Usage;
+use crate::error::{LlmError, LlmResult};
+
+// per-call embedding parameters; anything left unset uses the provider default
+#[derive(Debug, Clone, Default)]
+pub struct EmbeddingOptions {
+    pub model: Option<String>,
+    // shortened vectors, for models that support it (OpenAI text-embedding-3,
+    // recent ollama embedding models)
+    pub dimensions: Option<u32>,
+    // scale every vector to unit length, so a dot product is the cosine similarity
+    pub normalize: bool,
+    // inputs per request; None uses the provider's limit
+    pub batch_size: Option<usize>,
+}
+
+impl EmbeddingOptions {
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_dimensions(mut self, dimensions: u32) -> Self {
+        self.dimensions = Some(dimensions);
+        self
+    }
+
+    pub fn normalized(mut self) -> Self {
+        self.normalize = true;
+        self
+    }
+
+    pub fn with_batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = Some(batch_size.max(1));
+        self
+    }
+}
+
+// one vector per input, in input order
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Embeddings {
+    pub vectors: Vec<Vec<f32>>,
+    pub model: String,
+    pub dimensions: usize,
+    // only `prompt_tokens` is meaningful for embeddings
+    pub usage: Option<Usage>,
+}
+
+#[async_trait]
+pub trait EmbeddingClient: Send + Sync {
+    fn embedding_model(&self) -> &str;
+
+    // the most inputs a single request may carry
+    fn max_batch_size(&self) -> usize;
+
+    // a single request; callers should go through `embed`
+    async fn embed_batch(&self, inputs: &[String], options: &EmbeddingOptions) -> LlmResult<Embeddings>;
+
+    // embeds any number of inputs, split into batches
+    async fn embed(&self, inputs: &[String], options: &EmbeddingOptions) -> LlmResult<Embeddings> {
+        let batch_size = options.batch_size.unwrap_or(self.max_batch_size()).max(1);
+        let mut embeddings = Embeddings {
+            vectors: Vec::with_capacity(inputs.len()),
+            model: options.model.clone().unwrap_or_else(|| self.embedding_model().to_string()),
+            dimensions: 0,
+            usage: None,
+        };
+
+        for batch in inputs.chunks(batch_size) {
+            let reply = self.embed_batch(batch, options).await?;
+            if reply.vectors.len() != batch.len() {
+                return Err(LlmError::MalformedResponse(format!(
+                    "expected {} embeddings, got {}",
+                    batch.len(),
+                    reply.vectors.len()
+                )));
+            }
+            if embeddings.dimensions != 0 && reply.dimensions != embeddings.dimensions {
+                return Err(LlmError::MalformedResponse(format!(
+                    "embedding dimensions changed between batches ({} vs {})",
+                    embeddings.dimensions, reply.dimensions
+                )));
+            }
+            embeddings.dimensions = reply.dimensions;
+            embeddings.model = reply.model;
+            embeddings.vectors.extend(reply.vectors);
+            if let Some(usage) = reply.usage {
+                let total = embeddings.usage.get_or_insert_with(Usage::default);
+                total.prompt_tokens += usage.prompt_tokens;
+                total.completion_tokens += usage.completion_tokens;
+            }
+        }
+
+        if options.normalize {
+            embeddings.vectors.iter_mut().for_each(|v| normalize(v));
+        }
+        Ok(embeddings)
+    }
+
+    async fn embed_one(&self, input: &str, options: &EmbeddingOptions) -> LlmResult<Vec<f32>> {
+        let embeddings = self.embed(&[input.to_string()], options).await?;
+        embeddings.vectors.into_iter().next().ok_or(LlmError::EmptyChoices)
+    }
+}
+
+// the common dimension of a provider reply, rejecting ragged vectors
+pub(crate) fn dimensions_of(vectors: &[Vec<f32>]) -> LlmResult<usize> {
+    let dimensions = vectors.first().map(Vec::len).unwrap_or(0);
+    if vectors.iter().any(|v| v.len() != dimensions) {
+        return Err(LlmError::MalformedResponse("embeddings have differing dimensions".into()));
+    }
+    Ok(dimensions)
+}
+
+// scales `vector` to unit length; the zero vector is left as is
+pub fn normalize(vector: &mut [f32]) {
+    let norm = vector.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm > 0.0 {
+        vector.iter_mut().for_each(|x| *x /= norm);
+    }
+}
+
+pub fn cosine_similarity(a: &[f32], b: &[f32]) -> f32 {
+    let dot: f32 = a.iter().zip(b).map(|(x, y)| x * y).sum();
+    let norm_a = a.iter().map(|x| x * x).sum::<f32>().sqrt();
+    let norm_b = b.iter().map(|x| x * x).sum::<f32>().sqrt();
+    if norm_a == 0.0 || norm_b == 0.0 {
+        return 0.0;
+    }
+    dot / (norm_a * norm_b)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::Mutex;
+
+    // embeds each input as [len, 0] (or a ragged vector for "ragged") and
+    // records the batches it was sent
+    #[derive(Default)]
+    struct Lengths {
+        batches: Mutex<Vec<usize>>,
+    }
+
+    #[async_trait]
+    impl EmbeddingClient for Lengths {
+        fn embedding_model(&self) -> &str {
+            "lengths"
+        }
+
+        fn max_batch_size(&self) -> usize {
+            2
+        }
+
+        async fn embed_batch(&self, inputs: &[String], _options: &EmbeddingOptions) -> LlmResult<Embeddings> {
+            self.batches.lock().unwrap().push(inputs.len());
+            let vectors: Vec<Vec<f32>> = inputs
+                .iter()
+                .map(|input| if input == "ragged" { vec![1.0] } else { vec![input.len() as f32, 0.0] })
+                .collect();
+            Ok(Embeddings {
+                dimensions: dimensions_of(&vectors)?,
+                vectors,
+                model: "lengths-v1".into(),
+                usage: Some(Usage { prompt_tokens: inputs.len() as u32, completion_tokens: 0 }),
+            })
+        }
+    }
+
+    fn inputs(texts: &[&str]) -> Vec<String> {
+        texts.iter().map(|text| text.to_string()).collect()
+    }
+
+    #[tokio::test]
+    async fn inputs_are_batched_in_order() {
+        let client = Lengths::default();
+        let embeddings = client.embed(&inputs(&["a", "bb", "ccc", "dddd", "eeeee"]), &EmbeddingOptions::default()).await.unwrap();
+
+        assert_eq!(*client.batches.lock().unwrap(), [2, 2, 1]);
+        let firsts: Vec<f32> = embeddings.vectors.iter().map(|v| v[0]).collect();
+        assert_eq!(firsts, [1.0, 2.0, 3.0, 4.0, 5.0]);
+        assert_eq!(embeddings.dimensions, 2);
+        assert_eq!(embeddings.model, "lengths-v1");
+        assert_eq!(embeddings.usage.unwrap().prompt_tokens, 5);
+
+        let client = Lengths::default();
+        client.embed(&inputs(&["a", "b", "c"]), &EmbeddingOptions::default().with_batch_size(0)).await.unwrap();
+        assert_eq!(*client.batches.lock().unwrap(), [1, 1, 1]);
+    }
+
+    #[tokio::test]
+    async fn ragged_replies_are_rejected() {
+        let client = Lengths::default();
+        let result = client.embed(&inputs(&["a", "ragged"]), &EmbeddingOptions::default()).await;
+        assert!(matches!(result, Err(LlmError::MalformedResponse(_))));
+
+        // each batch is consistent, but they disagree with each other
+        let result = client.embed(&inputs(&["a", "b", "ragged"]), &EmbeddingOptions::default()).await;
+        assert!(matches!(result, Err(LlmError::MalformedResponse(message)) if message.contains("between batches")));
+    }
+
+    #[tokio::test]
+    async fn normalized_vectors_have_unit_length() {
+        let vector = Lengths::default().embed_one("abc", &EmbeddingOptions::default().normalized()).await.unwrap();
+        assert_eq!(vector, [1.0, 0.0]);
+
+        let mut zero = [0.0, 0.0];
+        normalize(&mut zero);
+        assert_eq!(zero, [0.0, 0.0]);
+    }
+
+    #[test]
+    fn cosine_similarity_ignores_magnitude() {
+        assert!((cosine_similarity(&[1.0, 1.0], &[3.0, 3.0]) - 1.0).abs() < 1e-6);
+        assert!(cosine_similarity(&[1.0, 0.0], &[0.0, 2.0]).abs() < 1e-6);
+        assert!((cosine_similarity(&[1.0, 0.0], &[-1.0, 0.0]) + 1.0).abs() < 1e-6);
+        assert_eq!(cosine_similarity(&[0.0, 0.0], &[1.0, 0.0]), 0.0);
+    }
+}
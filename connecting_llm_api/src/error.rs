@@ -0,0 +1,322 @@
+use std::{collections::BTreeMap, fmt, time::{Duration, SystemTime}};
+
+use reqwest::{header::HeaderMap, StatusCode};
+use serde::Deserialize;
+use serde_json::Value;
+
+pub type LlmResult<T> = Result<T, LlmError>;
+
+// failure modes of an LLM call, normalized across providers so callers can
+// decide programmatically whether to retry, shrink the prompt or give up.
+#[derive(Debug)]
+pub enum LlmError {
+    // missing, invalid or unauthorized credentials
+    Auth(String),
+    // the provider throttled us; `retry_after` comes from the response headers
+    RateLimited { retry_after: Option<Duration>, message: String },
+    // the provider is temporarily overloaded or down (HTTP 503)
+    Unavailable { retry_after: Option<Duration>, message: String },
+    // the request (or the provider's processing of it) took too long
+    Timeout,
+    // the prompt plus requested completion does not fit the model
+    ContextLengthExceeded(String),
+    // the prompt or reply was blocked by the provider's safety system
+    ContentFiltered(String),
+    // the provider answered with something we couldn't decode
+    MalformedResponse(String),
+    // a successful response without any choice / message in it
+    EmptyChoices,
+    // the reply still didn't match the requested schema after every repair attempt
+    InvalidStructuredOutput { attempts: usize, errors: Vec<String> },
+    // the model kept requesting tools past the allowed number of rounds
+    ToolRoundsExceeded(usize),
+    // connection level failures (DNS, refused connection, reset, ...)
+    Transport(reqwest::Error),
+    // replay mode found no recorded interaction for the request
+    Replay(String),
+    // the caller cancelled the request (see `cancel`)
+    Cancelled,
+    // the provider cannot take part of the request, e.g. a document on a text-only API
+    Unsupported(String),
+    // the request is malformed and was not sent, e.g. a tool reply without its call id
+    InvalidRequest(String),
+    // the client could not be built from its configuration (see `profiles`)
+    Config(String),
+    // the caller has spent its hard budget (in USD)
+    BudgetExceeded { scope: String, spent: f64, limit: f64 },
+    // any other non-success status
+    Api { status: u16, message: String },
+}
+
+impl LlmError {
+    // whether sending the same request again may succeed
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            LlmError::RateLimited { .. }
+            | LlmError::Unavailable { .. }
+            | LlmError::Timeout
+            | LlmError::Transport(_) => true,
+            LlmError::Api { status, .. } => *status >= 500,
+            _ => false,
+        }
+    }
+
+    // how long the provider asked us to wait, if it said so
+    pub fn retry_after(&self) -> Option<Duration> {
+        match self {
+            LlmError::RateLimited { retry_after, .. } | LlmError::Unavailable { retry_after, .. } => *retry_after,
+            _ => None,
+        }
+    }
+}
+
+impl fmt::Display for LlmError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            LlmError::Auth(message) => write!(f, "authentication failed: {}", message),
+            LlmError::RateLimited { retry_after: Some(after), message } => {
+                write!(f, "rate limited (retry after {:?}): {}", after, message)
+            }
+            LlmError::RateLimited { retry_after: None, message } => write!(f, "rate limited: {}", message),
+            LlmError::Unavailable { message, .. } => write!(f, "service unavailable: {}", message),
+            LlmError::Timeout => write!(f, "request timed out"),
+            LlmError::ContextLengthExceeded(message) => write!(f, "context length exceeded: {}", message),
+            LlmError::ContentFiltered(message) => write!(f, "content filtered: {}", message),
+            LlmError::MalformedResponse(message) => write!(f, "malformed response: {}", message),
+            LlmError::EmptyChoices => write!(f, "response contained no choices"),
+            LlmError::InvalidStructuredOutput { attempts, errors } => {
+                write!(f, "no valid structured output after {} attempts: {}", attempts, errors.join("; "))
+            }
+            LlmError::ToolRoundsExceeded(rounds) => write!(f, "model still requested tools after {} rounds", rounds),
+            LlmError::Transport(e) => write!(f, "transport error: {}", e),
+            LlmError::Replay(message) => write!(f, "cassette replay failed: {}", message),
+            LlmError::Cancelled => write!(f, "request was cancelled"),
+            LlmError::Unsupported(message) => write!(f, "not supported by this provider: {}", message),
+            LlmError::InvalidRequest(message) => write!(f, "invalid request: {}", message),
+            LlmError::Config(message) => write!(f, "invalid configuration: {}", message),
+            LlmError::BudgetExceeded { scope, spent, limit } => {
+                write!(f, "budget of {} exhausted: spent ${:.4} of ${:.4}", scope, spent, limit)
+            }
+            LlmError::Api { status, message } => write!(f, "API error {}: {}", status, message),
+        }
+    }
+}
+
+impl std::error::Error for LlmError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            LlmError::Transport(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<reqwest::Error> for LlmError {
+    fn from(e: reqwest::Error) -> Self {
+        if e.is_timeout() {
+            LlmError::Timeout
+        } else if e.is_decode() {
+            LlmError::MalformedResponse(e.to_string())
+        } else {
+            LlmError::Transport(e)
+        }
+    }
+}
+
+impl From<serde_json::Error> for LlmError {
+    fn from(e: serde_json::Error) -> Self {
+        LlmError::MalformedResponse(e.to_string())
+    }
+}
+
+// error bodies as the providers send them:
+//   OpenAI: {"error": {"message": "...", "type": "...", "code": "..."}}
+//   ollama: {"error": "..."}
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ErrorBody {
+    Detailed { error: ErrorDetail },
+    Plain { error: String },
+}
+
+#[derive(Deserialize)]
+struct ErrorDetail {
+    message: Option<String>,
+    #[serde(rename = "type")]
+    kind: Option<String>,
+    // a string on OpenAI, the HTTP status as a number on Gemini
+    code: Option<Value>,
+    // Azure OpenAI explains content-filter rejections here
+    innererror: Option<InnerError>,
+}
+
+#[derive(Deserialize)]
+struct InnerError {
+    code: Option<String>,
+    // category -> {"filtered": bool, "severity": ...}
+    #[serde(default)]
+    content_filter_result: BTreeMap<String, Value>,
+}
+
+impl InnerError {
+    fn filtered_categories(&self) -> Vec<&str> {
+        self.content_filter_result
+            .iter()
+            .filter(|(_, result)| result["filtered"].as_bool() == Some(true))
+            .map(|(category, _)| category.as_str())
+            .collect()
+    }
+}
+
+// OpenAI sends both `retry-after` (seconds) and `retry-after-ms`. per RFC 9110
+// `retry-after` may also be an HTTP date. negative, NaN and infinite values
+// are ignored rather than trusted.
+pub(crate) fn retry_after(headers: &HeaderMap) -> Option<Duration> {
+    let header = |name: &str| headers.get(name)?.to_str().ok().map(str::trim);
+    let seconds = |value: f64| (value >= 0.0).then(|| Duration::try_from_secs_f64(value).ok()).flatten();
+    let from_ms = header("retry-after-ms").and_then(|ms| seconds(ms.parse::<f64>().ok()? / 1000.0));
+    from_ms.or_else(|| {
+        let value = header("retry-after")?;
+        match value.parse::<f64>() {
+            Ok(value) => seconds(value),
+            // a date in the past means the wait is already over
+            Err(_) => {
+                let at = httpdate::parse_http_date(value).ok()?;
+                Some(at.duration_since(SystemTime::now()).unwrap_or(Duration::ZERO))
+            }
+        }
+    })
+}
+
+// maps a non-success response onto the taxonomy above
+pub(crate) fn classify(status: StatusCode, headers: &HeaderMap, body: &str) -> LlmError {
+    let (message, code) = match serde_json::from_str::<ErrorBody>(body) {
+        // Azure OpenAI names the offending categories in `innererror`
+        Ok(ErrorBody::Detailed { error: ErrorDetail { message, innererror: Some(inner), .. } })
+            if inner.code.as_deref() == Some("ResponsibleAIPolicyViolation") =>
+        {
+            let mut message = message.unwrap_or_else(|| body.to_string());
+            let categories = inner.filtered_categories();
+            if !categories.is_empty() {
+                message = format!("{} (filtered: {})", message, categories.join(", "));
+            }
+            return LlmError::ContentFiltered(message);
+        }
+        Ok(ErrorBody::Detailed { error }) => (
+            error.message.unwrap_or_else(|| body.to_string()),
+            error.code.and_then(|code| code.as_str().map(str::to_string)).or(error.kind).unwrap_or_default(),
+        ),
+        Ok(ErrorBody::Plain { error }) => (error, String::new()),
+        Err(_) => (body.to_string(), String::new()),
+    };
+    let lowered = message.to_lowercase();
+
+    if code == "context_length_exceeded"
+        || lowered.contains("context length")
+        || lowered.contains("maximum context")
+        || lowered.contains("prompt is too long")
+        || lowered.contains("exceeds the maximum number of tokens")
+    {
+        return LlmError::ContextLengthExceeded(message);
+    }
+    if code == "content_filter" || code == "content_policy_violation" {
+        return LlmError::ContentFiltered(message);
+    }
+
+    match status {
+        StatusCode::UNAUTHORIZED | StatusCode::FORBIDDEN => LlmError::Auth(message),
+        // an exhausted quota is reported as 429 too, but waiting won't fix it
+        StatusCode::TOO_MANY_REQUESTS if code != "insufficient_quota" => LlmError::RateLimited {
+            retry_after: retry_after(headers),
+            message,
+        },
+        // 529 is Anthropic's "overloaded"
+        StatusCode::SERVICE_UNAVAILABLE => LlmError::Unavailable {
+            retry_after: retry_after(headers),
+            message,
+        },
+        status if status.as_u16() == 529 => LlmError::Unavailable {
+            retry_after: retry_after(headers),
+            message,
+        },
+        StatusCode::REQUEST_TIMEOUT | StatusCode::GATEWAY_TIMEOUT => LlmError::Timeout,
+        _ => LlmError::Api { status: status.as_u16(), message },
+    }
+}
+
+// passes successful responses through and turns everything else into an `LlmError`
+pub(crate) async fn check(response: reqwest::Response) -> LlmResult<reqwest::Response> {
+    let status = response.status();
+    if status.is_success() {
+        return Ok(response);
+    }
+    let headers = response.headers().clone();
+    let body = response.text().await.unwrap_or_default();
+    Err(classify(status, &headers, &body))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn headers(pairs: &[(&'static str, &str)]) -> HeaderMap {
+        let mut headers = HeaderMap::new();
+        for (name, value) in pairs {
+            headers.insert(*name, HeaderValue::from_str(value).unwrap());
+        }
+        headers
+    }
+
+    #[test]
+    fn retry_after_reads_seconds_and_milliseconds() {
+        assert_eq!(retry_after(&headers(&[("retry-after", "2")])), Some(Duration::from_secs(2)));
+        assert_eq!(retry_after(&headers(&[("retry-after", " 1.5 ")])), Some(Duration::from_millis(1500)));
+        // the millisecond header is more precise and wins
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "2"), ("retry-after-ms", "250")])),
+            Some(Duration::from_millis(250))
+        );
+        assert_eq!(retry_after(&HeaderMap::new()), None);
+    }
+
+    #[test]
+    fn retry_after_ignores_values_that_are_not_durations() {
+        for value in ["-1", "NaN", "inf", "-inf", "1e400", "soon"] {
+            assert_eq!(retry_after(&headers(&[("retry-after", value)])), None, "retry-after: {}", value);
+            assert_eq!(retry_after(&headers(&[("retry-after-ms", value)])), None, "retry-after-ms: {}", value);
+        }
+        // a broken millisecond header falls back to the seconds one
+        assert_eq!(
+            retry_after(&headers(&[("retry-after", "3"), ("retry-after-ms", "-5")])),
+            Some(Duration::from_secs(3))
+        );
+    }
+
+    #[test]
+    fn retry_after_reads_http_dates() {
+        let at = httpdate::fmt_http_date(SystemTime::now() + Duration::from_secs(120));
+        let wait = retry_after(&headers(&[("retry-after", &at)])).unwrap();
+        assert!(wait > Duration::from_secs(115) && wait <= Duration::from_secs(120), "{:?}", wait);
+
+        let past = httpdate::fmt_http_date(SystemTime::now() - Duration::from_secs(60));
+        assert_eq!(retry_after(&headers(&[("retry-after", &past)])), Some(Duration::ZERO));
+    }
+
+    #[test]
+    fn azure_policy_violations_name_the_filtered_categories() {
+        let body = r#"{"error": {"message": "The response was filtered", "code": "content_filter", "status": 400,
+            "innererror": {"code": "ResponsibleAIPolicyViolation", "content_filter_result": {
+                "hate": {"filtered": false, "severity": "safe"},
+                "violence": {"filtered": true, "severity": "medium"},
+                "self_harm": {"filtered": true, "severity": "high"}}}}}"#;
+        match classify(StatusCode::BAD_REQUEST, &HeaderMap::new(), body) {
+            LlmError::ContentFiltered(message) => assert_eq!(message, "The response was filtered (filtered: self_harm, violence)"),
+            other => panic!("expected ContentFiltered, got {}", other),
+        }
+
+        // another inner code is classified as usual
+        let body = r#"{"error": {"message": "Invalid deployment", "code": "DeploymentNotFound", "innererror": {"code": "Other"}}}"#;
+        assert!(matches!(classify(StatusCode::NOT_FOUND, &HeaderMap::new(), body), LlmError::Api { status: 404, .. }));
+    }
+}
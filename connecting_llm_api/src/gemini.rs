@@ -0,0 +1,636 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use async_trait::async_trait;
+use std::{collections::HashMap, env, path::Path, sync::Arc, time::Duration};
+
+use crate::client::{self, ChatCompletion, ChatMessage, ChatOptions, ContentPart, FileSource, LlmClient, ResponseFormat, Role, ToolCall, ToolChoice, ToolDefinition, Usage};
+use crate::cancel;
+use crate::cassette::Cassette;
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::{self, ChatStream, StreamEvent};
+use crate::transport::HttpTransport;
+
+const GEMINI_BASE_URL: &str = "https://generativelanguage.googleapis.com/v1beta";
+
+// finish reasons meaning the candidate was cut off by a safety or policy filter
+const BLOCKING_REASONS: &[&str] = &["SAFETY", "RECITATION", "BLOCKLIST", "PROHIBITED_CONTENT", "SPII", "IMAGE_SAFETY"];
+
+// wire format of a message.
+// a `Content` is a role ("user" or "model") plus parts; a part holds exactly
+// one of text, inline bytes, a file URI, a function call or a function
+// response. function responses are matched to calls by name, like on ollama.
+#[derive(Serialize, Deserialize, Default)]
+struct Content {
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    role: String,
+    #[serde(default)]
+    parts: Vec<Part>,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+#[serde(rename_all = "camelCase")]
+struct Part {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    inline_data: Option<Blob>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_data: Option<FileData>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_call: Option<FunctionCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    function_response: Option<FunctionResponse>,
+    // thinking summaries of reasoning models, not part of the answer
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    thought: bool,
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Blob {
+    mime_type: String,
+    data: String,
+}
+
+// a file uploaded through the Files API, or a public URL
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct FileData {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    mime_type: Option<String>,
+    file_uri: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    #[serde(default)]
+    args: Value,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionResponse {
+    name: String,
+    response: Value,
+}
+
+impl Part {
+    fn text(text: impl Into<String>) -> Self {
+        Part {
+            text: Some(text.into()),
+            ..Part::default()
+        }
+    }
+
+    // a data URI becomes inline bytes, anything else a file URI. Gemini
+    // needs the type of a file URI, taken from the filename or the URI's
+    // extension.
+    fn media(uri: &str, filename: Option<&str>) -> LlmResult<Self> {
+        if let Some((mime_type, data)) = client::split_data_uri(uri) {
+            return Ok(Part {
+                inline_data: Some(Blob {
+                    mime_type: mime_type.to_string(),
+                    data: data.to_string(),
+                }),
+                ..Part::default()
+            });
+        }
+        let path = uri.split(['?', '#']).next().unwrap_or_default();
+        let mime_type = filename
+            .and_then(|filename| client::known_media_type(Path::new(filename)))
+            .or_else(|| client::known_media_type(Path::new(path)))
+            .ok_or_else(|| LlmError::InvalidRequest(format!("cannot tell the media type of {}; send it as data or name the file", uri)))?;
+        Ok(Part {
+            file_data: Some(FileData {
+                mime_type: Some(mime_type.to_string()),
+                file_uri: uri.to_string(),
+            }),
+            ..Part::default()
+        })
+    }
+}
+
+// the message text followed by its parts
+fn user_parts(message: &ChatMessage) -> LlmResult<Vec<Part>> {
+    let text = (!message.content.is_empty() || message.parts.is_empty()).then(|| Ok(Part::text(&message.content)));
+    text.into_iter()
+        .chain(message.parts.iter().map(|part| match part {
+            ContentPart::Text { text } => Ok(Part::text(text)),
+            ContentPart::Image { url } => Part::media(url, None),
+            ContentPart::File { filename, source: FileSource::Id(uri) | FileSource::Data(uri) } => Part::media(uri, filename.as_deref()),
+        }))
+        .collect()
+}
+
+// function responses must be JSON objects; anything else is wrapped
+fn function_response(content: &str) -> Value {
+    match serde_json::from_str::<Value>(content) {
+        Ok(value @ Value::Object(_)) => value,
+        _ => json!({ "content": content }),
+    }
+}
+
+// splits off the system instruction, resolves tool replies to function names
+// and merges consecutive turns of the same role
+fn to_wire(messages: &[ChatMessage]) -> LlmResult<(Option<Content>, Vec<Content>)> {
+    let mut names: HashMap<&str, &str> = HashMap::new();
+    let mut system: Vec<Part> = vec![];
+    let mut contents: Vec<Content> = vec![];
+
+    for message in messages {
+        let (role, parts) = match message.role {
+            Role::System => {
+                system.push(Part::text(&message.content));
+                continue;
+            }
+            Role::Assistant => {
+                let mut parts = vec![];
+                if !message.content.is_empty() {
+                    parts.push(Part::text(&message.content));
+                }
+                for call in &message.tool_calls {
+                    names.insert(&call.id, &call.name);
+                    parts.push(Part {
+                        function_call: Some(FunctionCall {
+                            name: call.name.clone(),
+                            args: call.arguments.clone(),
+                        }),
+                        ..Part::default()
+                    });
+                }
+                ("model", parts)
+            }
+            Role::Tool => {
+                let Some(id) = message.tool_call_id.as_deref() else {
+                    return Err(LlmError::InvalidRequest("tool message without a `tool_call_id`".into()));
+                };
+                let Some(name) = names.get(id).map(|name| name.to_string()) else {
+                    return Err(LlmError::InvalidRequest(format!("tool message answers `{}`, which no earlier assistant turn called", id)));
+                };
+                let part = Part {
+                    function_response: Some(FunctionResponse {
+                        name,
+                        response: function_response(&message.content),
+                    }),
+                    ..Part::default()
+                };
+                ("user", vec![part])
+            }
+            Role::User => ("user", user_parts(message)?),
+        };
+        // the API rejects turns without parts, e.g. an assistant reply that was empty
+        if parts.is_empty() {
+            continue;
+        }
+
+        match contents.last_mut() {
+            Some(last) if last.role == role => last.parts.extend(parts),
+            _ => contents.push(Content { role: role.to_string(), parts }),
+        }
+    }
+
+    let system = (!system.is_empty()).then_some(Content { role: String::new(), parts: system });
+    Ok((system, contents))
+}
+
+// Gemini doesn't assign ids, so synthesize stable ones for pairing replies
+fn from_wire(content: Content) -> ChatMessage {
+    let mut message = ChatMessage::assistant("");
+    for part in content.parts.into_iter().filter(|part| !part.thought) {
+        if let Some(text) = part.text {
+            message.content.push_str(&text);
+        }
+        if let Some(call) = part.function_call {
+            message.tool_calls.push(ToolCall {
+                id: format!("call_{}_{}", call.name, message.tool_calls.len()),
+                name: call.name,
+                arguments: call.args,
+            });
+        }
+    }
+    message
+}
+
+// finish reasons in the vocabulary the other providers use.
+// Gemini reports STOP for function calls too.
+fn finish_reason(reason: Option<String>, has_tool_calls: bool) -> Option<String> {
+    reason.map(|reason| match reason.as_str() {
+        "STOP" if has_tool_calls => "tool_calls".to_string(),
+        "STOP" => "stop".to_string(),
+        "MAX_TOKENS" => "length".to_string(),
+        reason if BLOCKING_REASONS.contains(&reason) => "content_filter".to_string(),
+        reason => reason.to_lowercase(),
+    })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GeminiTools<'a> {
+    function_declarations: Vec<FunctionDeclaration<'a>>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct FunctionDeclaration<'a> {
+    name: &'a str,
+    description: &'a str,
+    // plain JSON schema, unlike `parameters` which takes an OpenAPI subset
+    parameters_json_schema: &'a Value,
+}
+
+impl<'a> From<&'a ToolDefinition> for FunctionDeclaration<'a> {
+    fn from(tool: &'a ToolDefinition) -> Self {
+        FunctionDeclaration {
+            name: &tool.name,
+            description: &tool.description,
+            parameters_json_schema: &tool.parameters,
+        }
+    }
+}
+
+fn tool_config(choice: &ToolChoice) -> Value {
+    let config = match choice {
+        ToolChoice::Auto => json!({"mode": "AUTO"}),
+        ToolChoice::None => json!({"mode": "NONE"}),
+        ToolChoice::Required => json!({"mode": "ANY"}),
+        ToolChoice::Tool(name) => json!({"mode": "ANY", "allowedFunctionNames": [name]}),
+    };
+    json!({ "functionCallingConfig": config })
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerationConfig<'a> {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    temperature: Option<f32>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_output_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop_sequences: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_mime_type: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_json_schema: Option<&'a Value>,
+}
+
+#[derive(Serialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateRequest<'a> {
+    contents: Vec<Content>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system_instruction: Option<Content>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<GeminiTools<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_config: Option<Value>,
+    generation_config: GenerationConfig<'a>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GenerateResponse {
+    #[serde(default)]
+    candidates: Vec<Candidate>,
+    prompt_feedback: Option<PromptFeedback>,
+    usage_metadata: Option<UsageMetadata>,
+    model_version: Option<String>,
+}
+
+impl GenerateResponse {
+    // a blocked prompt comes back as 200 without candidates
+    fn check_prompt(&self) -> LlmResult<()> {
+        match self.prompt_feedback.as_ref().and_then(|f| f.block_reason.as_ref().map(|reason| (reason, f))) {
+            Some((reason, feedback)) => Err(LlmError::ContentFiltered(format!(
+                "prompt blocked ({}){}",
+                reason,
+                blocked_categories(&feedback.safety_ratings)
+            ))),
+            None => Ok(()),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct Candidate {
+    #[serde(default)]
+    content: Content,
+    finish_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct PromptFeedback {
+    block_reason: Option<String>,
+    #[serde(default)]
+    safety_ratings: Vec<SafetyRating>,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct UsageMetadata {
+    #[serde(default)]
+    prompt_token_count: u32,
+    #[serde(default)]
+    candidates_token_count: u32,
+}
+
+impl From<UsageMetadata> for Usage {
+    fn from(usage: UsageMetadata) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_token_count,
+            completion_tokens: usage.candidates_token_count,
+        }
+    }
+}
+
+// how likely a reply is to fall into a harm category, e.g.
+// HARM_CATEGORY_HARASSMENT / NEGLIGIBLE
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct SafetyRating {
+    pub category: String,
+    pub probability: String,
+    #[serde(default)]
+    pub blocked: bool,
+}
+
+// " (blocked: A, B)" for the categories that triggered a block, if named
+fn blocked_categories(ratings: &[SafetyRating]) -> String {
+    let blocked: Vec<&str> = ratings
+        .iter()
+        .filter(|rating| rating.blocked)
+        .map(|rating| rating.category.as_str())
+        .collect();
+    if blocked.is_empty() {
+        String::new()
+    } else {
+        format!(" (blocked: {})", blocked.join(", "))
+    }
+}
+
+// a completion together with the safety ratings of the returned candidate
+#[derive(Debug, Clone)]
+pub struct GeminiCompletion {
+    pub completion: ChatCompletion,
+    pub safety_ratings: Vec<SafetyRating>,
+}
+
+// everything needed to build a Gemini client.
+// ensure you load the API key from .env file or secure secret manager.
+#[derive(Debug, Clone)]
+pub struct GeminiConfig {
+    pub api_key: String,
+    pub model: String,
+    pub timeout: Duration,
+    pub base_url: String,
+}
+
+impl GeminiConfig {
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        GeminiConfig {
+            api_key: api_key.into(),
+            model: model.into(),
+            timeout: Duration::from_secs(60),
+            base_url: GEMINI_BASE_URL.to_string(),
+        }
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    // reads GEMINI_API_KEY (or GOOGLE_API_KEY) from the environment (or a .env file)
+    pub fn from_env(model: impl Into<String>) -> Result<Self, env::VarError> {
+        dotenv::dotenv().ok();
+        let api_key = env::var("GEMINI_API_KEY").or_else(|_| env::var("GOOGLE_API_KEY"))?;
+        Ok(Self::new(api_key, model))
+    }
+}
+
+pub struct GeminiClient {
+    config: GeminiConfig,
+    http: HttpTransport,
+}
+
+impl GeminiClient {
+    pub fn new(config: GeminiConfig) -> LlmResult<Self> {
+        let http = HttpTransport::new(config.timeout)?;
+        Ok(GeminiClient { config, http })
+    }
+
+    // records to or replays from `cassette` instead of the LLM_CASSETTE one
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.http = self.http.with_cassette(cassette);
+        self
+    }
+
+    fn request<'a>(&'a self, messages: &'a [ChatMessage], options: &'a ChatOptions) -> LlmResult<GenerateRequest<'a>> {
+        let (system_instruction, contents) = to_wire(messages)?;
+        let (response_mime_type, response_json_schema) = match &options.response_format {
+            Some(ResponseFormat::Json) => (Some("application/json"), None),
+            Some(ResponseFormat::JsonSchema { schema, .. }) => (Some("application/json"), Some(schema)),
+            None => (None, None),
+        };
+
+        Ok(GenerateRequest {
+            contents,
+            system_instruction,
+            tools: if options.tools.is_empty() {
+                vec![]
+            } else {
+                vec![GeminiTools {
+                    function_declarations: options.tools.iter().map(FunctionDeclaration::from).collect(),
+                }]
+            },
+            tool_config: options.tool_choice.as_ref().map(tool_config),
+            generation_config: GenerationConfig {
+                temperature: options.temperature,
+                max_output_tokens: options.max_tokens,
+                stop_sequences: &options.stop,
+                response_mime_type,
+                response_json_schema,
+            },
+        })
+    }
+
+    // `method` is `generateContent` or `streamGenerateContent`
+    async fn post(&self, model: &str, method: &str, body: &GenerateRequest<'_>) -> LlmResult<reqwest::Response> {
+        let streaming = method == "streamGenerateContent";
+        let mut url = format!("{}/models/{}:{}", self.config.base_url.trim_end_matches('/'), model, method);
+        if streaming {
+            url.push_str("?alt=sse");
+        }
+        let request = self.http
+            .client()
+            .post(url)
+            .header("x-goog-api-key", &self.config.api_key)
+            .json(body);
+        if streaming {
+            self.http.send_streaming(request).await
+        } else {
+            self.http.send(request).await
+        }
+    }
+
+    // like `chat`, but keeps the safety ratings of the candidate
+    pub async fn generate(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<GeminiCompletion> {
+        let model = options.model.as_deref().unwrap_or(&self.config.model);
+        let request_body = self.request(messages, options)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), async {
+            Ok(self.post(model, "generateContent", &request_body).await?.json::<GenerateResponse>().await?)
+        })
+        .await?;
+        response.check_prompt()?;
+
+        let candidate = response.candidates.into_iter().next().ok_or(LlmError::EmptyChoices)?;
+        let message = from_wire(candidate.content);
+        let blocked = candidate.finish_reason.as_deref().is_some_and(|reason| BLOCKING_REASONS.contains(&reason));
+        if blocked && message.content.is_empty() && message.tool_calls.is_empty() {
+            return Err(LlmError::ContentFiltered(format!(
+                "reply was blocked ({}){}",
+                candidate.finish_reason.unwrap_or_default(),
+                blocked_categories(&candidate.safety_ratings)
+            )));
+        }
+
+        let has_tool_calls = !message.tool_calls.is_empty();
+        Ok(GeminiCompletion {
+            completion: ChatCompletion {
+                message,
+                model: response.model_version.unwrap_or_else(|| model.to_string()),
+                finish_reason: finish_reason(candidate.finish_reason, has_tool_calls),
+                usage: response.usage_metadata.map(Usage::from),
+            },
+            safety_ratings: candidate.safety_ratings,
+        })
+    }
+}
+
+#[async_trait]
+impl LlmClient for GeminiClient {
+    fn provider(&self) -> &'static str {
+        "gemini"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.config.model
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        Some(&self.config.base_url)
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let generated = self.generate(messages, options).await?;
+        if generated.safety_ratings.iter().any(|rating| rating.probability != "NEGLIGIBLE") {
+            tracing::debug!(target: "llm::gemini", ratings = ?generated.safety_ratings, "reply has safety ratings");
+        }
+        Ok(generated.completion)
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let model = options.model.as_deref().unwrap_or(&self.config.model);
+        let request_body = self.request(messages, options)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), self.post(model, "streamGenerateContent", &request_body)).await?;
+
+        let mut tool_calls: Vec<ToolCall> = vec![];
+        let mut streamed_text = false;
+        let lines = streaming::lines(response.bytes_stream());
+
+        let events = streaming::events(lines, move |line| {
+            // server-sent events; every payload is a complete `GenerateResponse`
+            // holding the next parts, and the last one carries the finish reason
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(vec![]);
+            };
+            let chunk: GenerateResponse = serde_json::from_str(data)?;
+            chunk.check_prompt()?;
+            let Some(candidate) = chunk.candidates.into_iter().next() else {
+                return Ok(vec![]);
+            };
+
+            let mut events = vec![];
+            let message = from_wire(candidate.content);
+            if !message.content.is_empty() {
+                streamed_text = true;
+                events.push(StreamEvent::Delta(message.content));
+            }
+            // function calls arrive whole; renumber the ids across chunks
+            for mut call in message.tool_calls {
+                call.id = format!("call_{}_{}", call.name, tool_calls.len());
+                tool_calls.push(call);
+            }
+
+            if let Some(reason) = candidate.finish_reason {
+                if BLOCKING_REASONS.contains(&reason.as_str()) && !streamed_text && tool_calls.is_empty() {
+                    return Err(LlmError::ContentFiltered(format!(
+                        "reply was blocked ({}){}",
+                        reason,
+                        blocked_categories(&candidate.safety_ratings)
+                    )));
+                }
+                let has_tool_calls = !tool_calls.is_empty();
+                if has_tool_calls {
+                    events.push(StreamEvent::ToolCalls(std::mem::take(&mut tool_calls)));
+                }
+                events.push(StreamEvent::Done {
+                    finish_reason: finish_reason(Some(reason), has_tool_calls),
+                    usage: chunk.usage_metadata.map(Usage::from),
+                    model: chunk.model_version,
+                });
+            }
+            Ok(events)
+        });
+        Ok(cancel::guard(events, options.cancel.clone()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wire_parts(part: ContentPart) -> LlmResult<Value> {
+        let (_, contents) = to_wire(&[ChatMessage::user("look").with_part(part)])?;
+        Ok(serde_json::to_value(&contents[0].parts[1]).unwrap())
+    }
+
+    #[test]
+    fn file_uris_carry_a_media_type() {
+        assert_eq!(
+            wire_parts(ContentPart::file_id("gs://bucket/report.pdf?version=2")).unwrap(),
+            json!({ "fileData": { "mimeType": "application/pdf", "fileUri": "gs://bucket/report.pdf?version=2" } })
+        );
+        let named = ContentPart::File {
+            filename: Some("chart.png".into()),
+            source: FileSource::Id("https://generativelanguage.googleapis.com/v1beta/files/abc123".into()),
+        };
+        assert_eq!(wire_parts(named).unwrap()["fileData"]["mimeType"], "image/png");
+        assert!(matches!(
+            wire_parts(ContentPart::file_id("https://generativelanguage.googleapis.com/v1beta/files/abc123")),
+            Err(LlmError::InvalidRequest(_))
+        ));
+    }
+
+    #[test]
+    fn tool_replies_must_answer_a_call() {
+        let call = ToolCall {
+            id: "call_1".into(),
+            name: "get_weather".into(),
+            arguments: json!({"city": "Paris"}),
+        };
+        let asked = ChatMessage { tool_calls: vec![call], ..ChatMessage::assistant("") };
+        let (_, contents) = to_wire(&[ChatMessage::user("weather?"), ChatMessage::assistant(""), asked.clone(), ChatMessage::tool("call_1", "sunny")]).unwrap();
+        // the empty assistant turn is dropped, the call keeps its content
+        assert_eq!(contents.iter().map(|content| content.role.as_str()).collect::<Vec<_>>(), ["user", "model", "user"]);
+        assert_eq!(contents[1].parts.len(), 1);
+        assert_eq!(serde_json::to_value(&contents[2].parts[0]).unwrap()["functionResponse"]["name"], "get_weather");
+
+        for orphan in [ChatMessage::tool("call_9", "sunny"), ChatMessage { tool_call_id: None, ..ChatMessage::tool("", "sunny") }] {
+            assert!(matches!(to_wire(&[asked.clone(), orphan]), Err(LlmError::InvalidRequest(_))));
+        }
+    }
+}
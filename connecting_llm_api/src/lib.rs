@@ -5,8 +5,48 @@
 // using local models, monitor CPU and memory consumptions to avoid
 // system-level contemtion, especially in multi-agent environments.
 
+pub mod cache;
+pub mod cancel;
+pub mod cassette;
+pub mod client;
+pub mod conversation;
+pub mod embeddings;
+pub mod error;
+pub mod streaming;
+pub mod structured;
+pub mod tokens;
+pub mod profiles;
+pub mod tool_calling;
+pub mod usage;
+mod transport;
+pub mod anthropic;
+pub mod gemini;
 pub mod openai;
 pub mod ollama;
+pub mod rate_limit;
+pub mod retry;
+pub mod router;
+#[cfg(feature = "mock-server")]
+pub mod mock_server;
+
+pub use cache::{CachedClient, DiskCache, InMemoryCache};
+pub use cancel::CancellationToken;
+pub use cassette::{Cassette, CassetteMode, MatchRules};
+pub use client::{ChatCompletion, ChatMessage, ChatOptions, ContentPart, FileSource, LlmClient, ResponseFormat, Role, ToolCall, ToolChoice, ToolDefinition, Usage};
+pub use conversation::{Conversation, TrimStrategy};
+pub use embeddings::{EmbeddingClient, EmbeddingOptions, Embeddings};
+pub use error::{LlmError, LlmResult};
+pub use profiles::{Profile, ProfileClient, ProfileError, Profiles, ProviderKind};
+pub use rate_limit::{RateLimitedClient, RateLimiter, RateLimits};
+pub use retry::{RetryPolicy, RetryingClient};
+pub use router::{Capability, HealthPolicy, Route, RoutingClient, Strategy};
+pub use streaming::{ChatStream, StreamEvent};
+pub use structured::complete_structured;
+pub use usage::{Budget, GroupBy, MeteredClient, PriceTable, Pricing, Reservation, UsageRecord, UsageTotals, UsageTracker};
+pub use anthropic::{AnthropicClient, AnthropicConfig};
+pub use gemini::{GeminiClient, GeminiConfig};
+pub use openai::{AuthScheme, AzureSettings, OpenAiClient, OpenAiConfig};
+pub use ollama::{GenerateRequest, Generation, KeepAlive, LocalModel, ModelDetails, ModelInfo, OllamaClient, OllamaConfig, OllamaOptions, PullProgress, PullStream, RunningModel};
 
 // For example, use a timeout:
 /*
@@ -16,4 +56,12 @@ let response = client
     .json(&request)
     .send()
     .await?;
-*/
\ No newline at end of file
+*/
+// and wrap the client so rate limits, 503s and timeouts are retried with
+// exponential backoff (honoring Retry-After) instead of failing the agent:
+/*
+let client = RetryingClient::new(
+    OpenAiClient::new(OpenAiConfig::from_env("gpt-4")?)?,
+    RetryPolicy::default().with_max_attempts(5).with_deadline(Duration::from_secs(90)),
+);
+*/
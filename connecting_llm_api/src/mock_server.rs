@@ -0,0 +1,694 @@
+use axum::{
+    Router,
+    body::{Body, Bytes},
+    extract::{OriginalUri, State},
+    http::{StatusCode, header},
+    response::Response,
+    routing::{delete, get, post},
+};
+use futures::{StreamExt, stream};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use std::{
+    collections::VecDeque,
+    convert::Infallible,
+    io,
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::{net::TcpListener, task::JoinHandle};
+
+// a local stand-in for the OpenAI (`/v1/chat/completions`, `/v1/embeddings`),
+// Anthropic (`/v1/messages`), Gemini (`/v1beta/models/{model}:generateContent`)
+// and ollama (`/api/chat`, `/api/generate`, `/api/embed` and the model management)
+// endpoints, for end to end tests of agents.
+// replies come from a script (consumed in order), then from the first
+// matching rule, then from an echo fallback. every request is recorded so
+// tests can assert on what the agent actually sent.
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MockReply {
+    Text { content: String },
+    ToolCall { name: String, arguments: Value },
+    // any error status, e.g. 429 with a Retry-After (in seconds)
+    Error {
+        status: u16,
+        message: String,
+        #[serde(default)]
+        retry_after: Option<u64>,
+    },
+    // a 200 whose body is sent verbatim, to exercise decoding failures
+    Malformed { body: String },
+}
+
+impl MockReply {
+    pub fn text(content: impl Into<String>) -> Self {
+        MockReply::Text { content: content.into() }
+    }
+
+    pub fn tool_call(name: impl Into<String>, arguments: Value) -> Self {
+        MockReply::ToolCall { name: name.into(), arguments }
+    }
+
+    pub fn rate_limited(retry_after: u64) -> Self {
+        MockReply::Error {
+            status: 429,
+            message: "Rate limit reached (mock)".into(),
+            retry_after: Some(retry_after),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct MockRule {
+    // substring of the last message's content; None matches any prompt
+    #[serde(default)]
+    pub when_contains: Option<String>,
+    #[serde(default)]
+    pub model: Option<String>,
+    pub reply: MockReply,
+    #[serde(default)]
+    pub latency_ms: u64,
+    // how often the rule may fire; None = unlimited
+    #[serde(default)]
+    pub times: Option<usize>,
+}
+
+impl MockRule {
+    pub fn new(reply: MockReply) -> Self {
+        MockRule {
+            when_contains: None,
+            model: None,
+            reply,
+            latency_ms: 0,
+            times: None,
+        }
+    }
+
+    pub fn when_contains(mut self, text: impl Into<String>) -> Self {
+        self.when_contains = Some(text.into());
+        self
+    }
+
+    pub fn with_latency(mut self, latency: Duration) -> Self {
+        self.latency_ms = latency.as_millis() as u64;
+        self
+    }
+
+    pub fn times(mut self, times: usize) -> Self {
+        self.times = Some(times);
+        self
+    }
+
+    fn matches(&self, model: &str, prompt: &str) -> bool {
+        self.times != Some(0)
+            && self.model.as_deref().is_none_or(|m| m == model)
+            && self.when_contains.as_deref().is_none_or(|text| prompt.contains(text))
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct MockConfig {
+    #[serde(default)]
+    pub script: VecDeque<MockReply>,
+    #[serde(default)]
+    pub rules: Vec<MockRule>,
+    // pause between streamed chunks
+    #[serde(default)]
+    pub chunk_delay_ms: u64,
+    // what `/api/tags` lists as pulled; `/api/pull` and `/api/delete` update it
+    #[serde(default)]
+    pub models: Vec<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct ReceivedRequest {
+    pub path: String,
+    pub body: Value,
+}
+
+#[derive(Clone, Copy)]
+enum Dialect {
+    OpenAi,
+    Ollama,
+    Anthropic,
+    Gemini,
+}
+
+struct MockState {
+    config: Mutex<MockConfig>,
+    received: Mutex<Vec<ReceivedRequest>>,
+}
+
+impl MockState {
+    fn next_reply(&self, model: &str, prompt: &str) -> (MockReply, Duration) {
+        let mut config = self.config.lock().unwrap();
+        if let Some(reply) = config.script.pop_front() {
+            return (reply, Duration::ZERO);
+        }
+        if let Some(rule) = config.rules.iter_mut().find(|rule| rule.matches(model, prompt)) {
+            if let Some(times) = &mut rule.times {
+                *times -= 1;
+            }
+            return (rule.reply.clone(), Duration::from_millis(rule.latency_ms));
+        }
+        (MockReply::text(format!("mock reply to: {}", prompt)), Duration::ZERO)
+    }
+
+    // parses and records an incoming request body
+    fn receive(&self, path: &str, body: &[u8]) -> Option<Value> {
+        let body: Value = serde_json::from_slice(body).ok()?;
+        self.received.lock().unwrap().push(ReceivedRequest {
+            path: path.to_string(),
+            body: body.clone(),
+        });
+        Some(body)
+    }
+}
+
+pub struct MockServer {
+    addr: SocketAddr,
+    state: Arc<MockState>,
+    task: JoinHandle<()>,
+}
+
+impl MockServer {
+    // binds an ephemeral port on localhost
+    pub async fn start(config: MockConfig) -> io::Result<MockServer> {
+        Self::bind("127.0.0.1:0".parse().unwrap(), config).await
+    }
+
+    pub async fn bind(addr: SocketAddr, config: MockConfig) -> io::Result<MockServer> {
+        let state = Arc::new(MockState {
+            config: Mutex::new(config),
+            received: Mutex::new(vec![]),
+        });
+        let app = Router::new()
+            .route("/v1/chat/completions", post(|state, uri, body| handle(state, uri, Dialect::OpenAi, body)))
+            .route("/api/chat", post(|state, uri, body| handle(state, uri, Dialect::Ollama, body)))
+            // Azure OpenAI, any deployment
+            .route("/openai/deployments/{deployment}/chat/completions", post(|state, uri, body| handle(state, uri, Dialect::OpenAi, body)))
+            .route("/openai/deployments/{deployment}/embeddings", post(|state, uri, body| embed(state, uri, Dialect::OpenAi, body)))
+            .route("/v1/messages", post(|state, uri, body| handle(state, uri, Dialect::Anthropic, body)))
+            // Gemini, e.g. /v1beta/models/gemini-2.0-flash:generateContent
+            .route("/v1beta/models/{call}", post(|state, uri, body| handle(state, uri, Dialect::Gemini, body)))
+            .route("/v1/embeddings", post(|state, uri, body| embed(state, uri, Dialect::OpenAi, body)))
+            .route("/api/generate", post(generate))
+            .route("/api/embed", post(|state, uri, body| embed(state, uri, Dialect::Ollama, body)))
+            .route("/api/version", get(|| async { respond(StatusCode::OK, json!({"version": "0.0.0-mock"}).to_string()) }))
+            .route("/api/tags", get(tags))
+            .route("/api/ps", get(|| async { respond(StatusCode::OK, json!({"models": []}).to_string()) }))
+            .route("/api/show", post(show))
+            .route("/api/pull", post(pull))
+            .route("/api/delete", delete(remove))
+            .with_state(state.clone());
+
+        let listener = TcpListener::bind(addr).await?;
+        let addr = listener.local_addr()?;
+        let task = tokio::spawn(async move {
+            if let Err(e) = axum::serve(listener, app).await {
+                tracing::error!(target: "llm::mock", error = %e, "mock server stopped");
+            }
+        });
+        Ok(MockServer { addr, state, task })
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    // base URL for `OllamaConfig::with_host`
+    pub fn url(&self) -> String {
+        format!("http://{}", self.addr)
+    }
+
+    // base URL for `OpenAiConfig::with_base_url` and `AnthropicConfig::with_base_url`
+    pub fn openai_base_url(&self) -> String {
+        format!("http://{}/v1", self.addr)
+    }
+
+    // queues a reply that is served before any rule is consulted
+    pub fn push_reply(&self, reply: MockReply) {
+        self.state.config.lock().unwrap().script.push_back(reply);
+    }
+
+    pub fn add_rule(&self, rule: MockRule) {
+        self.state.config.lock().unwrap().rules.push(rule);
+    }
+
+    pub fn received(&self) -> Vec<ReceivedRequest> {
+        self.state.received.lock().unwrap().clone()
+    }
+
+    // runs until the process is stopped; used by the standalone binary
+    pub async fn wait(self) {
+        let _ = (&mut { self }.task).await;
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.task.abort();
+    }
+}
+
+async fn handle(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, dialect: Dialect, body: Bytes) -> Response {
+    let Some(body) = state.receive(uri.path(), &body) else {
+        return respond(StatusCode::BAD_REQUEST, "request body is not JSON".to_string());
+    };
+
+    let (model, streaming, prompt) = match dialect {
+        // model and method are in the path, the history is in `contents`
+        Dialect::Gemini => {
+            let call = uri.path().rsplit('/').next().unwrap_or_default();
+            let (model, method) = call.split_once(':').unwrap_or((call, "generateContent"));
+            let prompt = body["contents"]
+                .as_array()
+                .and_then(|contents| contents.last())
+                .map(|content| text_of(&content["parts"]))
+                .unwrap_or_default();
+            (model.to_string(), method == "streamGenerateContent", prompt)
+        }
+        _ => {
+            let prompt = body["messages"]
+                .as_array()
+                .and_then(|messages| messages.last())
+                .map(|message| text_of(&message["content"]))
+                .unwrap_or_default();
+            let model = body["model"].as_str().unwrap_or("mock").to_string();
+            (model, body["stream"].as_bool().unwrap_or(matches!(dialect, Dialect::Ollama)), prompt)
+        }
+    };
+
+    let (reply, latency) = state.next_reply(&model, &prompt);
+    tokio::time::sleep(latency).await;
+    let chunk_delay = Duration::from_millis(state.config.lock().unwrap().chunk_delay_ms);
+    let usage = (words(&prompt).len().max(1), reply_tokens(&reply));
+
+    match (reply, dialect, streaming) {
+        (MockReply::Error { status, message, retry_after }, dialect, _) => error(status, &message, retry_after, dialect),
+        (MockReply::Malformed { body }, _, _) => respond(StatusCode::OK, body),
+        (reply, Dialect::OpenAi, false) => respond(StatusCode::OK, openai_completion(&model, &reply, usage).to_string()),
+        (reply, Dialect::OpenAi, true) => stream_body(openai_chunks(&model, &reply, usage), chunk_delay, "text/event-stream"),
+        (reply, Dialect::Ollama, false) => respond(StatusCode::OK, ollama_message(&model, &reply, true, usage).to_string()),
+        (reply, Dialect::Ollama, true) => stream_body(ollama_chunks(&model, &reply, usage), chunk_delay, "application/x-ndjson"),
+        (reply, Dialect::Anthropic, false) => respond(StatusCode::OK, anthropic_message(&model, &reply, usage).to_string()),
+        (reply, Dialect::Anthropic, true) => stream_body(anthropic_events(&model, &reply, usage), chunk_delay, "text/event-stream"),
+        (reply, Dialect::Gemini, false) => respond(StatusCode::OK, gemini_response(&model, &reply, Some("STOP"), usage).to_string()),
+        (reply, Dialect::Gemini, true) => stream_body(gemini_chunks(&model, &reply, usage), chunk_delay, "text/event-stream"),
+    }
+}
+
+// ollama `/api/generate`, never streamed. the returned context extends the
+// one sent with a token per word, so continuations can be told apart.
+async fn generate(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, body: Bytes) -> Response {
+    let Some(body) = state.receive(uri.path(), &body) else {
+        return respond(StatusCode::BAD_REQUEST, "request body is not JSON".to_string());
+    };
+    let model = body["model"].as_str().unwrap_or("mock").to_string();
+    let prompt = body["prompt"].as_str().unwrap_or_default().to_string();
+
+    let (reply, latency) = state.next_reply(&model, &prompt);
+    tokio::time::sleep(latency).await;
+    let content = match reply {
+        MockReply::Error { status, message, retry_after } => return error(status, &message, retry_after, Dialect::Ollama),
+        MockReply::Malformed { body } => return respond(StatusCode::OK, body),
+        MockReply::Text { content } => content,
+        MockReply::ToolCall { arguments, .. } => arguments.to_string(),
+    };
+    let mut context: Vec<Value> = body["context"].as_array().cloned().unwrap_or_default();
+    context.extend(words(&prompt).iter().chain(&words(&content)).map(|word| json!(word.len())));
+    respond(StatusCode::OK, json!({
+        "model": model,
+        "response": content,
+        "done": true,
+        "done_reason": "stop",
+        "context": context,
+        "prompt_eval_count": words(&prompt).len().max(1),
+        "eval_count": words(&content).len(),
+    }).to_string())
+}
+
+// message content is a plain string, or a list of content blocks (Anthropic) or parts (Gemini)
+fn text_of(content: &Value) -> String {
+    match content {
+        Value::String(text) => text.clone(),
+        Value::Array(blocks) => blocks
+            .iter()
+            .filter_map(|block| block["text"].as_str().or(block["content"].as_str()))
+            .collect::<Vec<_>>()
+            .join(" "),
+        _ => String::new(),
+    }
+}
+
+// ollama model management over `MockConfig::models`; names without a tag
+// mean `:latest`
+fn tagged(name: &str) -> String {
+    if name.contains(':') { name.to_string() } else { format!("{}:latest", name) }
+}
+
+fn mock_digest(name: &str) -> String {
+    let hash = name.bytes().fold(0u64, |hash, byte| hash.wrapping_mul(31).wrapping_add(byte as u64));
+    format!("{:016x}", hash)
+}
+
+fn model_details() -> Value {
+    json!({"format": "gguf", "family": "mock", "families": ["mock"], "parameter_size": "7B", "quantization_level": "Q4_0"})
+}
+
+async fn tags(State(state): State<Arc<MockState>>) -> Response {
+    let models: Vec<Value> = state.config.lock().unwrap().models
+        .iter()
+        .map(|name| json!({"name": tagged(name), "model": tagged(name), "size": 4_100_000_000u64, "digest": mock_digest(name), "details": model_details()}))
+        .collect();
+    respond(StatusCode::OK, json!({"models": models}).to_string())
+}
+
+// the model named by `model` (or the older `name`) in an admin request,
+// if the body parses
+fn admin_model(state: &MockState, uri: &axum::http::Uri, body: &[u8]) -> Option<(String, bool)> {
+    let body = state.receive(uri.path(), body)?;
+    let name = tagged(body["model"].as_str().or(body["name"].as_str())?);
+    let pulled = state.config.lock().unwrap().models.iter().any(|model| tagged(model) == name);
+    Some((name, pulled))
+}
+
+async fn show(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, body: Bytes) -> Response {
+    match admin_model(&state, &uri, &body) {
+        None => respond(StatusCode::BAD_REQUEST, json!({"error": "missing model"}).to_string()),
+        Some((name, false)) => error(404, &format!("model '{}' not found", name), None, Dialect::Ollama),
+        Some((_, true)) => respond(StatusCode::OK, json!({
+            "modelfile": "FROM mock",
+            "parameters": "",
+            "template": "{{ .Prompt }}",
+            "details": model_details(),
+            "model_info": {"mock.context_length": 8192},
+            "capabilities": ["completion", "tools"],
+        }).to_string()),
+    }
+}
+
+// pretends to download one layer in two steps. names starting with
+// "missing" fail the way an unknown model does.
+async fn pull(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, body: Bytes) -> Response {
+    let Some((name, pulled)) = admin_model(&state, &uri, &body) else {
+        return respond(StatusCode::BAD_REQUEST, json!({"error": "missing model"}).to_string());
+    };
+    if name.starts_with("missing") {
+        let chunks = [json!({"status": "pulling manifest"}), json!({"error": "pull model manifest: file does not exist"})];
+        return stream_body(chunks.iter().map(|chunk| format!("{}\n", chunk)).collect(), Duration::ZERO, "application/x-ndjson");
+    }
+    if !pulled {
+        state.config.lock().unwrap().models.push(name.clone());
+    }
+    let digest = format!("sha256:{}", mock_digest(&name));
+    let chunks = [
+        json!({"status": "pulling manifest"}),
+        json!({"status": format!("pulling {}", digest), "digest": digest, "total": 2048, "completed": 1024}),
+        json!({"status": format!("pulling {}", digest), "digest": digest, "total": 2048, "completed": 2048}),
+        json!({"status": "verifying sha256 digest"}),
+        json!({"status": "writing manifest"}),
+        json!({"status": "success"}),
+    ];
+    let chunk_delay = Duration::from_millis(state.config.lock().unwrap().chunk_delay_ms);
+    stream_body(chunks.iter().map(|chunk| format!("{}\n", chunk)).collect(), chunk_delay, "application/x-ndjson")
+}
+
+async fn remove(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, body: Bytes) -> Response {
+    match admin_model(&state, &uri, &body) {
+        None => respond(StatusCode::BAD_REQUEST, json!({"error": "missing model"}).to_string()),
+        Some((name, false)) => error(404, &format!("model '{}' not found", name), None, Dialect::Ollama),
+        Some((name, true)) => {
+            state.config.lock().unwrap().models.retain(|model| tagged(model) != name);
+            respond(StatusCode::OK, String::new())
+        }
+    }
+}
+
+// embeddings are derived from the input bytes, so equal inputs get equal
+// vectors and tests can rely on similarity ordering being stable
+async fn embed(State(state): State<Arc<MockState>>, OriginalUri(uri): OriginalUri, dialect: Dialect, body: Bytes) -> Response {
+    let Some(body) = state.receive(uri.path(), &body) else {
+        return respond(StatusCode::BAD_REQUEST, "request body is not JSON".to_string());
+    };
+
+    let model = body["model"].as_str().unwrap_or("mock-embed");
+    let dimensions = body["dimensions"].as_u64().unwrap_or(8) as usize;
+    let inputs: Vec<&str> = match &body["input"] {
+        Value::String(input) => vec![input.as_str()],
+        Value::Array(inputs) => inputs.iter().filter_map(Value::as_str).collect(),
+        _ => vec![],
+    };
+    let vectors: Vec<Vec<f32>> = inputs.iter().map(|input| mock_embedding(input, dimensions)).collect();
+    let tokens: usize = inputs.iter().map(|input| words(input).len()).sum();
+
+    let reply = match dialect {
+        Dialect::OpenAi => json!({
+            "object": "list",
+            "model": model,
+            "data": vectors.iter().enumerate().map(|(index, v)| json!({"object": "embedding", "index": index, "embedding": v})).collect::<Vec<_>>(),
+            "usage": {"prompt_tokens": tokens, "total_tokens": tokens},
+        }),
+        _ => json!({"model": model, "embeddings": vectors, "prompt_eval_count": tokens}),
+    };
+    respond(StatusCode::OK, reply.to_string())
+}
+
+fn mock_embedding(input: &str, dimensions: usize) -> Vec<f32> {
+    let dimensions = dimensions.max(1);
+    let mut vector = vec![0.0; dimensions];
+    for (i, byte) in input.bytes().enumerate() {
+        vector[(i + byte as usize) % dimensions] += 1.0;
+    }
+    vector
+}
+
+fn words(text: &str) -> Vec<String> {
+    text.split_inclusive(' ').map(str::to_string).collect()
+}
+
+fn reply_tokens(reply: &MockReply) -> usize {
+    match reply {
+        MockReply::Text { content } => words(content).len(),
+        MockReply::ToolCall { arguments, .. } => words(&arguments.to_string()).len(),
+        _ => 0,
+    }
+}
+
+fn respond(status: StatusCode, body: String) -> Response {
+    Response::builder()
+        .status(status)
+        .header(header::CONTENT_TYPE, "application/json")
+        .body(Body::from(body))
+        .unwrap()
+}
+
+fn error(status: u16, message: &str, retry_after: Option<u64>, dialect: Dialect) -> Response {
+    let body = match dialect {
+        Dialect::OpenAi => json!({"error": {"message": message, "type": "mock_error", "code": null}}),
+        Dialect::Ollama => json!({"error": message}),
+        Dialect::Anthropic => json!({"type": "error", "error": {"type": "mock_error", "message": message}}),
+        Dialect::Gemini => json!({"error": {"code": status, "message": message, "status": "MOCK_ERROR"}}),
+    };
+    let mut response = respond(StatusCode::from_u16(status).unwrap_or(StatusCode::INTERNAL_SERVER_ERROR), body.to_string());
+    if let Some(seconds) = retry_after {
+        response.headers_mut().insert(header::RETRY_AFTER, seconds.into());
+    }
+    response
+}
+
+fn stream_body(chunks: Vec<String>, delay: Duration, content_type: &str) -> Response {
+    let body = stream::iter(chunks).then(move |chunk| async move {
+        tokio::time::sleep(delay).await;
+        Ok::<_, Infallible>(chunk)
+    });
+    Response::builder()
+        .header(header::CONTENT_TYPE, content_type)
+        .body(Body::from_stream(body))
+        .unwrap()
+}
+
+fn openai_usage((prompt, completion): (usize, usize)) -> Value {
+    json!({"prompt_tokens": prompt, "completion_tokens": completion, "total_tokens": prompt + completion})
+}
+
+fn openai_completion(model: &str, reply: &MockReply, usage: (usize, usize)) -> Value {
+    let (message, finish_reason) = match reply {
+        MockReply::ToolCall { name, arguments } => (
+            json!({"role": "assistant", "content": null, "tool_calls": [{
+                "id": format!("call_mock_{}", name),
+                "type": "function",
+                "function": {"name": name, "arguments": arguments.to_string()}
+            }]}),
+            "tool_calls",
+        ),
+        MockReply::Text { content } => (json!({"role": "assistant", "content": content}), "stop"),
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    json!({
+        "id": "chatcmpl-mock",
+        "object": "chat.completion",
+        "model": model,
+        "choices": [{"index": 0, "message": message, "finish_reason": finish_reason}],
+        "usage": openai_usage(usage),
+    })
+}
+
+fn openai_chunks(model: &str, reply: &MockReply, usage: (usize, usize)) -> Vec<String> {
+    let chunk = |delta: Value, finish_reason: Value| {
+        let data = json!({"model": model, "choices": [{"index": 0, "delta": delta, "finish_reason": finish_reason}]});
+        format!("data: {}\n\n", data)
+    };
+
+    let mut chunks = vec![];
+    let finish_reason = match reply {
+        MockReply::Text { content } => {
+            chunks.push(chunk(json!({"role": "assistant", "content": ""}), Value::Null));
+            for word in words(content) {
+                chunks.push(chunk(json!({"content": word}), Value::Null));
+            }
+            "stop"
+        }
+        MockReply::ToolCall { name, arguments } => {
+            // like OpenAI: id and name first, then the arguments in pieces
+            let call = json!([{"index": 0, "id": format!("call_mock_{}", name), "type": "function", "function": {"name": name, "arguments": ""}}]);
+            chunks.push(chunk(json!({"role": "assistant", "tool_calls": call}), Value::Null));
+            for piece in words(&arguments.to_string()) {
+                chunks.push(chunk(json!({"tool_calls": [{"index": 0, "function": {"arguments": piece}}]}), Value::Null));
+            }
+            "tool_calls"
+        }
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    chunks.push(chunk(json!({}), json!(finish_reason)));
+    chunks.push(format!("data: {}\n\n", json!({"model": model, "choices": [], "usage": openai_usage(usage)})));
+    chunks.push("data: [DONE]\n\n".to_string());
+    chunks
+}
+
+fn ollama_message(model: &str, reply: &MockReply, done: bool, (prompt, completion): (usize, usize)) -> Value {
+    let message = match reply {
+        MockReply::Text { content } => json!({"role": "assistant", "content": content}),
+        MockReply::ToolCall { name, arguments } => json!({
+            "role": "assistant",
+            "content": "",
+            "tool_calls": [{"function": {"name": name, "arguments": arguments}}]
+        }),
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    let mut body = json!({"model": model, "message": message, "done": done});
+    if done {
+        body["done_reason"] = json!("stop");
+        body["prompt_eval_count"] = json!(prompt);
+        body["eval_count"] = json!(completion);
+    }
+    body
+}
+
+fn ollama_chunks(model: &str, reply: &MockReply, usage: (usize, usize)) -> Vec<String> {
+    let mut chunks: Vec<String> = match reply {
+        MockReply::Text { content } => words(content)
+            .into_iter()
+            .map(|word| format!("{}\n", ollama_message(model, &MockReply::text(word), false, usage)))
+            .collect(),
+        // ollama sends a tool call as one complete chunk
+        reply => vec![format!("{}\n", ollama_message(model, reply, false, usage))],
+    };
+    chunks.push(format!("{}\n", ollama_message(model, &MockReply::text(""), true, usage)));
+    chunks
+}
+
+fn anthropic_message(model: &str, reply: &MockReply, (prompt, completion): (usize, usize)) -> Value {
+    let (block, stop_reason) = match reply {
+        MockReply::Text { content } => (json!({"type": "text", "text": content}), "end_turn"),
+        MockReply::ToolCall { name, arguments } => (
+            json!({"type": "tool_use", "id": format!("toolu_mock_{}", name), "name": name, "input": arguments}),
+            "tool_use",
+        ),
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    json!({
+        "id": "msg_mock",
+        "type": "message",
+        "role": "assistant",
+        "model": model,
+        "content": [block],
+        "stop_reason": stop_reason,
+        "usage": {"input_tokens": prompt, "output_tokens": completion},
+    })
+}
+
+fn anthropic_events(model: &str, reply: &MockReply, (prompt, completion): (usize, usize)) -> Vec<String> {
+    let event = |data: Value| format!("event: {}\ndata: {}\n\n", data["type"].as_str().unwrap_or_default(), data);
+
+    let mut events = vec![event(json!({
+        "type": "message_start",
+        "message": {"id": "msg_mock", "type": "message", "role": "assistant", "model": model, "content": [], "usage": {"input_tokens": prompt, "output_tokens": 0}}
+    }))];
+    let stop_reason = match reply {
+        MockReply::Text { content } => {
+            events.push(event(json!({"type": "content_block_start", "index": 0, "content_block": {"type": "text", "text": ""}})));
+            for word in words(content) {
+                events.push(event(json!({"type": "content_block_delta", "index": 0, "delta": {"type": "text_delta", "text": word}})));
+            }
+            "end_turn"
+        }
+        MockReply::ToolCall { name, arguments } => {
+            let block = json!({"type": "tool_use", "id": format!("toolu_mock_{}", name), "name": name, "input": {}});
+            events.push(event(json!({"type": "content_block_start", "index": 0, "content_block": block})));
+            for piece in words(&arguments.to_string()) {
+                events.push(event(json!({"type": "content_block_delta", "index": 0, "delta": {"type": "input_json_delta", "partial_json": piece}})));
+            }
+            "tool_use"
+        }
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    events.push(event(json!({"type": "content_block_stop", "index": 0})));
+    events.push(event(json!({"type": "message_delta", "delta": {"stop_reason": stop_reason}, "usage": {"output_tokens": completion}})));
+    events.push(event(json!({"type": "message_stop"})));
+    events
+}
+
+fn gemini_response(model: &str, reply: &MockReply, finish_reason: Option<&str>, (prompt, completion): (usize, usize)) -> Value {
+    let part = match reply {
+        MockReply::Text { content } => json!({"text": content}),
+        MockReply::ToolCall { name, arguments } => json!({"functionCall": {"name": name, "args": arguments}}),
+        _ => unreachable!("errors are answered before rendering"),
+    };
+    let mut candidate = json!({"content": {"role": "model", "parts": [part]}, "index": 0});
+    let mut response = json!({"modelVersion": model});
+    if let Some(reason) = finish_reason {
+        candidate["finishReason"] = json!(reason);
+        candidate["safetyRatings"] = json!([{"category": "HARM_CATEGORY_HARASSMENT", "probability": "NEGLIGIBLE"}]);
+        response["usageMetadata"] = json!({
+            "promptTokenCount": prompt,
+            "candidatesTokenCount": completion,
+            "totalTokenCount": prompt + completion,
+        });
+    }
+    response["candidates"] = json!([candidate]);
+    response
+}
+
+fn gemini_chunks(model: &str, reply: &MockReply, usage: (usize, usize)) -> Vec<String> {
+    let chunk = |reply: &MockReply, finish_reason| format!("data: {}\n\n", gemini_response(model, reply, finish_reason, usage));
+    match reply {
+        MockReply::Text { content } => {
+            let mut words = words(content);
+            if words.is_empty() {
+                words.push(String::new());
+            }
+            let last = words.len() - 1;
+            words
+                .into_iter()
+                .enumerate()
+                .map(|(i, word)| chunk(&MockReply::text(word), (i == last).then_some("STOP")))
+                .collect()
+        }
+        // function calls arrive whole, in the final chunk
+        reply => vec![chunk(reply, Some("STOP"))],
+    }
+}
@@ -1,45 +1,1040 @@
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
-use crate::openai::ChatMessage;
+use serde_json::{Map, Value, json};
+use async_trait::async_trait;
+use futures::{Stream, StreamExt};
+use std::{collections::HashMap, pin::Pin, sync::Arc, time::Duration};
 
+use crate::client::{self, ChatCompletion, ChatMessage, ChatOptions, ContentPart, FileSource, LlmClient, ResponseFormat, Role, ToolCall, ToolDefinition, Usage};
+use crate::cancel::{self, CancellationToken};
+use crate::cassette::Cassette;
+use crate::embeddings::{self, EmbeddingClient, EmbeddingOptions, Embeddings};
+use crate::error::{LlmError, LlmResult};
+use crate::profiles;
+use crate::streaming::{self, ChatStream, StreamEvent};
+use crate::transport::HttpTransport;
+
+const DEFAULT_OLLAMA_HOST: &str = "http://localhost:11434";
+const DEFAULT_EMBEDDING_MODEL: &str = "nomic-embed-text";
+// ollama has no hard limit; this keeps single requests reasonably sized
+const MAX_EMBEDDING_INPUTS: usize = 256;
+
+// wire format of a message.
+// ollama sends tool arguments as a JSON object and has no tool call ids, so
+// replies to a tool are matched by function name via `tool_name`. images go
+// in `images` as bare base64, and there is no notion of documents.
+#[derive(Serialize, Deserialize)]
+struct OllamaMessage {
+    role: Role,
+    #[serde(default)]
+    content: String,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    images: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OllamaToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaToolCall {
+    function: OllamaFunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OllamaFunctionCall {
+    name: String,
+    #[serde(default)]
+    arguments: Value,
+}
+
+// the message text with text parts and text documents appended, plus the
+// base64 of every image
+fn content(message: &ChatMessage) -> LlmResult<(String, Vec<String>)> {
+    let mut text = message.content.clone();
+    let mut images = vec![];
+    let mut append = |more: &str| {
+        if !text.is_empty() {
+            text.push_str("\n\n");
+        }
+        text.push_str(more);
+    };
+    for part in &message.parts {
+        match part {
+            ContentPart::Text { text } => append(text),
+            ContentPart::Image { url } => match client::split_data_uri(url) {
+                Some((_, data)) => images.push(data.to_string()),
+                None => return Err(LlmError::Unsupported("ollama needs image bytes, not image URLs".into())),
+            },
+            ContentPart::File {
+                filename,
+                source: FileSource::Data(data),
+            } if let Some(document) = client::data_uri_text(data) => {
+                append(&format!("{}:\n{}", filename.as_deref().unwrap_or("document"), document));
+            }
+            ContentPart::File { .. } => {
+                return Err(LlmError::Unsupported("ollama only takes documents inline as text".into()));
+            }
+        }
+    }
+    Ok((text, images))
+}
+
+// converts the history, resolving `tool_call_id` back to the function name
+// of the assistant turn that requested it. a tool reply that answers no
+// earlier call is rejected rather than sent unattributed.
+fn to_wire(messages: &[ChatMessage]) -> LlmResult<Vec<OllamaMessage>> {
+    let mut names: HashMap<&str, &str> = HashMap::new();
+    messages
+        .iter()
+        .map(|message| {
+            for call in &message.tool_calls {
+                names.insert(&call.id, &call.name);
+            }
+            let tool_name = match (message.role, message.tool_call_id.as_deref()) {
+                (Role::Tool, None) => return Err(LlmError::InvalidRequest("tool message without a `tool_call_id`".into())),
+                (Role::Tool, Some(id)) => match names.get(id) {
+                    Some(name) => Some(name.to_string()),
+                    None => return Err(LlmError::InvalidRequest(format!("tool message answers `{}`, which no earlier assistant turn called", id))),
+                },
+                _ => None,
+            };
+            let (content, images) = content(message)?;
+            Ok(OllamaMessage {
+                role: message.role,
+                content,
+                images,
+                tool_calls: message
+                    .tool_calls
+                    .iter()
+                    .map(|call| OllamaToolCall {
+                        function: OllamaFunctionCall {
+                            name: call.name.clone(),
+                            arguments: call.arguments.clone(),
+                        },
+                    })
+                    .collect(),
+                tool_name,
+            })
+        })
+        .collect()
+}
+
+// ollama doesn't assign ids, so synthesize stable ones for pairing replies
+fn from_wire(message: OllamaMessage) -> ChatMessage {
+    ChatMessage {
+        role: message.role,
+        content: message.content,
+        parts: vec![],
+        tool_calls: message
+            .tool_calls
+            .into_iter()
+            .enumerate()
+            .map(|(index, call)| ToolCall {
+                id: format!("call_{}_{}", call.function.name, index),
+                name: call.function.name,
+                arguments: call.function.arguments,
+            })
+            .collect(),
+        tool_call_id: None,
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaTool<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: &'a ToolDefinition,
+}
+
+// `format` is either the string "json" or a JSON schema the output must match
+fn format(format: &ResponseFormat) -> Value {
+    match format {
+        ResponseFormat::Json => json!("json"),
+        ResponseFormat::JsonSchema { schema, .. } => schema.clone(),
+    }
+}
+
+#[derive(Serialize)]
+struct OllamaRequest<'a> {
+    model: &'a str,
+    messages: Vec<OllamaMessage>,
+    stream: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OllamaTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<KeepAlive>,
+}
+
+// model parameters, sent in the nested `options` object.
+// the config holds defaults; `temperature`, `max_tokens` and `stop` of a
+// request override the matching fields. a fixed `seed` with temperature 0
+// makes local runs reproducible.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct OllamaOptions {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub temperature: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_p: Option<f32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub top_k: Option<u32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub seed: Option<i64>,
+    // context window in tokens; ollama defaults to a small one
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_ctx: Option<u32>,
+    // maximum tokens to generate, -1 = until the model stops
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub num_predict: Option<i32>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub repeat_penalty: Option<f32>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub stop: Vec<String>,
+}
+
+impl OllamaOptions {
+    pub fn with_temperature(mut self, temperature: f32) -> Self {
+        self.temperature = Some(temperature);
+        self
+    }
+
+    pub fn with_top_p(mut self, top_p: f32) -> Self {
+        self.top_p = Some(top_p);
+        self
+    }
+
+    pub fn with_top_k(mut self, top_k: u32) -> Self {
+        self.top_k = Some(top_k);
+        self
+    }
+
+    pub fn with_seed(mut self, seed: i64) -> Self {
+        self.seed = Some(seed);
+        self
+    }
+
+    pub fn with_num_ctx(mut self, num_ctx: u32) -> Self {
+        self.num_ctx = Some(num_ctx);
+        self
+    }
+
+    pub fn with_num_predict(mut self, num_predict: i32) -> Self {
+        self.num_predict = Some(num_predict);
+        self
+    }
+
+    pub fn with_repeat_penalty(mut self, repeat_penalty: f32) -> Self {
+        self.repeat_penalty = Some(repeat_penalty);
+        self
+    }
+
+    pub fn with_stop(mut self, stop: impl Into<String>) -> Self {
+        self.stop.push(stop.into());
+        self
+    }
+
+    // the fields set in `over` replace the ones here
+    fn overlay(&self, over: &OllamaOptions) -> OllamaOptions {
+        OllamaOptions {
+            temperature: over.temperature.or(self.temperature),
+            top_p: over.top_p.or(self.top_p),
+            top_k: over.top_k.or(self.top_k),
+            seed: over.seed.or(self.seed),
+            num_ctx: over.num_ctx.or(self.num_ctx),
+            num_predict: over.num_predict.or(self.num_predict),
+            repeat_penalty: over.repeat_penalty.or(self.repeat_penalty),
+            stop: if over.stop.is_empty() { self.stop.clone() } else { over.stop.clone() },
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        *self == OllamaOptions::default()
+    }
+}
+
+// how long the model stays loaded after a request. ollama takes seconds,
+// with 0 unloading right away and any negative value keeping it forever,
+// so a fraction of a second is rounded up rather than unloading the model.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(from = "i64", into = "i64")]
+pub enum KeepAlive {
+    For(Duration),
+    Forever,
+}
+
+impl From<i64> for KeepAlive {
+    fn from(seconds: i64) -> Self {
+        match u64::try_from(seconds) {
+            Ok(seconds) => KeepAlive::For(Duration::from_secs(seconds)),
+            Err(_) => KeepAlive::Forever,
+        }
+    }
+}
+
+impl From<KeepAlive> for i64 {
+    fn from(keep_alive: KeepAlive) -> Self {
+        match keep_alive {
+            KeepAlive::For(duration) => {
+                let seconds = duration.as_secs() + u64::from(duration.subsec_nanos() > 0);
+                seconds.try_into().unwrap_or(i64::MAX)
+            }
+            KeepAlive::Forever => -1,
+        }
+    }
+}
+
+// `/api/generate`: a single prompt instead of a message history.
+// with `raw` the prompt is sent as is, without the model's template, for
+// completion-style prompting with a custom template. `context` from a
+// previous `Generation` continues that exchange.
+#[derive(Debug, Clone, Default)]
+pub struct GenerateRequest {
+    pub prompt: String,
+    // None = the configured model
+    pub model: Option<String>,
+    pub system: Option<String>,
+    // overrides the model's template (ignored with `raw`)
+    pub template: Option<String>,
+    pub raw: bool,
+    pub context: Vec<i64>,
+    pub response_format: Option<ResponseFormat>,
+    // overlaid on the configured options
+    pub options: OllamaOptions,
+    pub keep_alive: Option<KeepAlive>,
+    pub cancel: Option<CancellationToken>,
+}
+
+impl GenerateRequest {
+    pub fn new(prompt: impl Into<String>) -> Self {
+        GenerateRequest {
+            prompt: prompt.into(),
+            ..GenerateRequest::default()
+        }
+    }
+
+    pub fn raw(mut self) -> Self {
+        self.raw = true;
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_system(mut self, system: impl Into<String>) -> Self {
+        self.system = Some(system.into());
+        self
+    }
+
+    pub fn with_template(mut self, template: impl Into<String>) -> Self {
+        self.template = Some(template.into());
+        self
+    }
+
+    pub fn with_context(mut self, context: Vec<i64>) -> Self {
+        self.context = context;
+        self
+    }
+
+    pub fn with_response_format(mut self, response_format: ResponseFormat) -> Self {
+        self.response_format = Some(response_format);
+        self
+    }
+
+    pub fn with_options(mut self, options: OllamaOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+
+    pub fn with_cancellation(mut self, token: CancellationToken) -> Self {
+        self.cancel = Some(token);
+        self
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Generation {
+    pub model: String,
+    pub response: String,
+    pub done_reason: Option<String>,
+    // pass to `GenerateRequest::with_context` to continue from here
+    pub context: Vec<i64>,
+    pub usage: Option<Usage>,
+}
 
 #[derive(Serialize)]
-struct OllamaRequest {
+struct GenerateBody<'a> {
+    model: &'a str,
+    prompt: &'a str,
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    system: Option<&'a str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    template: Option<&'a str>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    raw: bool,
+    #[serde(skip_serializing_if = "<[i64]>::is_empty")]
+    context: &'a [i64],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    format: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    options: Option<OllamaOptions>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    keep_alive: Option<KeepAlive>,
+}
+
+#[derive(Deserialize)]
+struct GenerateResponse {
     model: String,
-    messages: Vec<ChatMessage>,
+    #[serde(default)]
+    response: String,
+    done_reason: Option<String>,
+    #[serde(default)]
+    context: Vec<i64>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
 }
 
-#[derive(Serialize, Deserialize)]
+// used both for the single non-streamed reply and for every NDJSON chunk of a
+// streamed one; the token counts only appear once `done` is true.
+#[derive(Deserialize)]
 struct OllamaResponse {
-    message: ChatMessage,
-}
-
-pub async fn send_to_ollama(prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let client = Client::new();
-
-    let messages = vec![
-        ChatMessage {
-            role: "system".to_string(),
-            content: "You are a code writing assistant".to_string(),
-        },
-        ChatMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        },
-    ];
-
-    let request = OllamaRequest {
-        model: "mistral".to_string(),
-        messages
-    };
+    model: String,
+    message: OllamaMessage,
+    #[serde(default)]
+    done: bool,
+    done_reason: Option<String>,
+    prompt_eval_count: Option<u32>,
+    eval_count: Option<u32>,
+}
+
+impl OllamaResponse {
+    fn usage(&self) -> Option<Usage> {
+        usage(self.prompt_eval_count, self.eval_count)
+    }
+}
+
+fn usage(prompt_eval_count: Option<u32>, eval_count: Option<u32>) -> Option<Usage> {
+    match (prompt_eval_count, eval_count) {
+        (None, None) => None,
+        (prompt, completion) => Some(Usage {
+            prompt_tokens: prompt.unwrap_or(0),
+            completion_tokens: completion.unwrap_or(0),
+        }),
+    }
+}
+
+// `/api/embed` (the batched successor of `/api/embeddings`)
+#[derive(Serialize)]
+struct EmbedRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+}
+
+#[derive(Deserialize)]
+struct EmbedResponse {
+    model: String,
+    embeddings: Vec<Vec<f32>>,
+    #[serde(default)]
+    prompt_eval_count: Option<u32>,
+}
+
+// model management (`/api/tags`, `/api/show`, `/api/pull`, `/api/delete`, `/api/ps`).
+// names without a tag refer to `:latest`, as on the ollama CLI.
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelDetails {
+    #[serde(default)]
+    pub format: Option<String>,
+    #[serde(default)]
+    pub family: Option<String>,
+    #[serde(default)]
+    pub families: Option<Vec<String>>,
+    // e.g. "7.2B"
+    #[serde(default)]
+    pub parameter_size: Option<String>,
+    // e.g. "Q4_0"
+    #[serde(default)]
+    pub quantization_level: Option<String>,
+    #[serde(default)]
+    pub parent_model: Option<String>,
+}
+
+// a model pulled to the local store
+#[derive(Debug, Clone, Deserialize)]
+pub struct LocalModel {
+    pub name: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub modified_at: Option<String>,
+    // bytes on disk
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+}
+
+// a model currently loaded into memory
+#[derive(Debug, Clone, Deserialize)]
+pub struct RunningModel {
+    pub name: String,
+    #[serde(default)]
+    pub model: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub size_vram: u64,
+    #[serde(default)]
+    pub digest: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+    // when the model gets unloaded unless it is used again
+    #[serde(default)]
+    pub expires_at: Option<String>,
+}
+
+#[derive(Debug, Clone, Default, Deserialize)]
+pub struct ModelInfo {
+    #[serde(default)]
+    pub modelfile: String,
+    #[serde(default)]
+    pub parameters: String,
+    #[serde(default)]
+    pub template: String,
+    #[serde(default)]
+    pub details: ModelDetails,
+    // architecture metadata, e.g. "llama.context_length"
+    #[serde(default)]
+    pub model_info: Map<String, Value>,
+    // e.g. ["completion", "tools", "vision"]
+    #[serde(default)]
+    pub capabilities: Vec<String>,
+}
+
+// one status line of a pull. layer downloads report `total` and `completed`
+// bytes; the last line has the status "success".
+#[derive(Debug, Clone, Deserialize)]
+pub struct PullProgress {
+    pub status: String,
+    #[serde(default)]
+    pub digest: Option<String>,
+    #[serde(default)]
+    pub total: Option<u64>,
+    #[serde(default)]
+    pub completed: Option<u64>,
+}
+
+impl PullProgress {
+    // share of the current layer downloaded, 0.0..=1.0
+    pub fn fraction(&self) -> Option<f64> {
+        match (self.completed, self.total) {
+            (Some(completed), Some(total)) if total > 0 => Some(completed as f64 / total as f64),
+            _ => None,
+        }
+    }
+}
+
+pub type PullStream = Pin<Box<dyn Stream<Item = LlmResult<PullProgress>> + Send>>;
+
+#[derive(Deserialize)]
+struct TagsResponse {
+    #[serde(default)]
+    models: Vec<LocalModel>,
+}
+
+#[derive(Deserialize)]
+struct PsResponse {
+    #[serde(default)]
+    models: Vec<RunningModel>,
+}
+
+#[derive(Deserialize)]
+struct VersionResponse {
+    version: String,
+}
+
+// pull failures arrive as a line of the 200 response, not as a status
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum PullLine {
+    Failed { error: String },
+    Progress(PullProgress),
+}
+
+// a failed pull is mostly a name the registry doesn't know, which retrying
+// won't fix; only a failure to reach the registry is worth another try
+fn pull_error(error: String) -> LlmError {
+    const NETWORK: &[&str] = &["dial tcp", "no such host", "connection", "timeout", "timed out", "unexpected EOF"];
+    if NETWORK.iter().any(|hint| error.contains(hint)) {
+        LlmError::Unavailable { retry_after: None, message: format!("pull failed: {}", error) }
+    } else {
+        LlmError::InvalidRequest(format!("pull failed: {}", error))
+    }
+}
+
+fn with_tag(name: &str) -> String {
+    match name.rsplit_once(':') {
+        // a colon may also belong to a registry port, e.g. host:5000/model
+        Some((_, tag)) if !tag.contains('/') => name.to_string(),
+        _ => format!("{}:latest", name),
+    }
+}
+
+// like the ollama CLI, a host may leave out the scheme, as in the documented
+// OLLAMA_HOST=0.0.0.0:11434
+pub(crate) fn host_url(host: &str) -> String {
+    if host.is_empty() || host.contains("://") {
+        host.to_string()
+    } else {
+        format!("http://{}", host)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct OllamaConfig {
+    pub host: String,
+    pub model: String,
+    pub timeout: Duration,
+    // let `ensure_model` pull the model when it is missing
+    pub pull_missing: bool,
+    // defaults for every request
+    pub options: OllamaOptions,
+    // None = the server's default (5 minutes)
+    pub keep_alive: Option<KeepAlive>,
+}
+
+impl OllamaConfig {
+    pub fn new(model: impl Into<String>) -> Self {
+        OllamaConfig {
+            host: DEFAULT_OLLAMA_HOST.to_string(),
+            model: model.into(),
+            timeout: Duration::from_secs(120),
+            pull_missing: false,
+            options: OllamaOptions::default(),
+            keep_alive: None,
+        }
+    }
+
+    pub fn with_host(mut self, host: impl Into<String>) -> Self {
+        self.host = host_url(&host.into());
+        self
+    }
+
+    pub fn with_pull_missing(mut self, pull_missing: bool) -> Self {
+        self.pull_missing = pull_missing;
+        self
+    }
+
+    pub fn with_options(mut self, options: OllamaOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn with_keep_alive(mut self, keep_alive: KeepAlive) -> Self {
+        self.keep_alive = Some(keep_alive);
+        self
+    }
+}
+
+pub struct OllamaClient {
+    config: OllamaConfig,
+    http: HttpTransport,
+}
+
+impl OllamaClient {
+    pub fn new(config: OllamaConfig) -> LlmResult<Self> {
+        let http = HttpTransport::new(config.timeout)?;
+        Ok(OllamaClient { config, http })
+    }
+
+    // records to or replays from `cassette` instead of the LLM_CASSETTE one
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.http = self.http.with_cassette(cassette);
+        self
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!("{}{}", self.config.host.trim_end_matches('/'), path)
+    }
+
+    fn request<'a>(&'a self, messages: &'a [ChatMessage], options: &'a ChatOptions, stream: bool) -> LlmResult<OllamaRequest<'a>> {
+        let defaults = match &options.ollama_options {
+            Some(request_options) => self.config.options.overlay(request_options),
+            None => self.config.options.clone(),
+        };
+        let model_options = defaults.overlay(&OllamaOptions {
+            temperature: options.temperature,
+            num_predict: options.max_tokens.map(|max_tokens| max_tokens.try_into().unwrap_or(i32::MAX)),
+            stop: options.stop.clone(),
+            ..OllamaOptions::default()
+        });
+
+        Ok(OllamaRequest {
+            model: options.model.as_deref().unwrap_or(&self.config.model),
+            messages: to_wire(messages)?,
+            stream,
+            tools: options
+                .tools
+                .iter()
+                .map(|function| OllamaTool { kind: "function", function })
+                .collect(),
+            format: options.response_format.as_ref().map(format),
+            options: (!model_options.is_empty()).then_some(model_options),
+            keep_alive: options.keep_alive.or(self.config.keep_alive),
+        })
+    }
+
+    async fn post(&self, body: &OllamaRequest<'_>) -> LlmResult<reqwest::Response> {
+        let request = self.http
+            .client()
+            .post(self.url("/api/chat"))
+            .json(body);
+        if body.stream {
+            self.send_streaming(request).await
+        } else {
+            self.send(request).await
+        }
+    }
+
+    // one completion from `/api/generate`, without streaming
+    pub async fn generate(&self, request: &GenerateRequest) -> LlmResult<Generation> {
+        let options = self.config.options.overlay(&request.options);
+        let body = GenerateBody {
+            model: request.model.as_deref().unwrap_or(&self.config.model),
+            prompt: &request.prompt,
+            stream: false,
+            system: request.system.as_deref(),
+            template: request.template.as_deref(),
+            raw: request.raw,
+            context: &request.context,
+            format: request.response_format.as_ref().map(format),
+            options: (!options.is_empty()).then_some(options),
+            keep_alive: request.keep_alive.or(self.config.keep_alive),
+        };
+        let http = self.http
+            .client()
+            .post(self.url("/api/generate"))
+            .json(&body);
+        let response = cancel::cancellable(request.cancel.as_ref(), async {
+            Ok(self.send(http).await?.json::<GenerateResponse>().await?)
+        })
+        .await?;
 
-    let response = client
-        .post("http://localhost:11434/api/chat")
-        .json(&request)
-        .send()
-        .await?
-        .json::<OllamaResponse>()
+        Ok(Generation {
+            usage: usage(response.prompt_eval_count, response.eval_count),
+            model: response.model,
+            response: response.response,
+            done_reason: response.done_reason,
+            context: response.context,
+        })
+    }
+
+    async fn send(&self, request: reqwest::RequestBuilder) -> LlmResult<reqwest::Response> {
+        self.http.send(request).await.map_err(|e| self.unreachable(e))
+    }
+
+    async fn send_streaming(&self, request: reqwest::RequestBuilder) -> LlmResult<reqwest::Response> {
+        self.http.send_streaming(request).await.map_err(|e| self.unreachable(e))
+    }
+
+    // a refused connection almost always means the daemon is not running,
+    // so say that instead of surfacing the raw socket error
+    fn unreachable(&self, error: LlmError) -> LlmError {
+        match error {
+            LlmError::Transport(e) if e.is_connect() => LlmError::Unavailable {
+                retry_after: None,
+                message: format!("ollama is not reachable at {} (is `ollama serve` running?): {}", self.config.host, e),
+            },
+            e => e,
+        }
+    }
+
+    // the daemon's version; doubles as a health probe
+    pub async fn version(&self) -> LlmResult<String> {
+        let request = self.http.client().get(self.url("/api/version"));
+        Ok(self.send(request).await?.json::<VersionResponse>().await?.version)
+    }
+
+    pub async fn list_models(&self) -> LlmResult<Vec<LocalModel>> {
+        let request = self.http.client().get(self.url("/api/tags"));
+        Ok(self.send(request).await?.json::<TagsResponse>().await?.models)
+    }
+
+    pub async fn has_model(&self, name: &str) -> LlmResult<bool> {
+        let name = with_tag(name);
+        Ok(self.list_models().await?.iter().any(|model| with_tag(&model.name) == name))
+    }
+
+    pub async fn show_model(&self, name: &str) -> LlmResult<ModelInfo> {
+        let request = self.http
+            .client()
+            .post(self.url("/api/show"))
+            .json(&json!({ "model": name }));
+        Ok(self.send(request).await?.json::<ModelInfo>().await?)
+    }
+
+    // models loaded into memory right now
+    pub async fn running_models(&self) -> LlmResult<Vec<RunningModel>> {
+        let request = self.http.client().get(self.url("/api/ps"));
+        Ok(self.send(request).await?.json::<PsResponse>().await?.models)
+    }
+
+    // downloads a model, streaming progress as ollama reports it. the pull
+    // keeps going server side if the stream is dropped.
+    pub async fn pull_model(&self, name: &str) -> LlmResult<PullStream> {
+        let request = self.http
+            .client()
+            .post(self.url("/api/pull"))
+            .json(&json!({ "model": name, "stream": true }));
+        // pulls of large models easily outlast the request timeout
+        let response = self.send_streaming(request).await?;
+        let progress = streaming::lines(response.bytes_stream())
+            .filter(|line| futures::future::ready(!matches!(line, Ok(line) if line.is_empty())))
+            .map(|line| match serde_json::from_str::<PullLine>(&line?)? {
+                PullLine::Failed { error } => Err(pull_error(error)),
+                PullLine::Progress(progress) => Ok(progress),
+            });
+        Ok(Box::pin(progress))
+    }
+
+    pub async fn delete_model(&self, name: &str) -> LlmResult<()> {
+        let request = self.http
+            .client()
+            .delete(self.url("/api/delete"))
+            .json(&json!({ "model": name }));
+        self.send(request).await?;
+        Ok(())
+    }
+
+    // startup check: the daemon answers and the configured model is pulled.
+    // a missing model is an error naming the fix, or gets pulled when
+    // `pull_missing` is set.
+    pub async fn ensure_model(&self) -> LlmResult<()> {
+        let model = &self.config.model;
+        let version = self.version().await?;
+        if self.has_model(model).await? {
+            tracing::debug!(target: "llm::ollama", %version, %model, "model is available");
+            return Ok(());
+        }
+        if !self.config.pull_missing {
+            return Err(LlmError::Config(format!(
+                "ollama at {} has no model `{}`; run `ollama pull {}` or enable `pull_missing`",
+                self.config.host, model, model
+            )));
+        }
+
+        tracing::info!(target: "llm::ollama", %version, %model, "pulling missing model");
+        let mut progress = self.pull_model(model).await?;
+        let mut status = String::new();
+        while let Some(update) = progress.next().await {
+            let update = update?;
+            if update.status != status {
+                tracing::info!(target: "llm::ollama", %model, status = %update.status, "pull progress");
+                status = update.status;
+            }
+        }
+        if status != "success" {
+            return Err(LlmError::MalformedResponse(format!("pull of `{}` ended without success (last status: {:?})", model, status)));
+        }
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl LlmClient for OllamaClient {
+    fn provider(&self) -> &'static str {
+        "ollama"
+    }
+
+    fn default_model(&self) -> &str {
+        &self.config.model
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        Some(&self.config.host)
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.config.options.temperature
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let request = self.request(messages, options, false)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), async {
+            Ok(self.post(&request).await?.json::<OllamaResponse>().await?)
+        })
         .await?;
 
-    Ok(response.message.content)
-}
\ No newline at end of file
+        let usage = response.usage();
+        Ok(ChatCompletion {
+            message: from_wire(response.message),
+            model: response.model,
+            finish_reason: response.done_reason,
+            usage,
+        })
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let request = self.request(messages, options, true)?;
+        let response = cancel::cancellable(options.cancel.as_ref(), self.post(&request)).await?;
+        let lines = streaming::lines(response.bytes_stream());
+
+        let events = streaming::events(lines, |line| {
+            if line.is_empty() {
+                return Ok(vec![]);
+            }
+
+            // newline-delimited JSON: one `OllamaResponse` per line.
+            // tool calls are never split across chunks on ollama.
+            let chunk: OllamaResponse = serde_json::from_str(line)?;
+            let usage = chunk.usage();
+            let message = from_wire(chunk.message);
+            let mut events = vec![];
+            if !message.content.is_empty() {
+                events.push(StreamEvent::Delta(message.content));
+            }
+            if !message.tool_calls.is_empty() {
+                events.push(StreamEvent::ToolCalls(message.tool_calls));
+            }
+            if chunk.done {
+                events.push(StreamEvent::Done {
+                    finish_reason: chunk.done_reason,
+                    usage,
+                    model: Some(chunk.model),
+                });
+            }
+            Ok(events)
+        });
+        Ok(cancel::guard(events, options.cancel.clone()))
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for OllamaClient {
+    fn embedding_model(&self) -> &str {
+        DEFAULT_EMBEDDING_MODEL
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_EMBEDDING_INPUTS
+    }
+
+    async fn embed_batch(&self, inputs: &[String], options: &EmbeddingOptions) -> LlmResult<Embeddings> {
+        let body = EmbedRequest {
+            model: options.model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL),
+            input: inputs,
+            dimensions: options.dimensions,
+        };
+        let request = self.http
+            .client()
+            .post(self.url("/api/embed"))
+            .json(&body);
+        let response = self.send(request).await?.json::<EmbedResponse>().await?;
+
+        Ok(Embeddings {
+            dimensions: embeddings::dimensions_of(&response.embeddings)?,
+            vectors: response.embeddings,
+            model: response.model,
+            usage: response.prompt_eval_count.map(|prompt_tokens| Usage {
+                prompt_tokens,
+                completion_tokens: 0,
+            }),
+        })
+    }
+}
+
+// kept as a thin convenience wrapper over the "ollama" profile (see `profiles`).
+pub async fn send_to_ollama(prompt: &str) -> LlmResult<String> {
+    profiles::send_prompt("ollama", "send_to_ollama", prompt, &ChatOptions::default()).await
+}
+
+// like `send_to_ollama`, but gives up with `LlmError::Cancelled` as soon as
+// `cancel` is cancelled, e.g. when the calling agent shuts down
+pub async fn send_to_ollama_cancellable(prompt: &str, cancel: CancellationToken) -> LlmResult<String> {
+    let options = ChatOptions::default().with_cancellation(cancel);
+    profiles::send_prompt("ollama", "send_to_ollama", prompt, &options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn keep_alive_rounds_sub_second_durations_up() {
+        assert_eq!(i64::from(KeepAlive::For(Duration::from_millis(500))), 1);
+        assert_eq!(i64::from(KeepAlive::For(Duration::from_millis(1500))), 2);
+        assert_eq!(i64::from(KeepAlive::For(Duration::ZERO)), 0);
+        assert_eq!(i64::from(KeepAlive::Forever), -1);
+    }
+
+    #[test]
+    fn chat_options_map_to_model_options() {
+        let client = OllamaClient::new(OllamaConfig::new("mistral")).unwrap();
+        let body = serde_json::to_value(client.request(&[ChatMessage::user("hi")], &ChatOptions::default(), false).unwrap()).unwrap();
+        assert_eq!(body, json!({"model": "mistral", "messages": [{"role": "user", "content": "hi"}], "stream": false}));
+
+        let options = ChatOptions::default().with_model("llama3.1").with_max_tokens(64).with_stop("###");
+        let body = serde_json::to_value(client.request(&[ChatMessage::user("hi")], &options, true).unwrap()).unwrap();
+        assert_eq!(body["model"], "llama3.1");
+        assert_eq!(body["stream"], true);
+        assert_eq!(body["options"], json!({"num_predict": 64, "stop": ["###"]}));
+    }
+
+    #[test]
+    fn request_options_override_the_config() {
+        let client = OllamaClient::new(
+            OllamaConfig::new("mistral")
+                .with_options(OllamaOptions::default().with_seed(1).with_top_k(40))
+                .with_keep_alive(KeepAlive::For(Duration::from_secs(600))),
+        )
+        .unwrap();
+        let options = ChatOptions::default()
+            .with_ollama_options(OllamaOptions::default().with_seed(7).with_temperature(0.9))
+            .with_keep_alive(KeepAlive::Forever)
+            .with_temperature(0.0);
+        let body = serde_json::to_value(client.request(&[ChatMessage::user("hi")], &options, false).unwrap()).unwrap();
+        assert_eq!(body["options"], json!({ "seed": 7, "top_k": 40, "temperature": 0.0 }));
+        assert_eq!(body["keep_alive"], json!(-1));
+    }
+
+    #[test]
+    fn tool_replies_are_attributed_to_their_call() {
+        let reply = OllamaMessage {
+            role: Role::Assistant,
+            content: String::new(),
+            images: vec![],
+            tool_calls: ["get_weather", "get_time"]
+                .into_iter()
+                .map(|name| OllamaToolCall {
+                    function: OllamaFunctionCall { name: name.into(), arguments: json!({"city": "Paris"}) },
+                })
+                .collect(),
+            tool_name: None,
+        };
+        let asked = from_wire(reply);
+        let ids: Vec<_> = asked.tool_calls.iter().map(|call| call.id.as_str()).collect();
+        assert_eq!(ids, ["call_get_weather_0", "call_get_time_1"]);
+
+        let wire = to_wire(&[asked.clone(), ChatMessage::tool("call_get_time_1", "noon"), ChatMessage::tool("call_get_weather_0", "sunny")]).unwrap();
+        assert_eq!(wire[1].tool_name.as_deref(), Some("get_time"));
+        assert_eq!(wire[2].tool_name.as_deref(), Some("get_weather"));
+
+        for orphan in [ChatMessage::tool("call_unknown_0", "?"), ChatMessage { tool_call_id: None, ..ChatMessage::tool("", "?") }] {
+            assert!(matches!(to_wire(&[asked.clone(), orphan]), Err(LlmError::InvalidRequest(_))));
+        }
+    }
+}
@@ -1,67 +1,844 @@
 use serde::{Deserialize, Serialize};
-use reqwest::Client;
-use std::env;
+use serde_json::{Value, json};
+use async_trait::async_trait;
+use std::{collections::{BTreeMap, HashMap}, env, sync::Arc, time::Duration};
 
+pub use crate::client::ChatMessage;
+use crate::client::{ChatCompletion, ChatOptions, ContentPart, FileSource, LlmClient, Role, ResponseFormat, ToolCall, ToolChoice, ToolDefinition, Usage};
+use crate::cancel::{self, CancellationToken};
+use crate::cassette::Cassette;
+use crate::embeddings::{self, EmbeddingClient, EmbeddingOptions, Embeddings};
+use crate::error::{LlmError, LlmResult};
+use crate::profiles;
+use crate::streaming::{self, ChatStream, StreamEvent};
+use crate::transport::HttpTransport;
+
+const OPENAI_BASE_URL: &str = "https://api.openai.com/v1";
+const AZURE_API_VERSION: &str = "2024-10-21";
+const DEFAULT_EMBEDDING_MODEL: &str = "text-embedding-3-small";
+// OpenAI rejects requests with more inputs than this
+const MAX_EMBEDDING_INPUTS: usize = 2048;
+
+// wire format of a message.
+// OpenAI sends `content: null` on tool-calling turns and encodes function
+// arguments as a JSON string, so it can't reuse `ChatMessage` directly.
+#[derive(Serialize, Deserialize)]
+struct OpenAiMessage {
+    role: Role,
+    #[serde(default)]
+    content: Option<OpenAiContent>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    tool_calls: Vec<OpenAiToolCall>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    tool_call_id: Option<String>,
+}
+
+// a plain string, or an array of parts once images or files are attached
 #[derive(Serialize, Deserialize)]
-pub struct ChatMessage {
-    pub role: String,
-    pub content: String,
+#[serde(untagged)]
+enum OpenAiContent {
+    Text(String),
+    Parts(Vec<OpenAiPart>),
+}
+
+#[derive(Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+enum OpenAiPart {
+    Text {
+        text: String,
+    },
+    ImageUrl {
+        image_url: ImageUrl,
+    },
+    File {
+        file: OpenAiFile,
+    },
+    // audio, refusals, ... are not mapped onto `ChatMessage`
+    #[serde(other)]
+    Other,
+}
+
+#[derive(Serialize, Deserialize)]
+struct ImageUrl {
+    url: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiFile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    filename: Option<String>,
+    // a `data:` URI
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    file_data: Option<String>,
+}
+
+impl From<&ContentPart> for OpenAiPart {
+    fn from(part: &ContentPart) -> Self {
+        match part {
+            ContentPart::Text { text } => OpenAiPart::Text { text: text.clone() },
+            ContentPart::Image { url } => OpenAiPart::ImageUrl {
+                image_url: ImageUrl { url: url.clone() },
+            },
+            ContentPart::File { filename, source } => OpenAiPart::File {
+                file: match source {
+                    FileSource::Id(id) => OpenAiFile {
+                        file_id: Some(id.clone()),
+                        filename: None,
+                        file_data: None,
+                    },
+                    FileSource::Data(data) => OpenAiFile {
+                        file_id: None,
+                        filename: filename.clone(),
+                        file_data: Some(data.clone()),
+                    },
+                },
+            },
+        }
+    }
+}
+
+fn content(message: &ChatMessage) -> OpenAiContent {
+    if message.parts.is_empty() {
+        return OpenAiContent::Text(message.content.clone());
+    }
+    let text = (!message.content.is_empty()).then(|| OpenAiPart::Text { text: message.content.clone() });
+    OpenAiContent::Parts(text.into_iter().chain(message.parts.iter().map(OpenAiPart::from)).collect())
+}
+
+#[derive(Serialize, Deserialize)]
+struct OpenAiToolCall {
+    id: String,
+    #[serde(rename = "type")]
+    kind: String,
+    function: FunctionCall,
+}
+
+#[derive(Serialize, Deserialize)]
+struct FunctionCall {
+    name: String,
+    arguments: String,
+}
+
+impl From<&ChatMessage> for OpenAiMessage {
+    fn from(message: &ChatMessage) -> Self {
+        let tool_calls: Vec<OpenAiToolCall> = message
+            .tool_calls
+            .iter()
+            .map(|call| OpenAiToolCall {
+                id: call.id.clone(),
+                kind: "function".into(),
+                function: FunctionCall {
+                    name: call.name.clone(),
+                    arguments: call.arguments.to_string(),
+                },
+            })
+            .collect();
+
+        OpenAiMessage {
+            role: message.role,
+            content: (!message.content.is_empty() || !message.parts.is_empty() || tool_calls.is_empty()).then(|| content(message)),
+            tool_calls,
+            tool_call_id: message.tool_call_id.clone(),
+        }
+    }
+}
+
+impl From<OpenAiMessage> for ChatMessage {
+    fn from(message: OpenAiMessage) -> Self {
+        ChatMessage {
+            role: message.role,
+            content: match message.content {
+                Some(OpenAiContent::Text(text)) => text,
+                Some(OpenAiContent::Parts(parts)) => parts
+                    .into_iter()
+                    .filter_map(|part| match part {
+                        OpenAiPart::Text { text } => Some(text),
+                        _ => None,
+                    })
+                    .collect(),
+                None => String::new(),
+            },
+            parts: vec![],
+            tool_calls: message
+                .tool_calls
+                .into_iter()
+                .map(|call| ToolCall {
+                    id: call.id,
+                    name: call.function.name,
+                    arguments: parse_arguments(&call.function.arguments),
+                })
+                .collect(),
+            tool_call_id: message.tool_call_id,
+        }
+    }
+}
+
+// models occasionally emit arguments that are not valid JSON; keep the raw
+// string so the tool can report a useful error instead of failing the call.
+fn parse_arguments(arguments: &str) -> Value {
+    serde_json::from_str(arguments).unwrap_or_else(|_| Value::String(arguments.to_string()))
 }
 
 #[derive(Serialize)]
-struct ChatRequest {
-    model: String,
-    messages: Vec<ChatMessage>,
+struct OpenAiTool<'a> {
+    #[serde(rename = "type")]
+    kind: &'static str,
+    function: &'a ToolDefinition,
+}
+
+fn tool_choice(choice: &ToolChoice) -> Value {
+    match choice {
+        ToolChoice::Auto => json!("auto"),
+        ToolChoice::None => json!("none"),
+        ToolChoice::Required => json!("required"),
+        ToolChoice::Tool(name) => json!({"type": "function", "function": {"name": name}}),
+    }
+}
+
+fn response_format(format: &ResponseFormat) -> Value {
+    match format {
+        ResponseFormat::Json => json!({"type": "json_object"}),
+        ResponseFormat::JsonSchema { name, schema } => json!({
+            "type": "json_schema",
+            "json_schema": {"name": name, "schema": schema}
+        }),
+    }
+}
+
+#[derive(Serialize)]
+struct ChatRequest<'a> {
+    model: &'a str,
+    messages: Vec<OpenAiMessage>,
     temperature: f32,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    max_tokens: Option<u32>,
+    #[serde(skip_serializing_if = "<[String]>::is_empty")]
+    stop: &'a [String],
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    tools: Vec<OpenAiTool<'a>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tool_choice: Option<Value>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    response_format: Option<Value>,
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    stream: bool,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    stream_options: Option<StreamOptions>,
+}
+
+// asks OpenAI to append a final chunk with the token usage to the stream
+#[derive(Serialize)]
+struct StreamOptions {
+    include_usage: bool,
 }
 
 #[derive(Deserialize)]
 struct Choice {
-    message: ChatMessage
+    message: OpenAiMessage,
+    finish_reason: Option<String>,
 }
 
 #[derive(Deserialize)]
 struct ChatResponse {
-    choices: Vec<Choice>
+    model: String,
+    choices: Vec<Choice>,
+    usage: Option<OpenAiUsage>,
 }
 
-// implementation of the function that sends the request.
+#[derive(Deserialize)]
+struct OpenAiUsage {
+    prompt_tokens: u32,
+    completion_tokens: u32,
+}
+
+impl From<OpenAiUsage> for Usage {
+    fn from(usage: OpenAiUsage) -> Self {
+        Usage {
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+        }
+    }
+}
+
+// one `data:` payload of a streamed response
+#[derive(Deserialize)]
+struct ChatChunk {
+    #[serde(default)]
+    choices: Vec<ChunkChoice>,
+    usage: Option<OpenAiUsage>,
+    model: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ChunkChoice {
+    // Azure sends choices that only carry content filter results
+    #[serde(default)]
+    delta: Delta,
+    finish_reason: Option<String>,
+}
+
+#[derive(Deserialize, Default)]
+struct Delta {
+    content: Option<String>,
+    #[serde(default)]
+    tool_calls: Vec<ToolCallDelta>,
+}
+
+// a fragment of a streamed tool call; `index` ties fragments together and only
+// the first fragment carries the id and function name.
+#[derive(Deserialize)]
+struct ToolCallDelta {
+    index: usize,
+    id: Option<String>,
+    function: Option<FunctionDelta>,
+}
+
+#[derive(Deserialize)]
+struct FunctionDelta {
+    name: Option<String>,
+    arguments: Option<String>,
+}
+
+#[derive(Default)]
+struct PartialToolCall {
+    id: String,
+    name: String,
+    arguments: String,
+}
+
+#[derive(Serialize)]
+struct EmbeddingRequest<'a> {
+    model: &'a str,
+    input: &'a [String],
+    #[serde(skip_serializing_if = "Option::is_none")]
+    dimensions: Option<u32>,
+    encoding_format: &'static str,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingResponse {
+    data: Vec<EmbeddingData>,
+    model: String,
+    #[serde(default)]
+    usage: Option<EmbeddingUsage>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingData {
+    index: usize,
+    embedding: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct EmbeddingUsage {
+    prompt_tokens: u32,
+}
+
+// how the API key is sent
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum AuthScheme {
+    // `Authorization: Bearer <key>`
+    Bearer,
+    // the key as the value of a custom header, e.g. `api-key` or `x-api-key`
+    Header(String),
+    // local servers (llama.cpp, LM Studio, ...) usually need no key at all
+    None,
+}
+
+// Azure OpenAI serves each model from a named deployment:
+//   {endpoint}/openai/deployments/{deployment}/chat/completions?api-version=...
+#[derive(Debug, Clone)]
+pub struct AzureSettings {
+    pub api_version: String,
+}
+
+// everything needed to build an OpenAI client.
 // ensure you load the API key from .env file or secure secret manager.
-pub async fn send_to_openai(prompt: &str) -> Result<String, Box<dyn std::error::Error>> {
-    let api_key = env::var("OPENAI_API_KEY")?;
-    let client = Client::new();
+#[derive(Debug, Clone)]
+pub struct OpenAiConfig {
+    pub api_key: String,
+    pub model: String,
+    pub temperature: f32,
+    pub timeout: Duration,
+    // everything before `/chat/completions` or `/embeddings`, e.g. a local mock server
+    pub base_url: String,
+    pub auth: AuthScheme,
+    // sent with every request, e.g. an organization or routing header
+    pub headers: Vec<(String, String)>,
+    // logical model names mapped to the names the server knows them by
+    pub model_aliases: HashMap<String, String>,
+    // reported by `LlmClient::provider`, so caches and token counters can tell
+    // OpenAI itself apart from compatible servers
+    pub provider: &'static str,
+    // set for Azure OpenAI; `model_aliases` then map models to deployments
+    pub azure: Option<AzureSettings>,
+}
 
-    let messages = vec![
-        ChatMessage {
-            role: "system".to_string(),
-            content: "You are a helpful assistant".to_string(),
-        },
-        ChatMessage {
-            role: "user".to_string(),
-            content: prompt.to_string(),
-        },
-    ];
-
-    let request_body = ChatRequest {
-        model: "gpt-4".to_string(),
-        messages,
-        temperature: 0.7,
-    };
-
-    let response = client
-        .post("https://api.openai.com/v1/chat/completions")
-        .bearer_auth(api_key)
-        .json(&request_body)
-        .send()
-        .await?
-        .json::<ChatResponse>()
+impl OpenAiConfig {
+    pub fn new(api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        OpenAiConfig {
+            api_key: api_key.into(),
+            model: model.into(),
+            temperature: 0.7,
+            timeout: Duration::from_secs(60),
+            base_url: OPENAI_BASE_URL.to_string(),
+            auth: AuthScheme::Bearer,
+            headers: vec![],
+            model_aliases: HashMap::new(),
+            provider: "openai",
+            azure: None,
+        }
+    }
+
+    // any server that speaks the OpenAI chat dialect (vLLM, llama.cpp server,
+    // LM Studio, LocalAI, ...), e.g. `compatible("http://localhost:8000/v1", "qwen2.5")`
+    pub fn compatible(base_url: impl Into<String>, model: impl Into<String>) -> Self {
+        OpenAiConfig {
+            auth: AuthScheme::None,
+            provider: "openai-compatible",
+            ..Self::new("", model).with_base_url(base_url)
+        }
+    }
+
+    // an Azure OpenAI resource, e.g. `azure("https://my-resource.openai.azure.com", key, "gpt-4o")`.
+    // without a `with_deployment` mapping the model name is used as the deployment name.
+    pub fn azure(endpoint: impl Into<String>, api_key: impl Into<String>, model: impl Into<String>) -> Self {
+        OpenAiConfig {
+            provider: "azure-openai",
+            azure: Some(AzureSettings {
+                api_version: AZURE_API_VERSION.to_string(),
+            }),
+            ..Self::new("", model)
+                .with_base_url(endpoint)
+                .with_api_key(api_key, AuthScheme::Header("api-key".into()))
+        }
+    }
+
+    // reads AZURE_OPENAI_ENDPOINT, AZURE_OPENAI_API_KEY and optionally
+    // AZURE_OPENAI_API_VERSION from the environment (or a .env file)
+    pub fn azure_from_env(model: impl Into<String>) -> Result<Self, env::VarError> {
+        dotenv::dotenv().ok();
+        let config = Self::azure(env::var("AZURE_OPENAI_ENDPOINT")?, env::var("AZURE_OPENAI_API_KEY")?, model);
+        Ok(match env::var("AZURE_OPENAI_API_VERSION") {
+            Ok(version) => config.with_api_version(version),
+            Err(_) => config,
+        })
+    }
+
+    pub fn with_deployment(self, model: impl Into<String>, deployment: impl Into<String>) -> Self {
+        self.with_alias(model, deployment)
+    }
+
+    pub fn with_api_version(mut self, api_version: impl Into<String>) -> Self {
+        self.azure = Some(AzureSettings {
+            api_version: api_version.into(),
+        });
+        self
+    }
+
+    pub fn with_base_url(mut self, base_url: impl Into<String>) -> Self {
+        self.base_url = base_url.into();
+        self
+    }
+
+    pub fn with_api_key(mut self, api_key: impl Into<String>, auth: AuthScheme) -> Self {
+        self.api_key = api_key.into();
+        self.auth = auth;
+        self
+    }
+
+    pub fn with_header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.push((name.into(), value.into()));
+        self
+    }
+
+    pub fn with_alias(mut self, alias: impl Into<String>, model: impl Into<String>) -> Self {
+        self.model_aliases.insert(alias.into(), model.into());
+        self
+    }
+
+    // the name sent to the server for `model`
+    pub fn resolve_model<'a>(&'a self, model: &'a str) -> &'a str {
+        self.model_aliases.get(model).map(String::as_str).unwrap_or(model)
+    }
+
+    // reads OPENAI_API_KEY (and OPENAI_BASE_URL, if set) from the environment (or a .env file)
+    pub fn from_env(model: impl Into<String>) -> Result<Self, env::VarError> {
+        dotenv::dotenv().ok();
+        let api_key = env::var("OPENAI_API_KEY")?;
+        let config = Self::new(api_key, model);
+        Ok(match env::var("OPENAI_BASE_URL") {
+            Ok(base_url) => config.with_base_url(base_url),
+            Err(_) => config,
+        })
+    }
+}
+
+pub struct OpenAiClient {
+    config: OpenAiConfig,
+    http: HttpTransport,
+}
+
+impl OpenAiClient {
+    pub fn new(config: OpenAiConfig) -> LlmResult<Self> {
+        let http = HttpTransport::new(config.timeout)?;
+        Ok(OpenAiClient { config, http })
+    }
+
+    // records to or replays from `cassette` instead of the LLM_CASSETTE one
+    pub fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.http = self.http.with_cassette(cassette);
+        self
+    }
+
+    fn request<'a>(&'a self, messages: &'a [ChatMessage], options: &'a ChatOptions, stream: bool) -> ChatRequest<'a> {
+        ChatRequest {
+            model: self.config.resolve_model(options.model.as_deref().unwrap_or(&self.config.model)),
+            messages: messages.iter().map(OpenAiMessage::from).collect(),
+            temperature: options.temperature.unwrap_or(self.config.temperature),
+            max_tokens: options.max_tokens,
+            stop: &options.stop,
+            tools: options
+                .tools
+                .iter()
+                .map(|function| OpenAiTool { kind: "function", function })
+                .collect(),
+            tool_choice: options.tool_choice.as_ref().map(tool_choice),
+            response_format: options.response_format.as_ref().map(response_format),
+            stream,
+            stream_options: stream.then_some(StreamOptions { include_usage: true }),
+        }
+    }
+
+    async fn post(&self, body: &ChatRequest<'_>) -> LlmResult<reqwest::Response> {
+        let request = self.http
+            .client()
+            .post(self.url("/chat/completions", body.model))
+            .json(body);
+        let request = self.authorize(request);
+        if body.stream {
+            self.http.send_streaming(request).await
+        } else {
+            self.http.send(request).await
+        }
+    }
+
+    fn authorize(&self, request: reqwest::RequestBuilder) -> reqwest::RequestBuilder {
+        let request = match &self.config.auth {
+            AuthScheme::Bearer => request.bearer_auth(&self.config.api_key),
+            AuthScheme::Header(name) => request.header(name, &self.config.api_key),
+            AuthScheme::None => request,
+        };
+        self.config
+            .headers
+            .iter()
+            .fold(request, |request, (name, value)| request.header(name, value))
+    }
+
+    // `model` is already resolved, so on Azure it is the deployment name
+    fn url(&self, path: &str, model: &str) -> String {
+        let base_url = self.config.base_url.trim_end_matches('/');
+        match &self.config.azure {
+            Some(azure) => format!("{}/openai/deployments/{}{}?api-version={}", base_url, model, path, azure.api_version),
+            None => format!("{}{}", base_url, path),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for OpenAiClient {
+    fn provider(&self) -> &'static str {
+        self.config.provider
+    }
+
+    fn default_model(&self) -> &str {
+        &self.config.model
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        Some(&self.config.base_url)
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        Some(self.config.temperature)
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let request_body = self.request(messages, options, false);
+        let response = cancel::cancellable(options.cancel.as_ref(), async {
+            Ok(self.post(&request_body).await?.json::<ChatResponse>().await?)
+        })
         .await?;
 
-    let reply = response
-        .choices
-        .first()
-        .map(|c| c.message.content.clone())
-        .unwrap_or_else(|| "<no reply>".to_string());
+        let choice = response
+            .choices
+            .into_iter()
+            .next()
+            .ok_or(LlmError::EmptyChoices)?;
+
+        // a filtered reply still comes back as 200, just without content
+        let message = ChatMessage::from(choice.message);
+        if choice.finish_reason.as_deref() == Some("content_filter") && message.content.is_empty() {
+            return Err(LlmError::ContentFiltered("reply was blocked by the content filter".into()));
+        }
+
+        Ok(ChatCompletion {
+            message,
+            model: response.model,
+            finish_reason: choice.finish_reason,
+            usage: response.usage.map(Usage::from),
+        })
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let request_body = self.request(messages, options, true);
+        let response = cancel::cancellable(options.cancel.as_ref(), self.post(&request_body)).await?;
+
+        let mut finish_reason = None;
+        let mut usage = None;
+        let mut model = None;
+        let mut tool_calls: BTreeMap<usize, PartialToolCall> = BTreeMap::new();
+        let lines = streaming::lines(response.bytes_stream());
+
+        let events = streaming::events(lines, move |line| {
+            // server-sent events: only `data:` lines carry payloads
+            let Some(data) = line.strip_prefix("data:").map(str::trim) else {
+                return Ok(vec![]);
+            };
+            if data == "[DONE]" {
+                let mut events = vec![];
+                if !tool_calls.is_empty() {
+                    let calls = std::mem::take(&mut tool_calls)
+                        .into_values()
+                        .map(|call| ToolCall {
+                            id: call.id,
+                            name: call.name,
+                            arguments: parse_arguments(&call.arguments),
+                        })
+                        .collect();
+                    events.push(StreamEvent::ToolCalls(calls));
+                }
+                events.push(StreamEvent::Done {
+                    finish_reason: finish_reason.take(),
+                    usage: usage.take(),
+                    model: model.take(),
+                });
+                return Ok(events);
+            }
+
+            let chunk: ChatChunk = serde_json::from_str(data)?;
+            if let Some(chunk_usage) = chunk.usage {
+                usage = Some(Usage::from(chunk_usage));
+            }
+            // Azure's content filter chunks leave the model empty
+            if let Some(chunk_model) = chunk.model.filter(|m| !m.is_empty()) {
+                model = Some(chunk_model);
+            }
+
+            let mut events = vec![];
+            for choice in chunk.choices {
+                if choice.finish_reason.is_some() {
+                    finish_reason = choice.finish_reason;
+                }
+                if let Some(content) = choice.delta.content.filter(|c| !c.is_empty()) {
+                    events.push(StreamEvent::Delta(content));
+                }
+                for fragment in choice.delta.tool_calls {
+                    let call = tool_calls.entry(fragment.index).or_default();
+                    if let Some(id) = fragment.id {
+                        call.id = id;
+                    }
+                    if let Some(function) = fragment.function {
+                        call.name.push_str(function.name.as_deref().unwrap_or_default());
+                        call.arguments.push_str(function.arguments.as_deref().unwrap_or_default());
+                    }
+                }
+            }
+            Ok(events)
+        });
+        Ok(cancel::guard(events, options.cancel.clone()))
+    }
+}
+
+#[async_trait]
+impl EmbeddingClient for OpenAiClient {
+    fn embedding_model(&self) -> &str {
+        DEFAULT_EMBEDDING_MODEL
+    }
+
+    fn max_batch_size(&self) -> usize {
+        MAX_EMBEDDING_INPUTS
+    }
+
+    async fn embed_batch(&self, inputs: &[String], options: &EmbeddingOptions) -> LlmResult<Embeddings> {
+        let body = EmbeddingRequest {
+            model: self.config.resolve_model(options.model.as_deref().unwrap_or(DEFAULT_EMBEDDING_MODEL)),
+            input: inputs,
+            dimensions: options.dimensions,
+            encoding_format: "float",
+        };
+        let request = self.http
+            .client()
+            .post(self.url("/embeddings", body.model))
+            .json(&body);
+        let mut response = self.http.send(self.authorize(request)).await?.json::<EmbeddingResponse>().await?;
+
+        // `data` is documented to be in input order, but carries an index anyway
+        response.data.sort_by_key(|d| d.index);
+        let vectors: Vec<Vec<f32>> = response.data.into_iter().map(|d| d.embedding).collect();
+        Ok(Embeddings {
+            dimensions: embeddings::dimensions_of(&vectors)?,
+            vectors,
+            model: response.model,
+            usage: response.usage.map(|u| Usage {
+                prompt_tokens: u.prompt_tokens,
+                completion_tokens: 0,
+            }),
+        })
+    }
+}
+
+// implementation of the function that sends the request.
+// kept as a thin convenience wrapper over the "openai" profile (see `profiles`).
+pub async fn send_to_openai(prompt: &str) -> LlmResult<String> {
+    profiles::send_prompt("openai", "send_to_openai", prompt, &ChatOptions::default()).await
+}
+
+// like `send_to_openai`, but gives up with `LlmError::Cancelled` as soon as
+// `cancel` is cancelled, e.g. when the calling agent shuts down
+pub async fn send_to_openai_cancellable(prompt: &str, cancel: CancellationToken) -> LlmResult<String> {
+    let options = ChatOptions::default().with_cancellation(cancel);
+    profiles::send_prompt("openai", "send_to_openai", prompt, &options).await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn client(config: OpenAiConfig) -> OpenAiClient {
+        OpenAiClient::new(config).unwrap()
+    }
+
+    #[test]
+    fn unset_options_fall_back_to_the_config() {
+        let client = client(OpenAiConfig::new("sk-test", "gpt-4o"));
+        let body = serde_json::to_value(client.request(&[ChatMessage::user("hi")], &ChatOptions::default(), false)).unwrap();
+        assert_eq!(
+            body,
+            json!({"model": "gpt-4o", "messages": [{"role": "user", "content": "hi"}], "temperature": 0.7_f32})
+        );
+    }
+
+    #[test]
+    fn request_options_override_the_config() {
+        let client = client(OpenAiConfig::new("sk-test", "gpt-4o").with_alias("fast", "gpt-4o-mini"));
+        let options = ChatOptions::default()
+            .with_model("fast")
+            .with_temperature(0.0)
+            .with_max_tokens(256)
+            .with_stop("\n\n")
+            .with_tool_choice(ToolChoice::Tool("get_weather".into()));
+        let body = serde_json::to_value(client.request(&[ChatMessage::system("be brief")], &options, true)).unwrap();
+
+        assert_eq!(body["model"], "gpt-4o-mini");
+        assert_eq!(body["temperature"], 0.0);
+        assert_eq!(body["max_tokens"], 256);
+        assert_eq!(body["stop"], json!(["\n\n"]));
+        assert_eq!(body["tool_choice"], json!({"type": "function", "function": {"name": "get_weather"}}));
+        assert_eq!(body["stream_options"], json!({"include_usage": true}));
+    }
+
+    #[test]
+    fn tool_calls_round_trip_through_the_wire_format() {
+        let asked = ChatMessage {
+            tool_calls: vec![ToolCall { id: "call_1".into(), name: "get_weather".into(), arguments: json!({"city": "Paris"}) }],
+            ..ChatMessage::assistant("")
+        };
+        let wire = serde_json::to_value(OpenAiMessage::from(&asked)).unwrap();
+        // a call without text sends null content rather than ""
+        assert_eq!(
+            wire,
+            json!({"role": "assistant", "content": null, "tool_calls": [{"id": "call_1", "type": "function", "function": {"name": "get_weather", "arguments": "{\"city\":\"Paris\"}"}}]})
+        );
+
+        let back = ChatMessage::from(serde_json::from_value::<OpenAiMessage>(wire).unwrap());
+        assert_eq!(back.tool_calls, asked.tool_calls);
+
+        let reply = serde_json::to_value(OpenAiMessage::from(&ChatMessage::tool("call_1", "sunny"))).unwrap();
+        assert_eq!(reply, json!({"role": "tool", "content": "sunny", "tool_call_id": "call_1"}));
+    }
 
-    Ok(reply)
-}
\ No newline at end of file
+    #[test]
+    fn attachments_turn_the_content_into_parts() {
+        let message = ChatMessage::user("what is this?")
+            .with_part(ContentPart::image_url("https://example.com/cat.png"))
+            .with_part(ContentPart::file_id("file-123"))
+            .with_part(ContentPart::file_bytes("notes.txt", b"hi", "text/plain"));
+        let wire = serde_json::to_value(OpenAiMessage::from(&message)).unwrap();
+        assert_eq!(
+            wire["content"],
+            json!([
+                {"type": "text", "text": "what is this?"},
+                {"type": "image_url", "image_url": {"url": "https://example.com/cat.png"}},
+                {"type": "file", "file": {"file_id": "file-123"}},
+                {"type": "file", "file": {"filename": "notes.txt", "file_data": "data:text/plain;base64,aGk="}},
+            ])
+        );
+    }
+
+    #[test]
+    fn unparseable_arguments_are_kept_verbatim() {
+        assert_eq!(parse_arguments("{\"city\": \"Paris\"}"), json!({"city": "Paris"}));
+        assert_eq!(parse_arguments("{city: Paris"), json!("{city: Paris"));
+    }
+
+    // the headers `authorize` puts on a request
+    fn headers(client: &OpenAiClient) -> reqwest::header::HeaderMap {
+        let request = client.http.client().get("http://localhost/");
+        client.authorize(request).build().unwrap().headers().clone()
+    }
+
+    #[test]
+    fn compatible_servers_need_no_key() {
+        let local = client(OpenAiConfig::compatible("http://localhost:8000/v1/", "qwen2.5").with_header("x-team", "agents"));
+        assert_eq!(local.provider(), "openai-compatible");
+        assert_eq!(local.url("/chat/completions", "qwen2.5"), "http://localhost:8000/v1/chat/completions");
+
+        let sent = headers(&local);
+        assert!(sent.get("authorization").is_none());
+        assert_eq!(sent["x-team"], "agents");
+
+        let keyed = client(OpenAiConfig::compatible("http://localhost:8000/v1", "qwen2.5").with_api_key("secret", AuthScheme::Bearer));
+        assert_eq!(headers(&keyed)["authorization"], "Bearer secret");
+    }
+
+    #[test]
+    fn azure_routes_models_to_deployments() {
+        let azure = client(
+            OpenAiConfig::azure("https://res.openai.azure.com/", "azure-key", "gpt-4o")
+                .with_deployment("gpt-4o", "chat-prod")
+                .with_api_version("2025-01-01-preview"),
+        );
+        let (messages, options) = ([ChatMessage::user("hi")], ChatOptions::default());
+        let body = azure.request(&messages, &options, false);
+        assert_eq!(body.model, "chat-prod");
+        assert_eq!(
+            azure.url("/chat/completions", body.model),
+            "https://res.openai.azure.com/openai/deployments/chat-prod/chat/completions?api-version=2025-01-01-preview"
+        );
+        assert_eq!(azure.provider(), "azure-openai");
+
+        let sent = headers(&azure);
+        assert_eq!(sent["api-key"], "azure-key");
+        assert!(sent.get("authorization").is_none());
+
+        // without a mapping the model name is the deployment
+        let unmapped = client(OpenAiConfig::azure("https://res.openai.azure.com", "azure-key", "gpt-4o-mini"));
+        assert_eq!(
+            unmapped.url("/embeddings", unmapped.config.resolve_model("gpt-4o-mini")),
+            format!("https://res.openai.azure.com/openai/deployments/gpt-4o-mini/embeddings?api-version={}", AZURE_API_VERSION)
+        );
+    }
+}
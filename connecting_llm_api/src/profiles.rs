@@ -0,0 +1,663 @@
+use async_trait::async_trait;
+use serde::Deserialize;
+use serde_json::Value;
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    env, fmt, fs, io,
+    path::{Path, PathBuf},
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+
+use crate::anthropic::{AnthropicClient, AnthropicConfig};
+use crate::cassette::{Cassette, CassetteMode};
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient, Role};
+use crate::error::{LlmError, LlmResult};
+use crate::gemini::{GeminiClient, GeminiConfig};
+use crate::ollama::{self, KeepAlive, OllamaClient, OllamaConfig, OllamaOptions};
+use crate::openai::{AuthScheme, OpenAiClient, OpenAiConfig};
+use crate::rate_limit::{RateLimitedClient, RateLimiter};
+use crate::retry::{RetryPolicy, RetryingClient};
+use crate::streaming::ChatStream;
+use crate::usage::{MeteredClient, UsageTracker};
+
+// named provider profiles loaded from a TOML or YAML file, so models,
+// endpoints and credentials are configuration instead of code:
+//
+//   default = "fast"
+//
+//   [profiles.fast]
+//   provider = "openai"
+//   model = "gpt-4o-mini"
+//   api_key = "${OPENAI_API_KEY}"
+//   temperature = 0.2
+//   system_prompt = "You are a helpful assistant"
+//
+//   [profiles.local]
+//   provider = "ollama"
+//   model = "llama3.1"
+//   base_url = "${OLLAMA_HOST:-http://localhost:11434}"
+//
+// every string may reference environment variables (and .env entries) as
+// `${VAR}` or `${VAR:-fallback}`; `$$` is a literal `$`. a variable that is not
+// set only fails when a client is built from the profile that uses it, so one
+// file can hold profiles for providers you have no key for. likewise a value
+// taken from the environment that turns out invalid (say a malformed
+// OPENAI_BASE_URL) only fails its own profile.
+
+// the profiles `send_to_openai` and `send_to_ollama` fall back to
+const BUILTIN_PROFILES: &str = r#"
+default = "openai"
+
+[profiles.openai]
+provider = "openai"
+model = "gpt-4"
+api_key_env = "OPENAI_API_KEY"
+base_url = "${OPENAI_BASE_URL:-https://api.openai.com/v1}"
+system_prompt = "You are a helpful assistant"
+
+[profiles.ollama]
+provider = "ollama"
+model = "mistral"
+base_url = "${OLLAMA_HOST:-http://localhost:11434}"
+system_prompt = "You are a code writing assistant"
+"#;
+
+// files looked for in the working directory when LLM_PROFILES is not set
+const PROFILE_FILES: &[&str] = &["llm.toml", "llm.yaml", "llm.yml"];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+pub enum ProviderKind {
+    #[serde(rename = "openai")]
+    OpenAi,
+    #[serde(rename = "openai-compatible")]
+    OpenAiCompatible,
+    #[serde(rename = "azure-openai")]
+    AzureOpenAi,
+    #[serde(rename = "anthropic")]
+    Anthropic,
+    #[serde(rename = "gemini")]
+    Gemini,
+    #[serde(rename = "ollama")]
+    Ollama,
+}
+
+impl ProviderKind {
+    // as written in profile files
+    pub fn name(self) -> &'static str {
+        match self {
+            ProviderKind::OpenAi => "openai",
+            ProviderKind::OpenAiCompatible => "openai-compatible",
+            ProviderKind::AzureOpenAi => "azure-openai",
+            ProviderKind::Anthropic => "anthropic",
+            ProviderKind::Gemini => "gemini",
+            ProviderKind::Ollama => "ollama",
+        }
+    }
+
+    fn is_openai(self) -> bool {
+        matches!(self, ProviderKind::OpenAi | ProviderKind::OpenAiCompatible | ProviderKind::AzureOpenAi)
+    }
+
+    fn needs_key(self) -> bool {
+        !matches!(self, ProviderKind::OpenAiCompatible | ProviderKind::Ollama)
+    }
+
+    fn needs_base_url(self) -> bool {
+        matches!(self, ProviderKind::OpenAiCompatible | ProviderKind::AzureOpenAi)
+    }
+}
+
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub provider: ProviderKind,
+    pub model: String,
+    // the server, or the resource endpoint for Azure; None = the provider's default
+    pub base_url: Option<String>,
+    // the key itself, usually as "${SOME_VAR}"
+    pub api_key: Option<String>,
+    // or the name of the environment variable holding it
+    pub api_key_env: Option<String>,
+    // openai providers: send the key in this header instead of as a bearer token
+    pub api_key_header: Option<String>,
+    // azure-openai only
+    pub api_version: Option<String>,
+    // openai providers: extra headers sent with every request
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    // openai providers: model names mapped to server names (Azure deployments)
+    #[serde(default)]
+    pub aliases: HashMap<String, String>,
+    pub timeout_secs: Option<u64>,
+    // total attempts for retryable errors; None = no retries
+    pub max_attempts: Option<u32>,
+    // defaults for requests that don't set their own
+    pub temperature: Option<f32>,
+    pub max_tokens: Option<u32>,
+    // prepended to conversations that have no system message
+    pub system_prompt: Option<String>,
+    // ollama only: model options such as `seed`, `num_ctx` and `top_p`
+    pub options: Option<OllamaOptions>,
+    // ollama only: seconds the model stays loaded, -1 = forever
+    pub keep_alive: Option<KeepAlive>,
+    // variables referenced by this profile that are not set
+    #[serde(skip)]
+    unresolved: Vec<String>,
+    // problems with values taken from the environment, reported when a
+    // client is built from this profile
+    #[serde(skip)]
+    invalid: Vec<String>,
+}
+
+impl Profile {
+    // every problem with the profile, not just the first one, as (field, message)
+    fn problems(&self, name: &str) -> Vec<(&'static str, String)> {
+        let mut problems = vec![];
+        let mut problem = |field: &'static str, message: String| problems.push((field, format!("profile `{}`: {}", name, message)));
+
+        if self.model.trim().is_empty() {
+            problem("model", "`model` is empty".into());
+        }
+        if self.api_key.is_some() && self.api_key_env.is_some() {
+            problem("api_key", "set either `api_key` or `api_key_env`, not both".into());
+        }
+        if self.provider.needs_key() && self.api_key.is_none() && self.api_key_env.is_none() {
+            problem("api_key", format!("{} needs `api_key` or `api_key_env`", self.provider.name()));
+        }
+        if self.provider.needs_base_url() && self.base_url.is_none() {
+            problem("base_url", format!("{} needs `base_url`", self.provider.name()));
+        }
+        if let Some(url) = self.base_url.as_deref().filter(|url| !url.is_empty())
+            && !matches!(reqwest::Url::parse(url), Ok(parsed) if matches!(parsed.scheme(), "http" | "https"))
+        {
+            problem("base_url", format!("`base_url` {:?} is not an http(s) URL", url));
+        }
+        if !self.provider.is_openai() {
+            for (field, set) in [
+                ("api_key_header", self.api_key_header.is_some()),
+                ("headers", !self.headers.is_empty()),
+                ("aliases", !self.aliases.is_empty()),
+            ] {
+                if set {
+                    problem(field, format!("`{}` is only supported by openai providers", field));
+                }
+            }
+        }
+        if self.provider != ProviderKind::Ollama {
+            for (field, set) in [("options", self.options.is_some()), ("keep_alive", self.keep_alive.is_some())] {
+                if set {
+                    problem(field, format!("`{}` is only supported by ollama", field));
+                }
+            }
+        }
+        if self.api_version.is_some() && self.provider != ProviderKind::AzureOpenAi {
+            problem("api_version", "`api_version` is only supported by azure-openai".into());
+        }
+        if self.timeout_secs == Some(0) {
+            problem("timeout_secs", "`timeout_secs` must be positive".into());
+        }
+        if self.max_attempts == Some(0) {
+            problem("max_attempts", "`max_attempts` must be at least 1".into());
+        }
+        if let Some(temperature) = self.temperature
+            && !(0.0..=2.0).contains(&temperature)
+        {
+            problem("temperature", format!("`temperature` {} is outside 0.0..=2.0", temperature));
+        }
+        problems
+    }
+
+    fn api_key(&self) -> String {
+        let from_env = self.api_key_env.as_ref().and_then(|name| env::var(name).ok());
+        self.api_key.clone().or(from_env).unwrap_or_default()
+    }
+
+    fn timeout(&self, default: Duration) -> Duration {
+        self.timeout_secs.map(Duration::from_secs).unwrap_or(default)
+    }
+
+    fn openai_config(&self, config: OpenAiConfig) -> OpenAiConfig {
+        let mut config = self.headers.iter().fold(config, |config, (name, value)| config.with_header(name, value));
+        config.model_aliases.extend(self.aliases.clone());
+        config.timeout = self.timeout(config.timeout);
+        if let Some(version) = &self.api_version {
+            config = config.with_api_version(version);
+        }
+        let key = self.api_key();
+        match &self.api_key_header {
+            Some(header) => config.with_api_key(key, AuthScheme::Header(header.clone())),
+            None if !key.is_empty() && config.auth == AuthScheme::None => config.with_api_key(key, AuthScheme::Bearer),
+            None => config,
+        }
+    }
+
+    fn provider_client(&self) -> LlmResult<Arc<dyn LlmClient>> {
+        let base_url = self.base_url.clone().filter(|url| !url.is_empty());
+        Ok(match self.provider {
+            ProviderKind::OpenAi => {
+                let config = OpenAiConfig::new(self.api_key(), &self.model);
+                let config = match base_url {
+                    Some(url) => config.with_base_url(url),
+                    None => config,
+                };
+                Arc::new(OpenAiClient::new(self.openai_config(config))?)
+            }
+            ProviderKind::OpenAiCompatible => {
+                let config = OpenAiConfig::compatible(base_url.unwrap_or_default(), &self.model);
+                Arc::new(OpenAiClient::new(self.openai_config(config))?)
+            }
+            ProviderKind::AzureOpenAi => {
+                let config = OpenAiConfig::azure(base_url.unwrap_or_default(), self.api_key(), &self.model);
+                Arc::new(OpenAiClient::new(self.openai_config(config))?)
+            }
+            ProviderKind::Anthropic => {
+                let mut config = AnthropicConfig::new(self.api_key(), &self.model);
+                if let Some(url) = base_url {
+                    config = config.with_base_url(url);
+                }
+                if let Some(max_tokens) = self.max_tokens {
+                    config.max_tokens = max_tokens;
+                }
+                config.timeout = self.timeout(config.timeout);
+                Arc::new(AnthropicClient::new(config)?)
+            }
+            ProviderKind::Gemini => {
+                let mut config = GeminiConfig::new(self.api_key(), &self.model);
+                if let Some(url) = base_url {
+                    config = config.with_base_url(url);
+                }
+                config.timeout = self.timeout(config.timeout);
+                Arc::new(GeminiClient::new(config)?)
+            }
+            ProviderKind::Ollama => {
+                let mut config = OllamaConfig::new(&self.model);
+                if let Some(url) = base_url {
+                    config = config.with_host(url);
+                }
+                config.timeout = self.timeout(config.timeout);
+                config.options = self.options.clone().unwrap_or_default();
+                config.keep_alive = self.keep_alive;
+                Arc::new(OllamaClient::new(config)?)
+            }
+        })
+    }
+}
+
+#[derive(Debug)]
+pub enum ProfileError {
+    Io { path: PathBuf, source: io::Error },
+    // not valid TOML/YAML, or not the expected shape
+    Parse(String),
+    // every validation problem found in the file
+    Invalid(Vec<String>),
+    UnknownProfile(String),
+    // the profile references environment variables that are not set
+    UnresolvedEnv { profile: String, variables: Vec<String> },
+}
+
+impl fmt::Display for ProfileError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ProfileError::Io { path, source } => write!(f, "cannot read {}: {}", path.display(), source),
+            ProfileError::Parse(message) => write!(f, "cannot parse profiles: {}", message),
+            ProfileError::Invalid(problems) => write!(f, "invalid profiles: {}", problems.join("; ")),
+            ProfileError::UnknownProfile(name) => write!(f, "no profile named `{}`", name),
+            ProfileError::UnresolvedEnv { profile, variables } => {
+                write!(f, "profile `{}` needs unset environment variables: {}", profile, variables.join(", "))
+            }
+        }
+    }
+}
+
+impl std::error::Error for ProfileError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            ProfileError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+impl From<ProfileError> for LlmError {
+    fn from(error: ProfileError) -> Self {
+        LlmError::Config(error.to_string())
+    }
+}
+
+// profiles are decoded one by one so errors can name the profile
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ProfilesFile {
+    default: Option<String>,
+    #[serde(default)]
+    profiles: BTreeMap<String, Value>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Profiles {
+    default: Option<String>,
+    profiles: BTreeMap<String, Profile>,
+}
+
+impl Profiles {
+    pub fn from_toml_str(source: &str) -> Result<Self, ProfileError> {
+        let raw: Value = toml::from_str(source).map_err(|e| ProfileError::Parse(e.to_string()))?;
+        Self::from_value(raw)
+    }
+
+    pub fn from_yaml_str(source: &str) -> Result<Self, ProfileError> {
+        let raw: Value = serde_yaml::from_str(source).map_err(|e| ProfileError::Parse(e.to_string()))?;
+        Self::from_value(raw)
+    }
+
+    // the format follows the extension: .toml, .yaml or .yml
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self, ProfileError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| ProfileError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some("toml") => Self::from_toml_str(&source),
+            Some("yaml" | "yml") => Self::from_yaml_str(&source),
+            _ => Err(ProfileError::Parse(format!("{}: expected a .toml, .yaml or .yml file", path.display()))),
+        }
+    }
+
+    // the built-in "openai" and "ollama" profiles. their endpoints come from
+    // OPENAI_BASE_URL and OLLAMA_HOST; an invalid one fails only its profile.
+    pub fn builtin() -> Result<Self, ProfileError> {
+        Self::from_toml_str(BUILTIN_PROFILES)
+    }
+
+    // the file named by LLM_PROFILES, else llm.toml / llm.yaml in the working
+    // directory, layered over the built-in profiles
+    pub fn discover() -> Result<Self, ProfileError> {
+        dotenv::dotenv().ok();
+        let path = env::var_os("LLM_PROFILES")
+            .map(PathBuf::from)
+            .or_else(|| PROFILE_FILES.iter().map(PathBuf::from).find(|path| path.is_file()));
+        let builtin = Self::builtin()?;
+        match path {
+            Some(path) => Ok(builtin.merge(Self::from_file(path)?)),
+            None => Ok(builtin),
+        }
+    }
+
+    // profiles in `other` replace same-named ones, and its default wins
+    pub fn merge(mut self, other: Profiles) -> Self {
+        self.profiles.extend(other.profiles);
+        self.default = other.default.or(self.default);
+        self
+    }
+
+    fn from_value(mut raw: Value) -> Result<Self, ProfileError> {
+        dotenv::dotenv().ok();
+        let mut unresolved = vec![];
+        let mut interpolated = HashSet::new();
+        interpolate(&mut raw, "", &mut unresolved, &mut interpolated);
+        let file: ProfilesFile = serde_json::from_value(raw).map_err(|e| ProfileError::Parse(e.to_string()))?;
+        let mut profiles = Profiles {
+            default: file.default,
+            profiles: BTreeMap::new(),
+        };
+        for (name, profile) in file.profiles {
+            let mut profile: Profile =
+                serde_json::from_value(profile).map_err(|e| ProfileError::Parse(format!("profile `{}`: {}", name, e)))?;
+            if profile.provider == ProviderKind::Ollama {
+                profile.base_url = profile.base_url.as_deref().map(ollama::host_url);
+            }
+            profiles.profiles.insert(name, profile);
+        }
+
+        let mut problems = vec![];
+        for (path, variable) in unresolved {
+            match path.strip_prefix("profiles.").and_then(|rest| rest.split_once('.')) {
+                Some((name, _)) if profiles.profiles.contains_key(name) => {
+                    profiles.profiles.get_mut(name).unwrap().unresolved.push(variable);
+                }
+                _ => problems.push(format!("`{}` needs the unset environment variable {}", path, variable)),
+            }
+        }
+        for (name, profile) in profiles.profiles.iter_mut() {
+            if let Some(variable) = &profile.api_key_env
+                && env::var(variable).is_err()
+            {
+                profile.unresolved.push(variable.clone());
+            }
+            for (field, problem) in profile.problems(name) {
+                if interpolated.contains(&format!("profiles.{}.{}", name, field)) {
+                    profile.invalid.push(problem);
+                } else {
+                    problems.push(problem);
+                }
+            }
+        }
+        if let Some(default) = &profiles.default
+            && !profiles.profiles.contains_key(default)
+        {
+            problems.push(format!("`default` names the unknown profile `{}`", default));
+        }
+
+        if !problems.is_empty() {
+            return Err(ProfileError::Invalid(problems));
+        }
+        Ok(profiles)
+    }
+
+    pub fn names(&self) -> impl Iterator<Item = &str> {
+        self.profiles.keys().map(String::as_str)
+    }
+
+    pub fn get(&self, name: &str) -> Result<&Profile, ProfileError> {
+        self.profiles.get(name).ok_or_else(|| ProfileError::UnknownProfile(name.to_string()))
+    }
+
+    pub fn default_name(&self) -> Option<&str> {
+        self.default.as_deref()
+    }
+
+    // a ready-to-use client for the named profile, with its defaults applied
+    pub fn client(&self, name: &str) -> Result<ProfileClient, LlmError> {
+        let profile = self.get(name)?;
+        if !profile.invalid.is_empty() {
+            return Err(ProfileError::Invalid(profile.invalid.clone()).into());
+        }
+        // a replayed cassette never reaches the provider, so CI needs no keys
+        let replaying = Cassette::from_env().is_some_and(|c| c.mode() == CassetteMode::Replay);
+        if !profile.unresolved.is_empty() && !replaying {
+            return Err(ProfileError::UnresolvedEnv {
+                profile: name.to_string(),
+                variables: profile.unresolved.clone(),
+            }
+            .into());
+        }
+
+        let client = profile.provider_client()?;
+        let client: Arc<dyn LlmClient> = match profile.max_attempts {
+            Some(attempts) if attempts > 1 => {
+                Arc::new(RetryingClient::new(client, RetryPolicy::default().with_max_attempts(attempts)))
+            }
+            _ => client,
+        };
+        Ok(ProfileClient {
+            inner: client,
+            name: name.to_string(),
+            temperature: profile.temperature,
+            max_tokens: profile.max_tokens,
+            system_prompt: profile.system_prompt.clone(),
+        })
+    }
+
+    pub fn default_client(&self) -> Result<ProfileClient, LlmError> {
+        let name = self.default.as_deref().ok_or_else(|| LlmError::Config("no default profile is set".into()))?;
+        self.client(name)
+    }
+}
+
+// clients behind `send_prompt` by (profile, agent). profiles are discovered
+// and clients built on first use only, so the helpers share one connection
+// pool instead of re-reading the files and reconnecting on every prompt.
+type SharedClients = Mutex<HashMap<(String, String), Arc<dyn LlmClient>>>;
+
+fn shared_clients() -> &'static SharedClients {
+    static CLIENTS: OnceLock<SharedClients> = OnceLock::new();
+    CLIENTS.get_or_init(Default::default)
+}
+
+// drops the cached clients, so the next prompt discovers the profiles again,
+// e.g. after a test changed LLM_PROFILES or the environment
+pub fn reset_shared_clients() {
+    shared_clients().lock().unwrap().clear();
+}
+
+// the lock is not held while discovering, so a slow profile file doesn't
+// stall every other prompt; if two callers race, the first client stays
+fn shared_client(profile: &str, agent: &str) -> LlmResult<Arc<dyn LlmClient>> {
+    let key = (profile.to_string(), agent.to_string());
+    if let Some(client) = shared_clients().lock().unwrap().get(&key) {
+        return Ok(client.clone());
+    }
+    let client: Arc<dyn LlmClient> = Arc::new(MeteredClient::new(
+        RateLimitedClient::new(Profiles::discover()?.client(profile)?, RateLimiter::global()),
+        UsageTracker::global(),
+        agent,
+    ));
+    Ok(shared_clients().lock().unwrap().entry(key).or_insert(client).clone())
+}
+
+// one prompt through a discovered profile, rate limited and metered under
+// `agent` like every other call
+pub(crate) async fn send_prompt(profile: &str, agent: &str, prompt: &str, options: &ChatOptions) -> LlmResult<String> {
+    let client = shared_client(profile, agent)?;
+    let completion = client.chat(&[ChatMessage::user(prompt)], options).await?;
+    Ok(completion.message.content)
+}
+
+// replaces `${VAR}` / `${VAR:-fallback}` in every string below `value`,
+// collecting (path, variable) for variables that are not set and the paths of
+// the strings that referenced any
+fn interpolate(value: &mut Value, path: &str, unresolved: &mut Vec<(String, String)>, interpolated: &mut HashSet<String>) {
+    let child = |key: &str| if path.is_empty() { key.to_string() } else { format!("{}.{}", path, key) };
+    match value {
+        Value::String(text) => {
+            if text.contains("${") {
+                interpolated.insert(path.to_string());
+            }
+            *text = interpolate_str(text, path, unresolved);
+        }
+        Value::Array(items) => {
+            for (i, item) in items.iter_mut().enumerate() {
+                interpolate(item, &child(&i.to_string()), unresolved, interpolated);
+            }
+        }
+        Value::Object(fields) => {
+            for (key, field) in fields.iter_mut() {
+                interpolate(field, &child(key), unresolved, interpolated);
+            }
+        }
+        _ => {}
+    }
+}
+
+fn interpolate_str(text: &str, path: &str, unresolved: &mut Vec<(String, String)>) -> String {
+    let mut out = String::with_capacity(text.len());
+    let mut rest = text;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$$") {
+            out.push('$');
+            rest = after;
+            continue;
+        }
+        let Some(end) = rest.strip_prefix("${").and_then(|inner| inner.find('}')) else {
+            out.push('$');
+            rest = &rest[1..];
+            continue;
+        };
+        let reference = &rest[2..2 + end];
+        let (variable, fallback) = match reference.split_once(":-") {
+            Some((variable, fallback)) => (variable, Some(fallback)),
+            None => (reference, None),
+        };
+        // like the shell, an empty variable also takes the fallback
+        let value = env::var(variable).ok().filter(|value| !value.is_empty() || fallback.is_none());
+        match (value, fallback) {
+            (Some(value), _) => out.push_str(&value),
+            (None, Some(fallback)) => out.push_str(fallback),
+            (None, None) => unresolved.push((path.to_string(), variable.to_string())),
+        }
+        rest = &rest[3 + end..];
+    }
+    out.push_str(rest);
+    out
+}
+
+// a profile's client. requests that leave temperature or max_tokens unset get
+// the profile's values, and conversations without a system message get its
+// system prompt.
+pub struct ProfileClient {
+    inner: Arc<dyn LlmClient>,
+    name: String,
+    temperature: Option<f32>,
+    max_tokens: Option<u32>,
+    system_prompt: Option<String>,
+}
+
+impl ProfileClient {
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn inner(&self) -> &Arc<dyn LlmClient> {
+        &self.inner
+    }
+
+    fn options(&self, options: &ChatOptions) -> ChatOptions {
+        ChatOptions {
+            temperature: options.temperature.or(self.temperature),
+            max_tokens: options.max_tokens.or(self.max_tokens),
+            ..options.clone()
+        }
+    }
+
+    fn messages<'a>(&self, messages: &'a [ChatMessage]) -> std::borrow::Cow<'a, [ChatMessage]> {
+        match &self.system_prompt {
+            Some(prompt) if !messages.iter().any(|m| m.role == Role::System) => {
+                let mut with_system = vec![ChatMessage::system(prompt.clone())];
+                with_system.extend_from_slice(messages);
+                with_system.into()
+            }
+            _ => messages.into(),
+        }
+    }
+}
+
+#[async_trait]
+impl LlmClient for ProfileClient {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        self.inner.endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.temperature.or(self.inner.default_temperature())
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        self.inner.chat(&self.messages(messages), &self.options(options)).await
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        self.inner.chat_stream(&self.messages(messages), &self.options(options)).await
+    }
+}
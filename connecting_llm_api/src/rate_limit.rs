@@ -0,0 +1,487 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use std::{
+    collections::HashMap,
+    env,
+    sync::{Arc, Mutex, OnceLock},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::cancel;
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient};
+use crate::error::LlmResult;
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::tokens::{self, DEFAULT_COMPLETION_RESERVE};
+
+// client-side rate limiting, so concurrent agents stay within the provider's
+// requests-per-minute and tokens-per-minute quotas instead of collecting 429s.
+// every provider/model pair gets a lane with two token buckets. callers
+// reserve one request plus an estimate of prompt and completion tokens, wait
+// in FIFO order until the buckets allow it, and settle the reservation with
+// the real usage once the reply is in.
+
+// None = unlimited. a limit of 0 admits nothing: requests wait until
+// cancelled, wherever the limit comes from.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RateLimits {
+    pub requests_per_minute: Option<u32>,
+    pub tokens_per_minute: Option<u32>,
+}
+
+impl RateLimits {
+    pub fn new(requests_per_minute: u32, tokens_per_minute: u32) -> Self {
+        RateLimits {
+            requests_per_minute: Some(requests_per_minute),
+            tokens_per_minute: Some(tokens_per_minute),
+        }
+    }
+}
+
+// how often a blocked lane (a limit of 0) looks at its buckets again
+const BLOCKED_RECHECK: Duration = Duration::from_secs(60);
+
+// refills continuously up to a minute's worth of capacity
+struct Bucket {
+    capacity: f64,
+    available: f64,
+    refill_per_sec: f64,
+    updated: Instant,
+}
+
+impl Bucket {
+    fn new(per_minute: u32) -> Self {
+        Bucket {
+            capacity: per_minute as f64,
+            available: per_minute as f64,
+            refill_per_sec: per_minute as f64 / 60.0,
+            updated: Instant::now(),
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now.saturating_duration_since(self.updated).as_secs_f64();
+        self.available = (self.available + elapsed * self.refill_per_sec).min(self.capacity);
+        self.updated = now;
+    }
+
+    // amounts above the capacity could never be granted, so they are capped
+    fn clamp(&self, amount: f64) -> f64 {
+        amount.min(self.capacity)
+    }
+
+    fn is_blocked(&self) -> bool {
+        self.capacity <= 0.0
+    }
+
+    fn wait_for(&self, amount: f64) -> Duration {
+        if self.is_blocked() {
+            return BLOCKED_RECHECK;
+        }
+        let missing = self.clamp(amount) - self.available;
+        if missing <= 0.0 {
+            return Duration::ZERO;
+        }
+        Duration::from_secs_f64(missing / self.refill_per_sec)
+    }
+}
+
+struct Lane {
+    requests: Option<Bucket>,
+    tokens: Option<Bucket>,
+    // tokio's mutex hands out the lock in FIFO order, which makes the queue fair
+    queue: Arc<tokio::sync::Mutex<()>>,
+    queued: usize,
+}
+
+impl Lane {
+    fn new(limits: RateLimits) -> Self {
+        Lane {
+            requests: limits.requests_per_minute.map(Bucket::new),
+            tokens: limits.tokens_per_minute.map(Bucket::new),
+            queue: Arc::new(tokio::sync::Mutex::new(())),
+            queued: 0,
+        }
+    }
+
+    fn buckets(&mut self) -> impl Iterator<Item = &mut Bucket> {
+        self.requests.iter_mut().chain(self.tokens.iter_mut())
+    }
+
+    // takes one request and `tokens` if both buckets allow it and returns the
+    // tokens actually deducted (capped at the capacity), otherwise returns how
+    // long to wait
+    fn try_take(&mut self, tokens: u32) -> Result<f64, Duration> {
+        let now = Instant::now();
+        self.buckets().for_each(|bucket| bucket.refill(now));
+
+        let wait = self
+            .requests
+            .iter()
+            .map(|bucket| bucket.wait_for(1.0))
+            .chain(self.tokens.iter().map(|bucket| bucket.wait_for(tokens as f64)))
+            .max()
+            .unwrap_or(Duration::ZERO);
+        if !wait.is_zero() {
+            return Err(wait);
+        }
+
+        if let Some(bucket) = &mut self.requests {
+            bucket.available -= 1.0;
+        }
+        let mut taken = 0.0;
+        if let Some(bucket) = &mut self.tokens {
+            taken = bucket.clamp(tokens as f64);
+            bucket.available -= taken;
+        }
+        Ok(taken)
+    }
+}
+
+// what a lane could accept right now
+#[derive(Debug, Clone, Copy)]
+pub struct Headroom {
+    // None = unlimited
+    pub requests: Option<f64>,
+    pub tokens: Option<f64>,
+    // the most tokens the lane ever holds; larger requests are capped to it
+    pub token_capacity: Option<f64>,
+    // callers waiting for their turn
+    pub queued: usize,
+}
+
+impl Headroom {
+    // whether a request of `tokens` would go through without waiting, by the
+    // same rules as `RateLimiter::acquire`
+    pub fn can_send(&self, tokens: u32) -> bool {
+        let tokens_fit = match (self.tokens, self.token_capacity) {
+            (None, _) => true,
+            (Some(_), Some(capacity)) if capacity <= 0.0 => false,
+            (Some(available), capacity) => available >= capacity.map_or(tokens as f64, |capacity| (tokens as f64).min(capacity)),
+        };
+        self.queued == 0 && self.requests.is_none_or(|r| r >= 1.0) && tokens_fit
+    }
+}
+
+pub struct RateLimiter {
+    default_limits: RateLimits,
+    // keyed by "provider" or "provider/model"; the more specific one wins
+    limits: HashMap<String, RateLimits>,
+    lanes: Mutex<HashMap<String, Lane>>,
+}
+
+impl RateLimiter {
+    pub fn new(default_limits: RateLimits) -> Self {
+        RateLimiter {
+            default_limits,
+            limits: HashMap::new(),
+            lanes: Mutex::new(HashMap::new()),
+        }
+    }
+
+    // limits for one provider ("openai") or one model ("openai/gpt-4o")
+    pub fn with_limits(mut self, key: impl Into<String>, limits: RateLimits) -> Self {
+        self.limits.insert(key.into(), limits);
+        self
+    }
+
+    // the limiter `send_to_openai` and `send_to_ollama` share, configured via
+    // LLM_RPM and LLM_TPM (unset = unlimited)
+    pub fn global() -> Arc<RateLimiter> {
+        static GLOBAL: OnceLock<Arc<RateLimiter>> = OnceLock::new();
+        GLOBAL
+            .get_or_init(|| {
+                let limit = |name: &str| {
+                    let limit = env::var(name).ok()?.trim().parse::<u32>().ok()?;
+                    if limit == 0 {
+                        tracing::warn!(target: "llm::rate_limit", variable = name, "rate limit of 0, requests will wait until cancelled");
+                    }
+                    Some(limit)
+                };
+                Arc::new(RateLimiter::new(RateLimits {
+                    requests_per_minute: limit("LLM_RPM"),
+                    tokens_per_minute: limit("LLM_TPM"),
+                }))
+            })
+            .clone()
+    }
+
+    fn limits_for(&self, key: &str) -> RateLimits {
+        let provider = key.split('/').next().unwrap_or(key);
+        self.limits
+            .get(key)
+            .or_else(|| self.limits.get(provider))
+            .copied()
+            .unwrap_or(self.default_limits)
+    }
+
+    fn with_lane<T>(&self, key: &str, f: impl FnOnce(&mut Lane) -> T) -> T {
+        let mut lanes = self.lanes.lock().unwrap();
+        let lane = lanes.entry(key.to_string()).or_insert_with(|| Lane::new(self.limits_for(key)));
+        f(lane)
+    }
+
+    // waits for a request slot plus `tokens` in the lane for `key`
+    pub async fn acquire(self: &Arc<Self>, key: &str, tokens: u32) -> Permit {
+        let queue = self.with_lane(key, |lane| {
+            lane.queued += 1;
+            lane.queue.clone()
+        });
+
+        let reserved = {
+            let _queued = Queued { limiter: self, key };
+            let _turn = queue.lock().await;
+            loop {
+                match self.with_lane(key, |lane| lane.try_take(tokens)) {
+                    Ok(taken) => break taken,
+                    Err(wait) => {
+                        tracing::debug!(target: "llm::rate_limit", key, tokens, wait_ms = wait.as_millis() as u64, "waiting for rate limit");
+                        tokio::time::sleep(wait).await;
+                    }
+                }
+            }
+        };
+
+        Permit {
+            limiter: self.clone(),
+            key: key.to_string(),
+            reserved,
+            used: None,
+        }
+    }
+
+    pub fn headroom(&self, key: &str) -> Headroom {
+        self.with_lane(key, |lane| {
+            let now = Instant::now();
+            lane.buckets().for_each(|bucket| bucket.refill(now));
+            Headroom {
+                requests: lane.requests.as_ref().map(|bucket| bucket.available),
+                tokens: lane.tokens.as_ref().map(|bucket| bucket.available),
+                token_capacity: lane.tokens.as_ref().map(|bucket| bucket.capacity),
+                queued: lane.queued,
+            }
+        })
+    }
+}
+
+// leaves the queue count right even when a waiting caller is cancelled
+struct Queued<'a> {
+    limiter: &'a RateLimiter,
+    key: &'a str,
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.limiter.with_lane(self.key, |lane| lane.queued -= 1);
+    }
+}
+
+// a granted reservation. settle it with the real token count or release it
+// when the request failed; dropped as is, the estimate stands.
+pub struct Permit {
+    limiter: Arc<RateLimiter>,
+    key: String,
+    // what was deducted, which is less than asked for an oversized request
+    reserved: f64,
+    used: Option<u32>,
+}
+
+impl Permit {
+    pub fn settle(mut self, used: u32) {
+        self.used = Some(used);
+    }
+
+    // gives the reserved tokens back; the request itself still counts
+    pub fn release(self) {
+        self.settle(0);
+    }
+}
+
+impl Drop for Permit {
+    fn drop(&mut self) {
+        let Some(used) = self.used else {
+            return;
+        };
+        let difference = self.reserved - used as f64;
+        self.limiter.with_lane(&self.key, |lane| {
+            if let Some(bucket) = &mut lane.tokens {
+                // may go negative when the estimate was too low; later
+                // callers then wait for the overdraft to refill
+                bucket.available = (bucket.available + difference).min(bucket.capacity);
+            }
+        });
+    }
+}
+
+// wraps any client so every call waits for the shared limiter.
+// the reservation is the estimated prompt plus `max_tokens` (or a default
+// completion reserve) and is corrected with the reported usage.
+pub struct RateLimitedClient<C> {
+    inner: C,
+    limiter: Arc<RateLimiter>,
+}
+
+impl<C: LlmClient> RateLimitedClient<C> {
+    pub fn new(inner: C, limiter: Arc<RateLimiter>) -> Self {
+        RateLimitedClient { inner, limiter }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    fn key(&self, options: &ChatOptions) -> String {
+        let model = options.model.as_deref().unwrap_or(self.inner.default_model());
+        format!("{}/{}", self.inner.provider(), model)
+    }
+
+    fn estimate(&self, messages: &[ChatMessage], options: &ChatOptions) -> u32 {
+        let model = options.model.as_deref().unwrap_or(self.inner.default_model());
+        let prompt = tokens::counter_for(self.inner.provider(), model).count_messages(messages);
+        let completion = options.max_tokens.map(|t| t as usize).unwrap_or(DEFAULT_COMPLETION_RESERVE);
+        u32::try_from(prompt.saturating_add(completion)).unwrap_or(u32::MAX)
+    }
+
+    // a cancelled request leaves the queue without taking a slot
+    async fn acquire(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<Permit> {
+        let (key, tokens) = (self.key(options), self.estimate(messages, options));
+        cancel::cancellable(options.cancel.as_ref(), async { Ok(self.limiter.acquire(&key, tokens).await) }).await
+    }
+
+    pub fn headroom(&self, options: &ChatOptions) -> Headroom {
+        self.limiter.headroom(&self.key(options))
+    }
+}
+
+#[async_trait]
+impl<C: LlmClient> LlmClient for RateLimitedClient<C> {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        self.inner.endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.inner.default_temperature()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let permit = self.acquire(messages, options).await?;
+        match self.inner.chat(messages, options).await {
+            Ok(completion) => {
+                if let Some(usage) = completion.usage {
+                    permit.settle(usage.total_tokens());
+                }
+                Ok(completion)
+            }
+            Err(error) => {
+                permit.release();
+                Err(error)
+            }
+        }
+    }
+
+    // the permit travels with the stream: settled by its final event, released
+    // by an error (including a cancellation)
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let permit = self.acquire(messages, options).await?;
+        let stream = match self.inner.chat_stream(messages, options).await {
+            Ok(stream) => stream,
+            Err(error) => {
+                permit.release();
+                return Err(error);
+            }
+        };
+
+        let mut permit = Some(permit);
+        Ok(Box::pin(stream.inspect(move |event| match event {
+            Ok(StreamEvent::Done { usage: Some(usage), .. }) => {
+                if let Some(permit) = permit.take() {
+                    permit.settle(usage.total_tokens());
+                }
+            }
+            Err(_) => {
+                if let Some(permit) = permit.take() {
+                    permit.release();
+                }
+            }
+            Ok(_) => {}
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::error::LlmError;
+
+    #[test]
+    fn a_zero_limit_blocks_instead_of_allowing_everything() {
+        let mut lane = Lane::new(RateLimits {
+            requests_per_minute: Some(0),
+            tokens_per_minute: None,
+        });
+        for _ in 0..3 {
+            assert_eq!(lane.try_take(10), Err(BLOCKED_RECHECK));
+        }
+        let limiter = RateLimiter::new(RateLimits::new(60, 0));
+        assert!(!limiter.headroom("openai/gpt-4o").can_send(0));
+    }
+
+    #[test]
+    fn oversized_requests_are_capped_consistently() {
+        let limiter = RateLimiter::new(RateLimits::new(60, 1000));
+        // more than a minute's worth: granted once the bucket is full
+        assert!(limiter.headroom("openai/gpt-4o").can_send(5000));
+        assert_eq!(limiter.with_lane("openai/gpt-4o", |lane| lane.try_take(5000)), Ok(1000.0));
+        assert!(!limiter.headroom("openai/gpt-4o").can_send(5000));
+    }
+
+    #[tokio::test]
+    async fn settling_an_oversized_request_refunds_only_what_was_taken() {
+        let limiter = Arc::new(RateLimiter::new(RateLimits::new(60, 1000)));
+        limiter.acquire("openai/gpt-4o", 5000).await.settle(1200);
+        // 1000 taken, 1200 used: 200 in overdraft rather than a full refund
+        let tokens = limiter.headroom("openai/gpt-4o").tokens.unwrap();
+        assert!((-200.0..-199.0).contains(&tokens), "{}", tokens);
+    }
+
+    #[tokio::test]
+    async fn a_failed_stream_gives_its_tokens_back() {
+        use futures::stream;
+
+        struct Failing;
+
+        #[async_trait]
+        impl LlmClient for Failing {
+            fn provider(&self) -> &'static str {
+                "openai"
+            }
+
+            fn default_model(&self) -> &str {
+                "gpt-4o"
+            }
+
+            async fn chat(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatCompletion> {
+                Err(LlmError::Timeout)
+            }
+
+            async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+                Ok(Box::pin(stream::iter(vec![Ok(StreamEvent::Delta("Par".into())), Err(LlmError::Timeout)])))
+            }
+        }
+
+        let limiter = Arc::new(RateLimiter::new(RateLimits::new(60, 10_000)));
+        let client = RateLimitedClient::new(Failing, limiter.clone());
+        let options = ChatOptions::default().with_max_tokens(4000);
+        let events: Vec<_> = client.chat_stream(&[ChatMessage::user("hi")], &options).await.unwrap().collect().await;
+        assert!(events[1].is_err());
+        assert!(limiter.headroom("openai/gpt-4o").tokens.unwrap() > 9_999.0);
+    }
+}
@@ -0,0 +1,202 @@
+use async_trait::async_trait;
+use std::{future::Future, time::Duration};
+use tokio::time::{Instant, timeout_at};
+
+use crate::cancel;
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient};
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::ChatStream;
+
+// how persistently a failed LLM request is retried.
+// only errors for which `LlmError::is_retryable` is true are retried.
+#[derive(Debug, Clone)]
+pub struct RetryPolicy {
+    // total attempts, including the first one
+    pub max_attempts: u32,
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    // fraction of the delay randomized in both directions (0.0 = none)
+    pub jitter: f64,
+    // overall budget for all attempts and waits together
+    pub deadline: Option<Duration>,
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 4,
+            base_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            jitter: 0.25,
+            deadline: None,
+        }
+    }
+}
+
+impl RetryPolicy {
+    pub fn with_max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    pub fn with_deadline(mut self, deadline: Duration) -> Self {
+        self.deadline = Some(deadline);
+        self
+    }
+
+    // exponential backoff: base * 2^(attempt - 1), capped and jittered
+    pub fn backoff(&self, attempt: u32) -> Duration {
+        let exponential = self.base_delay.saturating_mul(2u32.saturating_pow(attempt.saturating_sub(1)));
+        let capped = exponential.min(self.max_delay).as_secs_f64();
+        let spread = capped * self.jitter.clamp(0.0, 1.0);
+        let jittered = capped - spread + fastrand::f64() * spread * 2.0;
+        Duration::from_secs_f64(jittered.max(0.0))
+    }
+
+    // the provider's Retry-After takes precedence over our own schedule, but
+    // never stretches a wait past `max_delay`
+    fn delay_for(&self, attempt: u32, error: &LlmError) -> Duration {
+        error
+            .retry_after()
+            .map(|after| after.min(self.max_delay))
+            .unwrap_or_else(|| self.backoff(attempt))
+    }
+}
+
+// runs `operation` until it succeeds, fails permanently, or the attempt or
+// time budget is spent. every attempt is reported through `tracing`.
+pub async fn with_retry<T, F, Fut>(policy: &RetryPolicy, label: &str, mut operation: F) -> LlmResult<T>
+where
+    F: FnMut() -> Fut,
+    Fut: Future<Output = LlmResult<T>>,
+{
+    let deadline = policy.deadline.map(|d| Instant::now() + d);
+    let mut attempt = 0;
+
+    loop {
+        attempt += 1;
+        tracing::debug!(target: "llm::retry", request = label, attempt, max_attempts = policy.max_attempts, "sending LLM request");
+
+        let outcome = match deadline {
+            Some(deadline) => timeout_at(deadline, operation()).await.unwrap_or(Err(LlmError::Timeout)),
+            None => operation().await,
+        };
+
+        let error = match outcome {
+            Ok(value) => {
+                if attempt > 1 {
+                    tracing::info!(target: "llm::retry", request = label, attempt, "LLM request succeeded after retrying");
+                }
+                return Ok(value);
+            }
+            Err(error) => error,
+        };
+
+        if !error.is_retryable() || attempt >= policy.max_attempts {
+            tracing::error!(target: "llm::retry", request = label, attempt, error = %error, "LLM request failed, giving up");
+            return Err(error);
+        }
+
+        let delay = policy.delay_for(attempt, &error);
+        if let Some(deadline) = deadline
+            && Instant::now() + delay >= deadline
+        {
+            tracing::error!(target: "llm::retry", request = label, attempt, error = %error, "LLM request failed, deadline would pass before the next attempt");
+            return Err(error);
+        }
+
+        tracing::warn!(
+            target: "llm::retry",
+            request = label,
+            attempt,
+            max_attempts = policy.max_attempts,
+            delay_ms = delay.as_millis() as u64,
+            error = %error,
+            "LLM request failed, retrying"
+        );
+        tokio::time::sleep(delay).await;
+    }
+}
+
+// wraps any client so every call goes through `with_retry`.
+// for streams only establishing the connection is retried; once deltas have
+// been handed out, a failure is returned to the caller as-is. cancelling the
+// request also cuts a backoff short.
+pub struct RetryingClient<C> {
+    inner: C,
+    policy: RetryPolicy,
+}
+
+impl<C: LlmClient> RetryingClient<C> {
+    pub fn new(inner: C, policy: RetryPolicy) -> Self {
+        RetryingClient { inner, policy }
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+#[async_trait]
+impl<C: LlmClient> LlmClient for RetryingClient<C> {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        self.inner.endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.inner.default_temperature()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let retried = with_retry(&self.policy, self.inner.provider(), || self.inner.chat(messages, options));
+        cancel::cancellable(options.cancel.as_ref(), retried).await
+    }
+
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let retried = with_retry(&self.policy, self.inner.provider(), || self.inner.chat_stream(messages, options));
+        cancel::cancellable(options.cancel.as_ref(), retried).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant as StdInstant;
+
+    fn rate_limited(retry_after: Duration) -> LlmError {
+        LlmError::RateLimited {
+            retry_after: Some(retry_after),
+            message: "slow down".into(),
+        }
+    }
+
+    #[test]
+    fn retry_after_is_capped_by_max_delay() {
+        let policy = RetryPolicy::default();
+        assert_eq!(policy.delay_for(1, &rate_limited(Duration::from_secs(86400))), policy.max_delay);
+        assert_eq!(policy.delay_for(1, &rate_limited(Duration::from_secs(2))), Duration::from_secs(2));
+    }
+
+    #[tokio::test]
+    async fn gives_up_when_the_wait_would_pass_the_deadline() {
+        let policy = RetryPolicy::default().with_deadline(Duration::from_millis(200));
+        let started = StdInstant::now();
+        let mut attempts = 0;
+        let result: LlmResult<()> = with_retry(&policy, "test", || {
+            attempts += 1;
+            async { Err(rate_limited(Duration::from_secs(86400))) }
+        })
+        .await;
+        assert!(matches!(result, Err(LlmError::RateLimited { .. })));
+        assert_eq!(attempts, 1);
+        assert!(started.elapsed() < Duration::from_millis(200));
+    }
+}
@@ -0,0 +1,508 @@
+use async_trait::async_trait;
+use std::{
+    future::Future,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+use tokio::time::Instant;
+
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient};
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::ChatStream;
+use crate::tokens::{HeuristicCounter, TokenCounter};
+
+// spreads requests over several providers and fails over between them, e.g.
+// OpenAI first and a local ollama model once OpenAI rate-limits us.
+// routes that cannot serve a request (no tool support, too small a context,
+// ...) are skipped under every strategy, and routes that keep failing are
+// ejected for a while.
+
+// what a request needs from a route
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Capability {
+    Tools,
+    StructuredOutput,
+    Streaming,
+}
+
+// the order in which routes are tried
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Strategy {
+    // in the order they were added
+    Failover,
+    // the first route rotates in proportion to the weights, the rest follow
+    // in the order they were added
+    WeightedRoundRobin,
+    // lowest cost first
+    CheapestFirst,
+}
+
+pub struct Route {
+    client: Arc<dyn LlmClient>,
+    name: String,
+    // replaces the caller's model; None uses the client's default
+    model: Option<String>,
+    weight: u32,
+    // relative price, e.g. USD per million tokens
+    cost: f64,
+    // None = everything
+    capabilities: Option<Vec<Capability>>,
+    context_window: Option<usize>,
+}
+
+impl Route {
+    pub fn new(client: impl LlmClient + 'static) -> Self {
+        Self::shared(Arc::new(client))
+    }
+
+    pub fn shared(client: Arc<dyn LlmClient>) -> Self {
+        Route {
+            name: client.provider().to_string(),
+            client,
+            model: None,
+            weight: 1,
+            cost: 0.0,
+            capabilities: None,
+            context_window: None,
+        }
+    }
+
+    pub fn named(mut self, name: impl Into<String>) -> Self {
+        self.name = name.into();
+        self
+    }
+
+    pub fn with_model(mut self, model: impl Into<String>) -> Self {
+        self.model = Some(model.into());
+        self
+    }
+
+    pub fn with_weight(mut self, weight: u32) -> Self {
+        self.weight = weight;
+        self
+    }
+
+    pub fn with_cost(mut self, cost: f64) -> Self {
+        self.cost = cost;
+        self
+    }
+
+    // restricts the route to requests needing only these capabilities
+    pub fn with_capabilities(mut self, capabilities: impl IntoIterator<Item = Capability>) -> Self {
+        self.capabilities = Some(capabilities.into_iter().collect());
+        self
+    }
+
+    // skips the route for prompts estimated to be larger than this
+    pub fn with_context_window(mut self, tokens: usize) -> Self {
+        self.context_window = Some(tokens);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    fn model(&self) -> &str {
+        self.model.as_deref().unwrap_or(self.client.default_model())
+    }
+
+    fn can_serve(&self, needs: &[Capability], prompt_tokens: usize) -> bool {
+        let capable = self.capabilities.as_ref().is_none_or(|caps| needs.iter().all(|need| caps.contains(need)));
+        capable && self.context_window.is_none_or(|window| prompt_tokens <= window)
+    }
+
+    fn options(&self, options: &ChatOptions) -> ChatOptions {
+        ChatOptions {
+            model: self.model.clone(),
+            ..options.clone()
+        }
+    }
+}
+
+// when a route is taken out of rotation
+#[derive(Debug, Clone)]
+pub struct HealthPolicy {
+    // consecutive provider-side failures before ejection
+    pub failure_threshold: u32,
+    pub ejection: Duration,
+}
+
+impl Default for HealthPolicy {
+    fn default() -> Self {
+        HealthPolicy {
+            failure_threshold: 3,
+            ejection: Duration::from_secs(30),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+struct Health {
+    consecutive_failures: u32,
+    ejected_until: Option<Instant>,
+}
+
+// a snapshot of one route's health
+#[derive(Debug, Clone)]
+pub struct RouteHealth {
+    pub name: String,
+    pub consecutive_failures: u32,
+    // time left until the route is tried again; None when healthy
+    pub ejected_for: Option<Duration>,
+}
+
+pub struct RoutingClient {
+    routes: Vec<Route>,
+    strategy: Strategy,
+    policy: HealthPolicy,
+    health: Mutex<Vec<Health>>,
+    // smooth weighted round-robin state, one entry per route
+    current_weights: Mutex<Vec<i64>>,
+}
+
+impl RoutingClient {
+    pub fn new(strategy: Strategy) -> Self {
+        RoutingClient {
+            routes: vec![],
+            strategy,
+            policy: HealthPolicy::default(),
+            health: Mutex::new(vec![]),
+            current_weights: Mutex::new(vec![]),
+        }
+    }
+
+    pub fn route(mut self, route: Route) -> Self {
+        self.routes.push(route);
+        self.health.get_mut().unwrap().push(Health::default());
+        self.current_weights.get_mut().unwrap().push(0);
+        self
+    }
+
+    pub fn with_health_policy(mut self, policy: HealthPolicy) -> Self {
+        self.policy = policy;
+        self
+    }
+
+    pub fn health(&self) -> Vec<RouteHealth> {
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        self.routes
+            .iter()
+            .zip(health.iter())
+            .map(|(route, health)| RouteHealth {
+                name: route.name.clone(),
+                consecutive_failures: health.consecutive_failures,
+                ejected_for: health.ejected_until.filter(|until| *until > now).map(|until| until - now),
+            })
+            .collect()
+    }
+
+    // indices of the routes to try, best first
+    fn plan(&self, messages: &[ChatMessage], options: &ChatOptions, streaming: bool) -> Vec<usize> {
+        let mut needs = vec![];
+        if !options.tools.is_empty() {
+            needs.push(Capability::Tools);
+        }
+        if options.response_format.is_some() {
+            needs.push(Capability::StructuredOutput);
+        }
+        if streaming {
+            needs.push(Capability::Streaming);
+        }
+        let prompt_tokens = HeuristicCounter::default().count_messages(messages);
+
+        let mut order: Vec<usize> = (0..self.routes.len())
+            .filter(|&i| self.routes[i].can_serve(&needs, prompt_tokens))
+            .collect();
+        match self.strategy {
+            Strategy::Failover => {}
+            Strategy::CheapestFirst => order.sort_by(|&a, &b| self.routes[a].cost.total_cmp(&self.routes[b].cost)),
+            Strategy::WeightedRoundRobin => {
+                if let Some(first) = self.next_weighted(&order) {
+                    order.retain(|&i| i != first);
+                    order.insert(0, first);
+                }
+            }
+        }
+
+        // ejected routes go last, soonest back first, so a request still has
+        // somewhere to go when every route is ejected
+        let now = Instant::now();
+        let health = self.health.lock().unwrap();
+        order.sort_by_key(|&i| health[i].ejected_until.filter(|until| *until > now));
+        order
+    }
+
+    // nginx's smooth weighted round-robin over `candidates`
+    fn next_weighted(&self, candidates: &[usize]) -> Option<usize> {
+        let mut current = self.current_weights.lock().unwrap();
+        let total: i64 = candidates.iter().map(|&i| self.routes[i].weight as i64).sum();
+        if total == 0 {
+            return candidates.first().copied();
+        }
+        for &i in candidates {
+            current[i] += self.routes[i].weight as i64;
+        }
+        let best = *candidates.iter().max_by_key(|&&i| (current[i], std::cmp::Reverse(i)))?;
+        current[best] -= total;
+        Some(best)
+    }
+
+    fn record_success(&self, index: usize) {
+        self.health.lock().unwrap()[index] = Health::default();
+    }
+
+    fn record_failure(&self, index: usize, error: &LlmError) {
+        // errors caused by the request itself say nothing about the provider
+        if !(error.is_retryable() || matches!(error, LlmError::Auth(_))) {
+            return;
+        }
+        let mut health = self.health.lock().unwrap();
+        let health = &mut health[index];
+        health.consecutive_failures += 1;
+        if health.consecutive_failures >= self.policy.failure_threshold {
+            health.ejected_until = Some(Instant::now() + self.policy.ejection);
+            tracing::warn!(
+                target: "llm::router",
+                route = %self.routes[index].name,
+                failures = health.consecutive_failures,
+                ejection_secs = self.policy.ejection.as_secs(),
+                "ejecting route"
+            );
+        }
+    }
+
+    // tries the planned routes in order until one succeeds
+    async fn dispatch<'a, T, F, Fut>(&'a self, messages: &[ChatMessage], options: &ChatOptions, streaming: bool, call: F) -> LlmResult<T>
+    where
+        F: Fn(&'a Route, ChatOptions) -> Fut,
+        Fut: Future<Output = LlmResult<T>>,
+    {
+        let order = self.plan(messages, options, streaming);
+        let mut last_error = None;
+
+        for index in order {
+            let route = &self.routes[index];
+            match call(route, route.options(options)).await {
+                Ok(value) => {
+                    self.record_success(index);
+                    return Ok(value);
+                }
+                Err(error) => {
+                    self.record_failure(index, &error);
+                    // a filtered prompt should not be retried elsewhere until it
+                    // passes, an exhausted budget is the same on every route, and
+                    // a cancelled request is meant to stop
+                    if matches!(error, LlmError::ContentFiltered(_) | LlmError::BudgetExceeded { .. } | LlmError::Cancelled) {
+                        return Err(error);
+                    }
+                    tracing::warn!(target: "llm::router", route = %route.name, model = route.model(), error = %error, "route failed, trying the next one");
+                    last_error = Some(error);
+                }
+            }
+        }
+
+        Err(last_error.unwrap_or_else(|| LlmError::Unavailable {
+            retry_after: None,
+            message: "no route can serve this request".into(),
+        }))
+    }
+}
+
+#[async_trait]
+impl LlmClient for RoutingClient {
+    fn provider(&self) -> &'static str {
+        "router"
+    }
+
+    fn default_model(&self) -> &str {
+        self.routes.first().map(Route::model).unwrap_or_default()
+    }
+
+    // the caller's `options.model` is replaced by each route's own model
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        self.dispatch(messages, options, false, |route, options| async move {
+            route.client.chat(messages, &options).await
+        })
+        .await
+    }
+
+    // only establishing the stream fails over; errors mid-stream are returned as-is
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        self.dispatch(messages, options, true, |route, options| async move {
+            route.client.chat_stream(messages, &options).await
+        })
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ToolDefinition;
+    use std::sync::atomic::{AtomicUsize, Ordering};
+
+    // answers with its own name, or fails with `error` while one is set
+    struct Stub {
+        name: &'static str,
+        error: Mutex<Option<fn() -> LlmError>>,
+        calls: AtomicUsize,
+    }
+
+    impl Stub {
+        fn new(name: &'static str) -> Arc<Self> {
+            Arc::new(Stub { name, error: Mutex::new(None), calls: AtomicUsize::new(0) })
+        }
+
+        fn failing(name: &'static str, error: fn() -> LlmError) -> Arc<Self> {
+            let stub = Self::new(name);
+            stub.fail_with(Some(error));
+            stub
+        }
+
+        fn fail_with(&self, error: Option<fn() -> LlmError>) {
+            *self.error.lock().unwrap() = error;
+        }
+
+        fn calls(&self) -> usize {
+            self.calls.load(Ordering::SeqCst)
+        }
+    }
+
+    #[async_trait]
+    impl LlmClient for Stub {
+        fn provider(&self) -> &'static str {
+            self.name
+        }
+
+        fn default_model(&self) -> &str {
+            self.name
+        }
+
+        async fn chat(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            self.calls.fetch_add(1, Ordering::SeqCst);
+            if let Some(error) = *self.error.lock().unwrap() {
+                return Err(error());
+            }
+            Ok(ChatCompletion {
+                message: ChatMessage::assistant(self.name),
+                model: self.name.to_string(),
+                finish_reason: Some("stop".into()),
+                usage: None,
+            })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            unimplemented!("not streamed in these tests")
+        }
+    }
+
+    fn unavailable() -> LlmError {
+        LlmError::Unavailable { retry_after: None, message: "overloaded".into() }
+    }
+
+    async fn ask(router: &RoutingClient, options: &ChatOptions) -> LlmResult<String> {
+        Ok(router.chat(&[ChatMessage::user("hello")], options).await?.content().to_string())
+    }
+
+    #[tokio::test]
+    async fn failover_tries_routes_in_order() {
+        let (a, b, c) = (Stub::failing("a", unavailable), Stub::new("b"), Stub::new("c"));
+        let router = RoutingClient::new(Strategy::Failover)
+            .route(Route::shared(a.clone()))
+            .route(Route::shared(b.clone()))
+            .route(Route::shared(c.clone()));
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "b");
+        assert_eq!((a.calls(), b.calls(), c.calls()), (1, 1, 0));
+    }
+
+    #[tokio::test]
+    async fn some_errors_stop_the_failover() {
+        let stopping: [fn() -> LlmError; 3] = [
+            || LlmError::ContentFiltered("blocked".into()),
+            || LlmError::BudgetExceeded { scope: "agent".into(), spent: 1.0, limit: 1.0 },
+            || LlmError::Cancelled,
+        ];
+        for error in stopping {
+            let (a, b) = (Stub::failing("a", error), Stub::new("b"));
+            let router = RoutingClient::new(Strategy::Failover).route(Route::shared(a.clone())).route(Route::shared(b.clone()));
+            let failed = ask(&router, &ChatOptions::default()).await.unwrap_err();
+            assert!(matches!(failed, LlmError::ContentFiltered(_) | LlmError::BudgetExceeded { .. } | LlmError::Cancelled), "{}", failed);
+            assert_eq!(b.calls(), 0, "{}", failed);
+        }
+    }
+
+    #[tokio::test]
+    async fn weighted_round_robin_follows_the_weights() {
+        let (a, b) = (Stub::new("a"), Stub::new("b"));
+        let router = RoutingClient::new(Strategy::WeightedRoundRobin)
+            .route(Route::shared(a.clone()).with_weight(3))
+            .route(Route::shared(b.clone()).with_weight(1));
+        let mut replies = vec![];
+        for _ in 0..8 {
+            replies.push(ask(&router, &ChatOptions::default()).await.unwrap());
+        }
+        assert_eq!((a.calls(), b.calls()), (6, 2));
+        // smooth: b is spread out rather than sent twice in a row
+        assert!(!replies.windows(2).any(|pair| pair == ["b", "b"]));
+    }
+
+    #[tokio::test]
+    async fn cheapest_routes_go_first() {
+        let router = RoutingClient::new(Strategy::CheapestFirst)
+            .route(Route::shared(Stub::new("a")).with_cost(5.0))
+            .route(Route::shared(Stub::new("b")).with_cost(1.0))
+            .route(Route::shared(Stub::new("c")).with_cost(3.0));
+        assert_eq!(router.plan(&[ChatMessage::user("hello")], &ChatOptions::default(), false), vec![1, 2, 0]);
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "b");
+    }
+
+    #[tokio::test]
+    async fn routes_that_cannot_serve_the_request_are_skipped() {
+        let router = RoutingClient::new(Strategy::Failover)
+            .route(Route::shared(Stub::new("small")).with_capabilities([Capability::Streaming]).with_context_window(50))
+            .route(Route::shared(Stub::new("large")));
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "small");
+
+        let tool = ToolDefinition {
+            name: "lookup".into(),
+            description: "looks something up".into(),
+            parameters: serde_json::json!({"type": "object"}),
+        };
+        assert_eq!(ask(&router, &ChatOptions::default().with_tools(vec![tool])).await.unwrap(), "large");
+
+        let long = ChatMessage::user("word ".repeat(500));
+        let reply = router.chat(&[long], &ChatOptions::default()).await.unwrap();
+        assert_eq!(reply.content(), "large");
+    }
+
+    #[tokio::test]
+    async fn failing_routes_are_ejected_until_the_cooldown_ends() {
+        let (a, b) = (Stub::failing("a", unavailable), Stub::new("b"));
+        let router = RoutingClient::new(Strategy::Failover)
+            .with_health_policy(HealthPolicy { failure_threshold: 2, ejection: Duration::from_millis(100) })
+            .route(Route::shared(a.clone()))
+            .route(Route::shared(b.clone()));
+
+        for _ in 0..2 {
+            assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "b");
+        }
+        assert!(router.health()[0].ejected_for.is_some());
+        // ejected: the healthy route is tried first
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "b");
+        assert_eq!(a.calls(), 2);
+
+        // request errors don't count against a route
+        a.fail_with(Some(|| LlmError::InvalidRequest("bad".into())));
+        tokio::time::sleep(Duration::from_millis(150)).await;
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "b");
+        assert_eq!(a.calls(), 3);
+        assert_eq!(router.health()[0].consecutive_failures, 2);
+
+        a.fail_with(None);
+        assert_eq!(ask(&router, &ChatOptions::default()).await.unwrap(), "a");
+        assert_eq!(router.health()[0].consecutive_failures, 0);
+        assert!(router.health()[0].ejected_for.is_none());
+    }
+}
@@ -0,0 +1,165 @@
+use futures::{Stream, StreamExt, stream};
+use std::pin::Pin;
+
+use crate::client::{ToolCall, Usage};
+use crate::error::{LlmError, LlmResult};
+
+// incremental output of a streamed chat call.
+// a stream yields any number of deltas followed by exactly one `Done` record.
+// tool calls arrive in fragments on the wire, so they are only emitted once
+// fully assembled, right before `Done`.
+#[derive(Debug, Clone)]
+pub enum StreamEvent {
+    Delta(String),
+    ToolCalls(Vec<ToolCall>),
+    Done {
+        finish_reason: Option<String>,
+        usage: Option<Usage>,
+        // the model that answered, when the provider names it
+        model: Option<String>,
+    },
+}
+
+// dropping the stream drops the underlying HTTP response, which is how a long
+// generation gets cancelled.
+pub type ChatStream = Pin<Box<dyn Stream<Item = LlmResult<StreamEvent>> + Send>>;
+
+// splits a chunked HTTP body into lines.
+// both OpenAI server-sent events and ollama NDJSON are line oriented, but a
+// network chunk can end in the middle of a line, so we buffer until '\n'.
+pub(crate) fn lines<S, B, E>(body: S) -> impl Stream<Item = LlmResult<String>> + Send
+where
+    S: Stream<Item = Result<B, E>> + Send + Unpin,
+    B: AsRef<[u8]>,
+    E: Into<LlmError>,
+{
+    stream::unfold((body, Vec::new(), false), |(mut body, mut buffer, mut eof)| async move {
+        loop {
+            if let Some(pos) = buffer.iter().position(|b| *b == b'\n') {
+                let line: Vec<u8> = buffer.drain(..=pos).collect();
+                let line = String::from_utf8_lossy(&line).trim_end().to_string();
+                return Some((Ok(line), (body, buffer, eof)));
+            }
+            if eof {
+                if buffer.is_empty() {
+                    return None;
+                }
+                let line = String::from_utf8_lossy(&buffer).trim_end().to_string();
+                return Some((Ok(line), (body, Vec::new(), eof)));
+            }
+            match body.next().await {
+                Some(Ok(chunk)) => buffer.extend_from_slice(chunk.as_ref()),
+                Some(Err(e)) => return Some((Err(e.into()), (body, Vec::new(), true))),
+                None => eof = true,
+            }
+        }
+    })
+}
+
+// turns a line stream into a `ChatStream` using a provider specific parser.
+// a single line may produce several events (e.g. the last delta and `Done`).
+pub(crate) fn events<L, F>(lines: L, mut parse: F) -> ChatStream
+where
+    L: Stream<Item = LlmResult<String>> + Send + 'static,
+    F: FnMut(&str) -> LlmResult<Vec<StreamEvent>> + Send + 'static,
+{
+    let events = lines
+        .map(move |line| {
+            let parsed = line.and_then(|line| parse(&line));
+            let events: Vec<LlmResult<StreamEvent>> = match parsed {
+                Ok(events) => events.into_iter().map(Ok).collect(),
+                Err(e) => vec![Err(e)],
+            };
+            stream::iter(events)
+        })
+        .flatten();
+
+    Box::pin(terminated(Box::pin(events)))
+}
+
+// a body that ends without the provider's final record (`[DONE]`,
+// `message_stop`, `"done": true`, a finish reason) was cut off, so it ends in
+// an error instead of looking like a complete reply. a stream that already
+// failed is not reported twice.
+fn terminated<S>(events: S) -> impl Stream<Item = LlmResult<StreamEvent>> + Send
+where
+    S: Stream<Item = LlmResult<StreamEvent>> + Send + Unpin,
+{
+    stream::unfold(Some((events, false)), |state| async move {
+        let (mut events, settled) = state?;
+        match events.next().await {
+            Some(event) => {
+                let settled = settled || matches!(event, Ok(StreamEvent::Done { .. }) | Err(_));
+                Some((event, Some((events, settled))))
+            }
+            None if settled => None,
+            None => Some((Err(LlmError::MalformedResponse("stream ended before completion".into())), None)),
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunks(parts: &[&'static str]) -> impl Stream<Item = Result<&'static [u8], LlmError>> + Send + Unpin {
+        stream::iter(parts.iter().map(|part| Ok(part.as_bytes())).collect::<Vec<_>>())
+    }
+
+    fn done() -> StreamEvent {
+        StreamEvent::Done { finish_reason: Some("stop".into()), usage: None, model: None }
+    }
+
+    #[tokio::test]
+    async fn lines_are_reassembled_across_chunks() {
+        let lines: Vec<String> = lines(chunks(&["data: {\"a\"", ":1}\r\n\ndata: [DO", "NE]\n", "tail"]))
+            .map(|line| line.unwrap())
+            .collect()
+            .await;
+        assert_eq!(lines, ["data: {\"a\":1}", "", "data: [DONE]", "tail"]);
+    }
+
+    #[tokio::test]
+    async fn a_failing_body_ends_the_lines() {
+        let body = stream::iter(vec![Ok(&b"one\ntw"[..]), Err(LlmError::Timeout), Ok(&b"o\n"[..])]);
+        let lines: Vec<LlmResult<String>> = lines(body).collect().await;
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].as_deref().unwrap(), "one");
+        assert!(matches!(lines[1], Err(LlmError::Timeout)));
+    }
+
+    #[tokio::test]
+    async fn one_line_can_carry_several_events() {
+        let lines = stream::iter(vec![Ok("a".to_string()), Ok("b!".to_string())]);
+        let events: Vec<LlmResult<StreamEvent>> = events(lines, |line| {
+            let mut events = vec![StreamEvent::Delta(line.trim_end_matches('!').into())];
+            if line.ends_with('!') {
+                events.push(done());
+            }
+            Ok(events)
+        })
+        .collect()
+        .await;
+
+        assert_eq!(events.len(), 3);
+        assert!(matches!(&events[1], Ok(StreamEvent::Delta(text)) if text == "b"));
+        assert!(matches!(events[2], Ok(StreamEvent::Done { .. })));
+    }
+
+    #[tokio::test]
+    async fn streams_without_a_final_record_were_cut_off() {
+        let lines = stream::iter(vec![Ok("partial".to_string())]);
+        let events: Vec<LlmResult<StreamEvent>> = events(lines, |line| Ok(vec![StreamEvent::Delta(line.into())])).collect().await;
+        assert_eq!(events.len(), 2);
+        assert!(matches!(&events[1], Err(LlmError::MalformedResponse(message)) if message.contains("before completion")));
+    }
+
+    #[tokio::test]
+    async fn failed_streams_are_not_reported_twice() {
+        let lines = stream::iter(vec![Ok("{".to_string())]);
+        let events: Vec<LlmResult<StreamEvent>> =
+            events(lines, |_| Err(LlmError::MalformedResponse("bad json".into()))).collect().await;
+        assert_eq!(events.len(), 1);
+        assert!(matches!(&events[0], Err(LlmError::MalformedResponse(message)) if message == "bad json"));
+    }
+}
@@ -0,0 +1,210 @@
+use schemars::{JsonSchema, schema_for};
+use serde::de::DeserializeOwned;
+use serde_json::Value;
+
+use crate::client::{ChatMessage, ChatOptions, LlmClient, ResponseFormat};
+use crate::error::{LlmError, LlmResult};
+
+// asks the model for a `T` instead of free text.
+// the JSON schema of `T` is sent as the response format (and spelled out in
+// a system message for models that ignore it). a reply that isn't valid JSON,
+// violates the schema or doesn't deserialize is sent back to the model along
+// with the errors, up to `max_repairs` times.
+pub async fn complete_structured<T>(
+    client: &dyn LlmClient,
+    messages: &[ChatMessage],
+    options: &ChatOptions,
+    max_repairs: usize,
+) -> LlmResult<T>
+where
+    T: DeserializeOwned + JsonSchema,
+{
+    let schema = serde_json::to_value(schema_for!(T))?;
+    let validator = jsonschema::validator_for(&schema)
+        .map_err(|e| LlmError::MalformedResponse(format!("invalid schema for {}: {}", T::schema_name(), e)))?;
+
+    let options = options.clone().with_response_format(ResponseFormat::JsonSchema {
+        name: schema_name::<T>(),
+        schema: schema.clone(),
+    });
+
+    let mut conversation = messages.to_vec();
+    conversation.push(ChatMessage::system(format!(
+        "Respond only with a JSON value matching this JSON schema, without any other text:\n{}",
+        schema
+    )));
+
+    let mut errors = vec![];
+    for _ in 0..=max_repairs {
+        let completion = client.chat(&conversation, &options).await?;
+        let reply = completion.message.content;
+
+        let problems = match parse(&reply) {
+            Ok(value) => {
+                let violations: Vec<String> = validator
+                    .iter_errors(&value)
+                    .map(|e| format!("{} (at {})", e, e.instance_path))
+                    .collect();
+                if violations.is_empty() {
+                    match serde_json::from_value::<T>(value) {
+                        Ok(parsed) => return Ok(parsed),
+                        Err(e) => vec![e.to_string()],
+                    }
+                } else {
+                    violations
+                }
+            }
+            Err(e) => vec![format!("not valid JSON: {}", e)],
+        };
+
+        tracing::debug!(target: "llm::structured", schema = %T::schema_name(), errors = ?problems, "structured reply rejected");
+        conversation.push(ChatMessage::assistant(reply));
+        conversation.push(ChatMessage::user(format!(
+            "Your reply did not match the schema:\n- {}\nReply again with corrected JSON only.",
+            problems.join("\n- ")
+        )));
+        errors = problems;
+    }
+
+    Err(LlmError::InvalidStructuredOutput {
+        attempts: max_repairs + 1,
+        errors,
+    })
+}
+
+// models like to wrap JSON in a markdown code fence even when told not to
+fn parse(reply: &str) -> serde_json::Result<Value> {
+    let trimmed = reply.trim();
+    let unfenced = trimmed
+        .strip_prefix("```json")
+        .or_else(|| trimmed.strip_prefix("```"))
+        .and_then(|rest| rest.trim_end().strip_suffix("```"))
+        .unwrap_or(trimmed);
+    serde_json::from_str(unfenced.trim())
+}
+
+// OpenAI only accepts [a-zA-Z0-9_-] in schema names, schemars may produce
+// e.g. "Vec<Plan>"
+fn schema_name<T: JsonSchema>() -> String {
+    T::schema_name()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() || c == '-' { c } else { '_' })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::ChatCompletion;
+    use crate::streaming::ChatStream;
+    use async_trait::async_trait;
+    use serde::Deserialize;
+    use std::sync::Mutex;
+
+    #[derive(Debug, PartialEq, Deserialize, JsonSchema)]
+    struct Plan {
+        steps: Vec<String>,
+        done: bool,
+    }
+
+    // answers with scripted replies and keeps every conversation it was sent
+    struct Scripted {
+        replies: Mutex<Vec<&'static str>>,
+        seen: Mutex<Vec<(Vec<ChatMessage>, ChatOptions)>>,
+    }
+
+    impl Scripted {
+        fn new(replies: &[&'static str]) -> Self {
+            Scripted { replies: Mutex::new(replies.iter().rev().copied().collect()), seen: Mutex::new(vec![]) }
+        }
+
+        fn calls(&self) -> usize {
+            self.seen.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl LlmClient for Scripted {
+        fn provider(&self) -> &'static str {
+            "openai"
+        }
+
+        fn default_model(&self) -> &str {
+            "gpt-4o"
+        }
+
+        async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            self.seen.lock().unwrap().push((messages.to_vec(), options.clone()));
+            let reply = self.replies.lock().unwrap().pop().expect("no scripted reply left");
+            Ok(ChatCompletion {
+                message: ChatMessage::assistant(reply),
+                model: "gpt-4o".into(),
+                finish_reason: Some("stop".into()),
+                usage: None,
+            })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            unimplemented!("not streamed in these tests")
+        }
+    }
+
+    #[test]
+    fn code_fences_are_stripped() {
+        let expected = serde_json::json!({"done": true});
+        assert_eq!(parse("```json\n{\"done\": true}\n```").unwrap(), expected);
+        assert_eq!(parse("  ```\n{\"done\": true}```  ").unwrap(), expected);
+        assert_eq!(parse("{\"done\": true}").unwrap(), expected);
+        // an unclosed fence is not silently accepted
+        assert!(parse("```json\n{\"done\": true}").is_err());
+    }
+
+    #[test]
+    fn schema_names_are_sanitized() {
+        assert_eq!(schema_name::<Vec<Plan>>(), "Array_of_Plan");
+    }
+
+    #[tokio::test]
+    async fn a_fenced_reply_parses_and_sends_the_schema() {
+        let client = Scripted::new(&["```json\n{\"steps\": [\"look\"], \"done\": false}\n```"]);
+        let plan: Plan = complete_structured(&client, &[ChatMessage::user("plan")], &ChatOptions::default(), 0).await.unwrap();
+        assert_eq!(plan, Plan { steps: vec!["look".into()], done: false });
+
+        let seen = client.seen.lock().unwrap();
+        let (messages, options) = &seen[0];
+        assert!(matches!(&options.response_format, Some(ResponseFormat::JsonSchema { name, .. }) if name == "Plan"));
+        assert!(messages.last().unwrap().content.contains("\"steps\""));
+    }
+
+    #[tokio::test]
+    async fn invalid_replies_are_sent_back_for_repair() {
+        let client = Scripted::new(&["{\"steps\": \"look\"}", "{\"steps\": [\"look\"], \"done\": true}"]);
+        let plan: Plan = complete_structured(&client, &[ChatMessage::user("plan")], &ChatOptions::default(), 1).await.unwrap();
+        assert!(plan.done);
+        assert_eq!(client.calls(), 2);
+
+        // the repair round sees its own bad reply and what was wrong with it
+        let seen = client.seen.lock().unwrap();
+        let repair = &seen[1].0;
+        assert_eq!(repair[repair.len() - 2].content, "{\"steps\": \"look\"}");
+        let feedback = &repair[repair.len() - 1].content;
+        assert!(feedback.contains("did not match the schema"), "{}", feedback);
+        assert!(feedback.contains("/steps"), "{}", feedback);
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_repairs() {
+        let client = Scripted::new(&["not json", "still not json", "{\"done\": 1}", "never asked"]);
+        let result = complete_structured::<Plan>(&client, &[ChatMessage::user("plan")], &ChatOptions::default(), 2).await;
+        match result {
+            Err(LlmError::InvalidStructuredOutput { attempts, errors }) => {
+                assert_eq!(attempts, 3);
+                // the errors of the last attempt
+                assert!(!errors.is_empty());
+                assert!(errors.iter().all(|e| !e.starts_with("not valid JSON")), "{:?}", errors);
+            }
+            other => panic!("expected InvalidStructuredOutput, got {:?}", other.map(|_| ())),
+        }
+        assert_eq!(client.calls(), 3);
+    }
+}
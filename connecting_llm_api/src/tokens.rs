@@ -0,0 +1,192 @@
+use tiktoken_rs::{CoreBPE, cl100k_base_singleton, o200k_base_singleton, tokenizer::{Tokenizer, get_tokenizer}};
+
+use crate::client::{ChatMessage, ContentPart};
+
+// every chat message costs a few tokens of framing on top of its content
+// (role markers, separators), and the reply is primed with a few more.
+const TOKENS_PER_MESSAGE: usize = 4;
+const TOKENS_PER_REPLY: usize = 3;
+// providers bill images and documents by resolution or page count, which the
+// request doesn't tell us; this is roughly one high-detail 1024px image
+const TOKENS_PER_ATTACHMENT: usize = 765;
+
+// fallback when a model doesn't define a completion budget of its own
+pub const DEFAULT_COMPLETION_RESERVE: usize = 1024;
+
+pub trait TokenCounter: Send + Sync {
+    fn count(&self, text: &str) -> usize;
+
+    fn count_message(&self, message: &ChatMessage) -> usize {
+        let tool_calls: usize = message
+            .tool_calls
+            .iter()
+            .map(|call| self.count(&call.name) + self.count(&call.arguments.to_string()))
+            .sum();
+        let parts: usize = message
+            .parts
+            .iter()
+            .map(|part| match part {
+                ContentPart::Text { text } => self.count(text),
+                ContentPart::Image { .. } | ContentPart::File { .. } => TOKENS_PER_ATTACHMENT,
+            })
+            .sum();
+        TOKENS_PER_MESSAGE + self.count(message.role.as_str()) + self.count(&message.content) + parts + tool_calls
+    }
+
+    fn count_messages(&self, messages: &[ChatMessage]) -> usize {
+        messages.iter().map(|m| self.count_message(m)).sum::<usize>() + TOKENS_PER_REPLY
+    }
+}
+
+// exact counts for OpenAI models, using the same BPE encodings as the API
+pub struct BpeCounter {
+    bpe: &'static CoreBPE,
+}
+
+impl BpeCounter {
+    pub fn for_model(model: &str) -> Self {
+        let bpe = match get_tokenizer(model) {
+            Some(Tokenizer::O200kBase) => o200k_base_singleton(),
+            _ => cl100k_base_singleton(),
+        };
+        BpeCounter { bpe }
+    }
+}
+
+impl TokenCounter for BpeCounter {
+    fn count(&self, text: &str) -> usize {
+        self.bpe.encode_with_special_tokens(text).len()
+    }
+}
+
+// approximation for models whose tokenizer we don't ship (most ollama
+// models); roughly four characters per token for English text and code.
+pub struct HeuristicCounter {
+    pub chars_per_token: f32,
+}
+
+impl Default for HeuristicCounter {
+    fn default() -> Self {
+        HeuristicCounter { chars_per_token: 4.0 }
+    }
+}
+
+impl TokenCounter for HeuristicCounter {
+    fn count(&self, text: &str) -> usize {
+        (text.chars().count() as f32 / self.chars_per_token).ceil() as usize
+    }
+}
+
+// picks the most accurate counter available for a provider/model pair
+pub fn counter_for(provider: &str, model: &str) -> Box<dyn TokenCounter> {
+    if provider == "openai" || get_tokenizer(model).is_some() {
+        Box::new(BpeCounter::for_model(model))
+    } else {
+        Box::new(HeuristicCounter::default())
+    }
+}
+
+// context window sizes in tokens, matched by model name prefix.
+// more specific prefixes must come before shorter ones.
+const CONTEXT_WINDOWS: &[(&str, usize)] = &[
+    ("gpt-4o", 128_000),
+    ("gpt-4.1", 1_047_576),
+    ("gpt-4-turbo", 128_000),
+    ("gpt-4-32k", 32_768),
+    ("gpt-4", 8_192),
+    ("gpt-3.5-turbo", 16_385),
+    ("o1", 200_000),
+    ("o3", 200_000),
+    ("o4", 200_000),
+    ("claude", 200_000),
+    ("gemini-1.5-pro", 2_097_152),
+    ("gemini", 1_048_576),
+    ("llama3.1", 131_072),
+    ("llama3.2", 131_072),
+    ("llama3.3", 131_072),
+    ("llama3", 8_192),
+    ("llama2", 4_096),
+    ("mistral-nemo", 131_072),
+    ("mistral", 32_768),
+    ("mixtral", 32_768),
+    ("codellama", 16_384),
+    ("qwen2.5", 32_768),
+    ("qwen3", 40_960),
+    ("phi3", 4_096),
+    ("phi4", 16_384),
+    ("gemma2", 8_192),
+    ("gemma3", 131_072),
+    ("deepseek-r1", 131_072),
+];
+
+pub const DEFAULT_CONTEXT_WINDOW: usize = 4_096;
+
+pub fn context_window(model: &str) -> usize {
+    // ollama tags look like "mistral:7b-instruct", OpenAI snapshots like "gpt-4o-2024-08-06"
+    let name = model.rsplit('/').next().unwrap_or(model);
+    CONTEXT_WINDOWS
+        .iter()
+        .find(|(prefix, _)| name.starts_with(prefix))
+        .map(|(_, size)| *size)
+        .unwrap_or(DEFAULT_CONTEXT_WINDOW)
+}
+
+// how many prompt tokens fit once the completion has room to be generated
+pub fn prompt_budget(model: &str, max_tokens: Option<u32>) -> usize {
+    let reserve = max_tokens.map(|t| t as usize).unwrap_or(DEFAULT_COMPLETION_RESERVE);
+    context_window(model).saturating_sub(reserve)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn context_windows_match_the_most_specific_prefix() {
+        assert_eq!(context_window("gpt-4o-2024-08-06"), 128_000);
+        assert_eq!(context_window("gpt-4-0613"), 8_192);
+        assert_eq!(context_window("gpt-4-turbo-preview"), 128_000);
+        assert_eq!(context_window("llama3.1:8b"), 131_072);
+        assert_eq!(context_window("llama3:8b-instruct"), 8_192);
+        assert_eq!(context_window("openrouter/mistral-nemo"), 131_072);
+        assert_eq!(context_window("my-finetune"), DEFAULT_CONTEXT_WINDOW);
+    }
+
+    #[test]
+    fn the_completion_is_reserved_from_the_window() {
+        assert_eq!(prompt_budget("gpt-4", Some(1_000)), 7_192);
+        assert_eq!(prompt_budget("gpt-4", None), 8_192 - DEFAULT_COMPLETION_RESERVE);
+        assert_eq!(prompt_budget("phi3", Some(10_000)), 0);
+    }
+
+    #[test]
+    fn messages_cost_their_framing_parts_and_tool_calls() {
+        let counter = HeuristicCounter { chars_per_token: 1.0 };
+        assert_eq!(counter.count("abcd"), 4);
+        assert_eq!(HeuristicCounter::default().count("abcdefghi"), 3);
+
+        let plain = ChatMessage::user("hi");
+        assert_eq!(counter.count_message(&plain), TOKENS_PER_MESSAGE + 4 + 2);
+
+        let with_parts = ChatMessage::user("hi")
+            .with_part(ContentPart::text("more"))
+            .with_part(ContentPart::image_url("https://example.com/cat.png"));
+        assert_eq!(counter.count_message(&with_parts), counter.count_message(&plain) + 4 + TOKENS_PER_ATTACHMENT);
+
+        let calling = ChatMessage {
+            tool_calls: vec![crate::client::ToolCall { id: "c".into(), name: "f".into(), arguments: serde_json::json!({}) }],
+            ..ChatMessage::assistant("")
+        };
+        assert_eq!(counter.count_message(&calling), TOKENS_PER_MESSAGE + 9 + 1 + 2);
+
+        assert_eq!(counter.count_messages(&[plain.clone(), plain]), 2 * (TOKENS_PER_MESSAGE + 6) + TOKENS_PER_REPLY);
+    }
+
+    #[test]
+    fn openai_models_are_counted_exactly() {
+        assert_eq!(counter_for("openai", "gpt-4o").count("hello world"), 2);
+        assert_eq!(counter_for("ollama", "gpt-4").count("hello world"), 2);
+        // ollama models fall back to the estimate
+        assert_eq!(counter_for("ollama", "mistral").count("hello world"), 3);
+    }
+}
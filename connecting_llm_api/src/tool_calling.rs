@@ -0,0 +1,193 @@
+use serde_json::Value;
+use tool_using_agents::secure_tool_functions::{call_tool_by_name, ToolOutput, ToolRegistery};
+
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient, ToolCall, ToolDefinition};
+use crate::error::{LlmError, LlmResult};
+
+// describing the registered tools in the shape LLM function calling expects
+pub async fn tool_definitions(registry: &ToolRegistery) -> Vec<ToolDefinition> {
+    let tools = registry.read().await;
+    let mut definitions: Vec<ToolDefinition> = tools
+        .values()
+        .map(|tool| ToolDefinition {
+            name: tool.name().to_string(),
+            description: tool.description().to_string(),
+            parameters: tool.parameters(),
+        })
+        .collect();
+    // keep the request body stable regardless of HashMap ordering
+    definitions.sort_by(|a, b| a.name.cmp(&b.name));
+    definitions
+}
+
+// dispatching a single tool call through the registry.
+// unknown tools are reported back to the model instead of aborting the loop,
+// so it gets a chance to correct itself.
+pub async fn dispatch_tool_call(registry: &ToolRegistery, call: &ToolCall) -> ChatMessage {
+    let output = call_tool_by_name(registry, &call.name, call.arguments.clone())
+        .await
+        .unwrap_or_else(|| ToolOutput {
+            result: Value::Null,
+            success: false,
+            message: Some(format!("Tool not found: {}", call.name)),
+        });
+
+    let content = serde_json::to_string(&output).unwrap_or_default();
+    ChatMessage::tool(call.id.clone(), content)
+}
+
+// the function-calling loop.
+// sends the conversation, runs every requested tool, appends the results as
+// `tool` messages and repeats until the model answers without tool calls.
+// `messages` is extended in place so the caller keeps the full transcript.
+pub async fn run_with_tools(
+    client: &dyn LlmClient,
+    messages: &mut Vec<ChatMessage>,
+    options: &ChatOptions,
+    registry: &ToolRegistery,
+    max_rounds: usize,
+) -> LlmResult<ChatCompletion> {
+    let mut options = options.clone();
+    if options.tools.is_empty() {
+        options.tools = tool_definitions(registry).await;
+    }
+
+    for _ in 0..max_rounds {
+        let completion = client.chat(messages, &options).await?;
+        messages.push(completion.message.clone());
+
+        if completion.tool_calls().is_empty() {
+            return Ok(completion);
+        }
+
+        for call in completion.tool_calls() {
+            let reply = dispatch_tool_call(registry, call).await;
+            messages.push(reply);
+        }
+    }
+
+    Err(LlmError::ToolRoundsExceeded(max_rounds))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::client::Role;
+    use crate::streaming::ChatStream;
+    use async_trait::async_trait;
+    use serde_json::json;
+    use std::{collections::HashMap, sync::{Arc, Mutex}};
+    use tokio::sync::RwLock;
+    use tool_using_agents::secure_tool_functions::{register_tool, LengthTool};
+
+    // answers with scripted assistant messages and counts the rounds
+    struct Scripted {
+        replies: Mutex<Vec<ChatMessage>>,
+        seen: Mutex<Vec<ChatOptions>>,
+    }
+
+    impl Scripted {
+        fn new(replies: Vec<ChatMessage>) -> Self {
+            Scripted { replies: Mutex::new(replies.into_iter().rev().collect()), seen: Mutex::new(vec![]) }
+        }
+
+        fn calls(&self) -> usize {
+            self.seen.lock().unwrap().len()
+        }
+    }
+
+    #[async_trait]
+    impl LlmClient for Scripted {
+        fn provider(&self) -> &'static str {
+            "openai"
+        }
+
+        fn default_model(&self) -> &str {
+            "gpt-4o"
+        }
+
+        async fn chat(&self, _messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            self.seen.lock().unwrap().push(options.clone());
+            let message = self.replies.lock().unwrap().pop().expect("no scripted reply left");
+            Ok(ChatCompletion { message, model: "gpt-4o".into(), finish_reason: None, usage: None })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            unimplemented!("not streamed in these tests")
+        }
+    }
+
+    fn calling(calls: &[(&str, &str, serde_json::Value)]) -> ChatMessage {
+        ChatMessage {
+            tool_calls: calls
+                .iter()
+                .map(|(id, name, arguments)| ToolCall { id: id.to_string(), name: name.to_string(), arguments: arguments.clone() })
+                .collect(),
+            ..ChatMessage::assistant("")
+        }
+    }
+
+    async fn registry() -> ToolRegistery {
+        let registry: ToolRegistery = Arc::new(RwLock::new(HashMap::new()));
+        register_tool(&registry, Arc::new(LengthTool)).await;
+        registry
+    }
+
+    #[tokio::test]
+    async fn unknown_tools_are_reported_to_the_model() {
+        let registry = registry().await;
+        let call = ToolCall { id: "call_1".into(), name: "teleport".into(), arguments: json!({}) };
+        let reply = dispatch_tool_call(&registry, &call).await;
+
+        assert_eq!(reply.role, Role::Tool);
+        assert_eq!(reply.tool_call_id.as_deref(), Some("call_1"));
+        let output: Value = serde_json::from_str(&reply.content).unwrap();
+        assert_eq!(output["success"], json!(false));
+        assert_eq!(output["message"], json!("Tool not found: teleport"));
+    }
+
+    #[tokio::test]
+    async fn loops_until_the_model_stops_calling_tools() {
+        let registry = registry().await;
+        let client = Scripted::new(vec![
+            calling(&[("call_1", "length", json!({"text": "hello"})), ("call_2", "teleport", json!({}))]),
+            calling(&[("call_3", "length", json!({"text": "hi"}))]),
+            ChatMessage::assistant("hello has 5 letters"),
+        ]);
+        let mut messages = vec![ChatMessage::user("how long is hello?")];
+
+        let answer = run_with_tools(&client, &mut messages, &ChatOptions::default(), &registry, 5).await.unwrap();
+        assert_eq!(answer.content(), "hello has 5 letters");
+        assert_eq!(client.calls(), 3);
+
+        // the registry's tools were advertised on every round
+        assert!(client.seen.lock().unwrap().iter().all(|options| options.tools.len() == 1 && options.tools[0].name == "length"));
+
+        // user, assistant, two tool replies, assistant, one tool reply, answer
+        let roles: Vec<Role> = messages.iter().map(|m| m.role).collect();
+        assert_eq!(
+            roles,
+            [Role::User, Role::Assistant, Role::Tool, Role::Tool, Role::Assistant, Role::Tool, Role::Assistant]
+        );
+        let output: Value = serde_json::from_str(&messages[2].content).unwrap();
+        assert_eq!(output["result"], json!(5));
+        assert_eq!(messages[3].tool_call_id.as_deref(), Some("call_2"));
+        assert!(messages[3].content.contains("Tool not found"));
+    }
+
+    #[tokio::test]
+    async fn gives_up_after_max_rounds() {
+        let registry = registry().await;
+        let client = Scripted::new(vec![
+            calling(&[("call_1", "length", json!({"text": "a"}))]),
+            calling(&[("call_2", "length", json!({"text": "b"}))]),
+        ]);
+        let mut messages = vec![ChatMessage::user("keep going")];
+
+        let result = run_with_tools(&client, &mut messages, &ChatOptions::default(), &registry, 2).await;
+        assert!(matches!(result, Err(LlmError::ToolRoundsExceeded(2))));
+        assert_eq!(client.calls(), 2);
+        // the transcript keeps what happened before giving up
+        assert_eq!(messages.len(), 5);
+    }
+}
@@ -0,0 +1,79 @@
+use reqwest::{Client, RequestBuilder, Response};
+use std::{sync::Arc, time::Duration};
+
+use crate::cassette::{self, Cassette, CassetteMode, Interaction};
+use crate::error::{self, LlmResult};
+
+// the HTTP layer every provider sends through.
+// without a cassette it is a plain `reqwest::Client`; with one, traffic is
+// recorded to or replayed from the cassette file.
+#[derive(Clone)]
+pub(crate) struct HttpTransport {
+    client: Client,
+    cassette: Option<Arc<Cassette>>,
+    timeout: Duration,
+}
+
+impl HttpTransport {
+    // picks up the process-wide cassette from LLM_CASSETTE, if any
+    // `timeout` bounds a whole exchange in `send`, but only the silence
+    // between chunks in `send_streaming`
+    pub(crate) fn new(timeout: Duration) -> LlmResult<Self> {
+        let client = Client::builder()
+            .connect_timeout(timeout)
+            .read_timeout(timeout)
+            .build()?;
+        Ok(HttpTransport {
+            client,
+            cassette: Cassette::from_env(),
+            timeout,
+        })
+    }
+
+    pub(crate) fn with_cassette(mut self, cassette: Arc<Cassette>) -> Self {
+        self.cassette = Some(cassette);
+        self
+    }
+
+    pub(crate) fn client(&self) -> &Client {
+        &self.client
+    }
+
+    // sends the request and maps non-success statuses onto `LlmError`
+    pub(crate) async fn send(&self, request: RequestBuilder) -> LlmResult<Response> {
+        let response = self.execute(request.timeout(self.timeout)).await?;
+        error::check(response).await
+    }
+
+    // like `send`, for a body that is read as a stream. a long generation may
+    // take far longer than the timeout, so only an idle connection times out.
+    pub(crate) async fn send_streaming(&self, request: RequestBuilder) -> LlmResult<Response> {
+        let response = self.execute(request).await?;
+        error::check(response).await
+    }
+
+    async fn execute(&self, request: RequestBuilder) -> LlmResult<Response> {
+        let Some(cassette) = &self.cassette else {
+            return Ok(request.send().await?);
+        };
+
+        let request = request.build()?;
+        let recorded_request = cassette::recorded_request(&request);
+
+        match cassette.mode() {
+            CassetteMode::Replay => cassette::into_response(cassette.find(&recorded_request)?),
+            CassetteMode::Record => {
+                let response = self.client.execute(request).await?;
+                let recorded_response = cassette::recorded_response(response).await?;
+                let interaction = Interaction {
+                    request: recorded_request,
+                    response: recorded_response.clone(),
+                };
+                if let Err(e) = cassette.store(interaction) {
+                    tracing::warn!(target: "llm::cassette", error = %e, "failed to write cassette");
+                }
+                cassette::into_response(recorded_response)
+            }
+        }
+    }
+}
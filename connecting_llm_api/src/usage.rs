@@ -0,0 +1,601 @@
+use async_trait::async_trait;
+use futures::StreamExt;
+use serde::{Deserialize, Serialize};
+use std::{
+    collections::{BTreeMap, HashMap, HashSet, VecDeque},
+    sync::{Arc, Mutex, OnceLock},
+    time::{Duration, Instant, SystemTime},
+};
+
+use crate::client::{ChatCompletion, ChatMessage, ChatOptions, LlmClient, Usage};
+use crate::error::{LlmError, LlmResult};
+use crate::streaming::{ChatStream, StreamEvent};
+use crate::tokens::{self, DEFAULT_COMPLETION_RESERVE};
+
+// token and cost accounting.
+// every call made through a `MeteredClient` is recorded with its tokens,
+// latency and cost, tagged with the agent and session that made it. totals can
+// be grouped per agent, session or model, and per-agent budgets warn (soft) or
+// reject further calls (hard) once the spend passes them. a call holds its
+// estimated cost against the hard budget while it runs.
+
+// USD per million tokens
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct Pricing {
+    pub input_per_mtok: f64,
+    pub output_per_mtok: f64,
+}
+
+impl Pricing {
+    pub fn new(input_per_mtok: f64, output_per_mtok: f64) -> Self {
+        Pricing { input_per_mtok, output_per_mtok }
+    }
+
+    pub fn cost(&self, usage: &Usage) -> f64 {
+        (usage.prompt_tokens as f64 * self.input_per_mtok + usage.completion_tokens as f64 * self.output_per_mtok) / 1_000_000.0
+    }
+}
+
+// list prices, matched by the longest model name prefix
+const DEFAULT_PRICES: &[(&str, f64, f64)] = &[
+    ("gpt-4o-mini", 0.15, 0.60),
+    ("gpt-4o", 2.50, 10.00),
+    ("gpt-4.1-nano", 0.10, 0.40),
+    ("gpt-4.1-mini", 0.40, 1.60),
+    ("gpt-4.1", 2.00, 8.00),
+    ("gpt-4-turbo", 10.00, 30.00),
+    ("gpt-4", 30.00, 60.00),
+    ("gpt-3.5-turbo", 0.50, 1.50),
+    ("o1-mini", 1.10, 4.40),
+    ("o1", 15.00, 60.00),
+    ("o3-mini", 1.10, 4.40),
+    ("o4-mini", 1.10, 4.40),
+    ("text-embedding-3-small", 0.02, 0.0),
+    ("text-embedding-3-large", 0.13, 0.0),
+    ("claude-opus", 15.00, 75.00),
+    ("claude-3-opus", 15.00, 75.00),
+    ("claude-sonnet", 3.00, 15.00),
+    ("claude-3-7-sonnet", 3.00, 15.00),
+    ("claude-3-5-sonnet", 3.00, 15.00),
+    ("claude-3-5-haiku", 0.80, 4.00),
+    ("claude-3-haiku", 0.25, 1.25),
+    ("claude-haiku", 1.00, 5.00),
+    ("gemini-2.5-pro", 1.25, 10.00),
+    ("gemini-2.5-flash", 0.30, 2.50),
+    ("gemini-2.0-flash", 0.10, 0.40),
+    ("gemini-1.5-pro", 1.25, 5.00),
+    ("gemini-1.5-flash", 0.075, 0.30),
+];
+
+#[derive(Debug, Clone)]
+pub struct PriceTable {
+    // (prefix, pricing); overrides are kept in front so they win ties
+    prices: Vec<(String, Pricing)>,
+    // providers that never cost anything, e.g. a local ollama
+    free_providers: HashSet<String>,
+}
+
+impl Default for PriceTable {
+    fn default() -> Self {
+        PriceTable {
+            prices: DEFAULT_PRICES
+                .iter()
+                .map(|(prefix, input, output)| (prefix.to_string(), Pricing::new(*input, *output)))
+                .collect(),
+            free_providers: HashSet::from(["ollama".to_string()]),
+        }
+    }
+}
+
+impl PriceTable {
+    // a table without any prices
+    pub fn empty() -> Self {
+        PriceTable {
+            prices: vec![],
+            free_providers: HashSet::new(),
+        }
+    }
+
+    // replaces the price for exactly this prefix; longer prefixes such as
+    // "gpt-4o-mini" for "gpt-4o" keep their own price
+    pub fn with_price(mut self, prefix: impl Into<String>, pricing: Pricing) -> Self {
+        self.prices.insert(0, (prefix.into(), pricing));
+        self
+    }
+
+    pub fn with_free_provider(mut self, provider: impl Into<String>) -> Self {
+        self.free_providers.insert(provider.into());
+        self
+    }
+
+    // None when the model has no known price
+    pub fn cost(&self, provider: &str, model: &str, usage: &Usage) -> Option<f64> {
+        if self.free_providers.contains(provider) {
+            return Some(0.0);
+        }
+        let name = model.rsplit('/').next().unwrap_or(model);
+        self.prices
+            .iter()
+            .filter(|(prefix, _)| name.starts_with(prefix.as_str()))
+            .min_by_key(|(prefix, _)| std::cmp::Reverse(prefix.len()))
+            .map(|(_, pricing)| pricing.cost(usage))
+    }
+}
+
+// one LLM call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct UsageRecord {
+    pub agent: String,
+    pub session: String,
+    pub provider: String,
+    pub model: String,
+    pub prompt_tokens: u32,
+    pub completion_tokens: u32,
+    pub latency: Duration,
+    // USD; 0 when the model has no known price
+    pub cost: f64,
+    pub at: SystemTime,
+}
+
+#[derive(Debug, Clone, Copy, Default, Serialize, Deserialize)]
+pub struct UsageTotals {
+    pub requests: u64,
+    pub prompt_tokens: u64,
+    pub completion_tokens: u64,
+    pub cost: f64,
+    pub latency: Duration,
+}
+
+impl UsageTotals {
+    fn add(&mut self, record: &UsageRecord) {
+        self.requests += 1;
+        self.prompt_tokens += record.prompt_tokens as u64;
+        self.completion_tokens += record.completion_tokens as u64;
+        self.cost += record.cost;
+        self.latency += record.latency;
+    }
+
+    pub fn average_latency(&self) -> Duration {
+        if self.requests == 0 {
+            return Duration::ZERO;
+        }
+        self.latency / self.requests as u32
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum GroupBy {
+    Agent,
+    Session,
+    Model,
+}
+
+// USD limits per agent; None = no limit
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Budget {
+    // log a warning once the spend passes this
+    pub soft: Option<f64>,
+    // reject further calls once the spend reaches this
+    pub hard: Option<f64>,
+}
+
+impl Budget {
+    pub fn soft(limit: f64) -> Self {
+        Budget { soft: Some(limit), hard: None }
+    }
+
+    pub fn hard(limit: f64) -> Self {
+        Budget { soft: None, hard: Some(limit) }
+    }
+
+    pub fn with_soft(mut self, limit: f64) -> Self {
+        self.soft = Some(limit);
+        self
+    }
+}
+
+// running totals, so a long-lived tracker doesn't grow with every call.
+// only the latest records are kept, for inspection.
+#[derive(Default)]
+struct Ledger {
+    all: UsageTotals,
+    by_agent: HashMap<String, UsageTotals>,
+    by_session: HashMap<String, UsageTotals>,
+    by_model: HashMap<String, UsageTotals>,
+    // estimated cost of the calls each agent has in flight
+    reserved: HashMap<String, f64>,
+    recent: VecDeque<UsageRecord>,
+    // agents already warned about their soft budget
+    warned: HashSet<String>,
+    // (agent, model) pairs already warned about a missing price
+    unpriced: HashSet<(String, String)>,
+}
+
+impl Ledger {
+    // spent plus reserved
+    fn committed(&self, agent: &str) -> f64 {
+        self.by_agent.get(agent).map_or(0.0, |totals| totals.cost) + self.reserved.get(agent).copied().unwrap_or_default()
+    }
+}
+
+// how many records `records()` returns by default
+const DEFAULT_HISTORY: usize = 1000;
+
+// shared by every `MeteredClient` that should count against the same budgets
+pub struct UsageTracker {
+    prices: PriceTable,
+    budgets: HashMap<String, Budget>,
+    history: usize,
+    ledger: Mutex<Ledger>,
+}
+
+impl Default for UsageTracker {
+    fn default() -> Self {
+        Self::new(PriceTable::default())
+    }
+}
+
+impl UsageTracker {
+    pub fn new(prices: PriceTable) -> Self {
+        UsageTracker {
+            prices,
+            budgets: HashMap::new(),
+            history: DEFAULT_HISTORY,
+            ledger: Mutex::new(Ledger::default()),
+        }
+    }
+
+    // the tracker `send_to_openai` and `send_to_ollama` record into
+    pub fn global() -> Arc<UsageTracker> {
+        static GLOBAL: OnceLock<Arc<UsageTracker>> = OnceLock::new();
+        GLOBAL.get_or_init(|| Arc::new(UsageTracker::default())).clone()
+    }
+
+    pub fn with_budget(mut self, agent: impl Into<String>, budget: Budget) -> Self {
+        self.budgets.insert(agent.into(), budget);
+        self
+    }
+
+    // how many of the latest records to keep; totals cover every call
+    pub fn with_history(mut self, records: usize) -> Self {
+        self.history = records;
+        self
+    }
+
+    // the latest records, oldest first
+    pub fn records(&self) -> Vec<UsageRecord> {
+        self.ledger.lock().unwrap().recent.iter().cloned().collect()
+    }
+
+    pub fn spent(&self, agent: &str) -> f64 {
+        self.ledger.lock().unwrap().by_agent.get(agent).map_or(0.0, |totals| totals.cost)
+    }
+
+    pub fn totals(&self) -> UsageTotals {
+        self.ledger.lock().unwrap().all
+    }
+
+    pub fn totals_by(&self, group: GroupBy) -> BTreeMap<String, UsageTotals> {
+        let ledger = self.ledger.lock().unwrap();
+        let totals = match group {
+            GroupBy::Agent => &ledger.by_agent,
+            GroupBy::Session => &ledger.by_session,
+            GroupBy::Model => &ledger.by_model,
+        };
+        totals.iter().map(|(key, totals)| (key.clone(), *totals)).collect()
+    }
+
+    // fails once `agent` has used up its hard budget, counting calls in flight
+    pub fn check_budget(&self, agent: &str) -> LlmResult<()> {
+        self.admit(&self.ledger.lock().unwrap(), agent, 0.0)
+    }
+
+    // checks the hard budget and holds `estimated_cost` against it until the
+    // reservation is dropped, so concurrent calls can't all pass the same check
+    pub fn reserve(self: &Arc<Self>, agent: &str, estimated_cost: f64) -> LlmResult<Reservation> {
+        let mut ledger = self.ledger.lock().unwrap();
+        self.admit(&ledger, agent, estimated_cost)?;
+        let held = self.budgets.get(agent).is_some_and(|budget| budget.hard.is_some()) && estimated_cost > 0.0;
+        if held {
+            *ledger.reserved.entry(agent.to_string()).or_default() += estimated_cost;
+        }
+        Ok(Reservation {
+            tracker: self.clone(),
+            agent: agent.to_string(),
+            cost: if held { estimated_cost } else { 0.0 },
+        })
+    }
+
+    fn admit(&self, ledger: &Ledger, agent: &str, estimated_cost: f64) -> LlmResult<()> {
+        let Some(limit) = self.budgets.get(agent).and_then(|budget| budget.hard) else {
+            return Ok(());
+        };
+        let spent = ledger.committed(agent);
+        if spent >= limit || (estimated_cost > 0.0 && spent + estimated_cost > limit) {
+            return Err(LlmError::BudgetExceeded {
+                scope: agent.to_string(),
+                spent,
+                limit,
+            });
+        }
+        Ok(())
+    }
+
+    // what a call is expected to cost at most: the prompt plus `max_tokens`
+    // (or the default completion reserve); 0 for unpriced models
+    pub fn estimate(&self, provider: &str, model: &str, messages: &[ChatMessage], options: &ChatOptions) -> f64 {
+        let usage = Usage {
+            prompt_tokens: tokens::counter_for(provider, model).count_messages(messages) as u32,
+            completion_tokens: options.max_tokens.unwrap_or(DEFAULT_COMPLETION_RESERVE as u32),
+        };
+        self.prices.cost(provider, model, &usage).unwrap_or_default()
+    }
+
+    pub fn record(&self, agent: &str, session: &str, provider: &str, model: &str, usage: Usage, latency: Duration) -> UsageRecord {
+        let price = self.prices.cost(provider, model, &usage);
+        let record = UsageRecord {
+            agent: agent.to_string(),
+            session: session.to_string(),
+            provider: provider.to_string(),
+            model: model.to_string(),
+            prompt_tokens: usage.prompt_tokens,
+            completion_tokens: usage.completion_tokens,
+            latency,
+            cost: price.unwrap_or_default(),
+            at: SystemTime::now(),
+        };
+        tracing::debug!(
+            target: "llm::usage",
+            agent,
+            session,
+            model,
+            prompt_tokens = usage.prompt_tokens,
+            completion_tokens = usage.completion_tokens,
+            latency_ms = latency.as_millis() as u64,
+            cost = record.cost,
+            "LLM call"
+        );
+
+        let mut ledger = self.ledger.lock().unwrap();
+        ledger.all.add(&record);
+        ledger.by_agent.entry(record.agent.clone()).or_default().add(&record);
+        ledger.by_session.entry(record.session.clone()).or_default().add(&record);
+        ledger.by_model.entry(record.model.clone()).or_default().add(&record);
+        if self.history > 0 {
+            if ledger.recent.len() == self.history {
+                ledger.recent.pop_front();
+            }
+            ledger.recent.push_back(record.clone());
+        }
+
+        let budget = self.budgets.get(agent).copied().unwrap_or_default();
+        if price.is_none() {
+            // a budget can't hold if the calls count as free
+            if (budget.soft.is_some() || budget.hard.is_some()) && ledger.unpriced.insert((agent.to_string(), model.to_string())) {
+                tracing::warn!(target: "llm::usage", agent, provider, model, "no price known for model, its calls don't count against the budget");
+            } else {
+                tracing::debug!(target: "llm::usage", provider, model, "no price known for model");
+            }
+        }
+        if let Some(limit) = budget.soft {
+            let spent = ledger.by_agent.get(agent).map_or(0.0, |totals| totals.cost);
+            if spent >= limit && ledger.warned.insert(agent.to_string()) {
+                tracing::warn!(target: "llm::usage", agent, spent, limit, "agent passed its soft budget");
+            }
+        }
+        record
+    }
+
+    fn release(&self, agent: &str, cost: f64) {
+        let mut ledger = self.ledger.lock().unwrap();
+        if let Some(reserved) = ledger.reserved.get_mut(agent) {
+            *reserved -= cost;
+            if *reserved <= 0.0 {
+                ledger.reserved.remove(agent);
+            }
+        }
+    }
+}
+
+// the estimated cost of a call in flight, held against the agent's hard
+// budget until dropped
+pub struct Reservation {
+    tracker: Arc<UsageTracker>,
+    agent: String,
+    cost: f64,
+}
+
+impl Drop for Reservation {
+    fn drop(&mut self) {
+        if self.cost > 0.0 {
+            self.tracker.release(&self.agent, self.cost);
+        }
+    }
+}
+
+// wraps any client so every call is recorded against `agent` and `session`
+// and refused once the agent's hard budget is spent.
+pub struct MeteredClient<C> {
+    inner: C,
+    tracker: Arc<UsageTracker>,
+    agent: String,
+    session: String,
+}
+
+impl<C: LlmClient> MeteredClient<C> {
+    pub fn new(inner: C, tracker: Arc<UsageTracker>, agent: impl Into<String>) -> Self {
+        MeteredClient {
+            inner,
+            tracker,
+            agent: agent.into(),
+            session: String::new(),
+        }
+    }
+
+    pub fn with_session(mut self, session: impl Into<String>) -> Self {
+        self.session = session.into();
+        self
+    }
+
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+
+    pub fn tracker(&self) -> &Arc<UsageTracker> {
+        &self.tracker
+    }
+
+    fn reserve(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<Reservation> {
+        let model = options.model.as_deref().unwrap_or(self.inner.default_model());
+        let estimate = self.tracker.estimate(self.inner.provider(), model, messages, options);
+        self.tracker.reserve(&self.agent, estimate)
+    }
+}
+
+#[async_trait]
+impl<C: LlmClient> LlmClient for MeteredClient<C> {
+    fn provider(&self) -> &'static str {
+        self.inner.provider()
+    }
+
+    fn default_model(&self) -> &str {
+        self.inner.default_model()
+    }
+
+    fn endpoint(&self) -> Option<&str> {
+        self.inner.endpoint()
+    }
+
+    fn default_temperature(&self) -> Option<f32> {
+        self.inner.default_temperature()
+    }
+
+    async fn chat(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatCompletion> {
+        let _reservation = self.reserve(messages, options)?;
+        let started = Instant::now();
+        let completion = self.inner.chat(messages, options).await?;
+        self.tracker.record(
+            &self.agent,
+            &self.session,
+            self.inner.provider(),
+            &completion.model,
+            completion.usage.unwrap_or_default(),
+            started.elapsed(),
+        );
+        Ok(completion)
+    }
+
+    // recorded when the final event arrives, under the model it names;
+    // latency covers the whole stream
+    async fn chat_stream(&self, messages: &[ChatMessage], options: &ChatOptions) -> LlmResult<ChatStream> {
+        let reservation = self.reserve(messages, options)?;
+        let started = Instant::now();
+        let stream = self.inner.chat_stream(messages, options).await?;
+
+        let tracker = self.tracker.clone();
+        let (agent, session) = (self.agent.clone(), self.session.clone());
+        let provider = self.inner.provider();
+        let requested = options.model.clone().unwrap_or_else(|| self.inner.default_model().to_string());
+        let mut reservation = Some(reservation);
+        Ok(Box::pin(stream.inspect(move |event| {
+            if let Ok(StreamEvent::Done { usage, model, .. }) = event {
+                let model = model.as_deref().unwrap_or(&requested);
+                tracker.record(&agent, &session, provider, model, usage.unwrap_or_default(), started.elapsed());
+                reservation.take();
+            }
+        })))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use futures::stream;
+
+    // answers as a dated snapshot of whatever model was asked for
+    struct Snapshot;
+
+    #[async_trait]
+    impl LlmClient for Snapshot {
+        fn provider(&self) -> &'static str {
+            "openai"
+        }
+
+        fn default_model(&self) -> &str {
+            "gpt-4o"
+        }
+
+        async fn chat(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatCompletion> {
+            Ok(ChatCompletion {
+                message: ChatMessage::assistant("hi"),
+                model: "gpt-4o-2024-08-06".into(),
+                finish_reason: Some("stop".into()),
+                usage: Some(Usage { prompt_tokens: 1000, completion_tokens: 1000 }),
+            })
+        }
+
+        async fn chat_stream(&self, _messages: &[ChatMessage], _options: &ChatOptions) -> LlmResult<ChatStream> {
+            Ok(Box::pin(stream::iter(vec![
+                Ok(StreamEvent::Delta("hi".into())),
+                Ok(StreamEvent::Done {
+                    finish_reason: Some("stop".into()),
+                    usage: Some(Usage { prompt_tokens: 1000, completion_tokens: 1000 }),
+                    model: Some("gpt-4o-2024-08-06".into()),
+                }),
+            ])))
+        }
+    }
+
+    #[tokio::test]
+    async fn streams_are_recorded_under_the_answering_model() {
+        let tracker = Arc::new(UsageTracker::default());
+        let client = MeteredClient::new(Snapshot, tracker.clone(), "agent");
+        let events: Vec<_> = client.chat_stream(&[ChatMessage::user("hi")], &ChatOptions::default()).await.unwrap().collect().await;
+        assert_eq!(events.len(), 2);
+        assert_eq!(tracker.records()[0].model, "gpt-4o-2024-08-06");
+        assert!(tracker.totals_by(GroupBy::Model).contains_key("gpt-4o-2024-08-06"));
+    }
+
+    #[test]
+    fn calls_in_flight_count_against_the_hard_budget() {
+        let tracker = Arc::new(UsageTracker::default().with_budget("agent", Budget::hard(0.05)));
+        let first = tracker.reserve("agent", 0.03).unwrap();
+        // a concurrent call would pass a plain spend check, but not with the
+        // first call's estimate held
+        assert!(matches!(tracker.reserve("agent", 0.03), Err(LlmError::BudgetExceeded { .. })));
+        drop(first);
+        let second = tracker.reserve("agent", 0.03).unwrap();
+        tracker.record("agent", "", "openai", "gpt-4o", Usage { prompt_tokens: 10_000, completion_tokens: 0 }, Duration::ZERO);
+        drop(second);
+        assert!((tracker.spent("agent") - 0.025).abs() < 1e-9);
+        assert!(matches!(tracker.reserve("agent", 0.03), Err(LlmError::BudgetExceeded { .. })));
+        assert!(tracker.reserve("agent", 0.02).is_ok());
+        // other agents have no budget to hold against
+        assert!(tracker.reserve("other", 10.0).is_ok());
+    }
+
+    #[test]
+    fn totals_outlive_the_record_history() {
+        let tracker = UsageTracker::default().with_history(2);
+        for (agent, model) in [("a", "gpt-4o"), ("a", "gpt-4o-mini"), ("b", "gpt-4o")] {
+            tracker.record(agent, "s", "openai", model, Usage { prompt_tokens: 100, completion_tokens: 10 }, Duration::from_millis(10));
+        }
+        assert_eq!(tracker.records().len(), 2);
+        assert_eq!(tracker.records()[0].model, "gpt-4o-mini");
+        assert_eq!(tracker.totals().requests, 3);
+        assert_eq!(tracker.totals_by(GroupBy::Agent)["a"].requests, 2);
+        assert_eq!(tracker.totals_by(GroupBy::Model)["gpt-4o"].prompt_tokens, 200);
+        assert_eq!(tracker.totals_by(GroupBy::Session)["s"].requests, 3);
+    }
+
+    #[test]
+    fn older_claude_models_are_priced() {
+        let prices = PriceTable::default();
+        let usage = Usage { prompt_tokens: 1_000_000, completion_tokens: 0 };
+        assert_eq!(prices.cost("anthropic", "claude-3-5-sonnet-20241022", &usage), Some(3.0));
+        assert_eq!(prices.cost("anthropic", "claude-3-7-sonnet-latest", &usage), Some(3.0));
+        assert_eq!(prices.cost("anthropic", "claude-3-opus-20240229", &usage), Some(15.0));
+        assert_eq!(prices.cost("anthropic", "claude-3-haiku-20240307", &usage), Some(0.25));
+        assert_eq!(prices.cost("anthropic", "claude-3-5-haiku-latest", &usage), Some(0.80));
+    }
+}
@@ -0,0 +1,28 @@
+use connecting_llm_api::ollama::send_to_ollama;
+use connecting_llm_api::openai::send_to_openai;
+use connecting_llm_api::LlmError;
+use std::env;
+
+// a replay cassette that cannot be loaded must fail requests instead of
+// letting them reach the real providers
+#[tokio::test]
+async fn unloadable_cassette_fails_every_request() {
+    let corrupt = env::temp_dir().join(format!("corrupt-cassette-{}.json", std::process::id()));
+    std::fs::write(&corrupt, "[{\"request\": ").unwrap();
+    // SAFETY: no other test in this binary touches the environment
+    unsafe {
+        env::set_var("LLM_CASSETTE", &corrupt);
+        env::remove_var("LLM_CASSETTE_MODE");
+        env::remove_var("LLM_PROFILES");
+        // a request that slipped through would fail as unavailable, not as a replay miss
+        env::set_var("OLLAMA_HOST", "127.0.0.1:9");
+    }
+
+    for result in [send_to_openai("hello").await, send_to_ollama("hello").await] {
+        match result {
+            Err(LlmError::Replay(message)) => assert!(message.contains("cannot load"), "{}", message),
+            other => panic!("expected a replay error, got {:?}", other),
+        }
+    }
+    let _ = std::fs::remove_file(corrupt);
+}
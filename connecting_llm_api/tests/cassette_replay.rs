@@ -0,0 +1,31 @@
+use connecting_llm_api::ollama::send_to_ollama;
+use connecting_llm_api::openai::send_to_openai;
+use connecting_llm_api::LlmError;
+use std::env;
+
+// replays tests/cassettes/send_to.json, so neither an API key nor a running
+// ollama is needed. the cassette is process-wide and read once, hence a
+// single test.
+#[tokio::test]
+async fn send_helpers_replay_offline() {
+    // SAFETY: no other test in this binary touches the environment
+    unsafe {
+        env::set_var("LLM_CASSETTE", concat!(env!("CARGO_MANIFEST_DIR"), "/tests/cassettes/send_to.json"));
+        env::remove_var("LLM_CASSETTE_MODE");
+        env::remove_var("LLM_PROFILES");
+        env::remove_var("OPENAI_API_KEY");
+        env::remove_var("OPENAI_BASE_URL");
+        env::remove_var("OLLAMA_HOST");
+    }
+
+    let reply = send_to_openai("What is the capital of France?").await.unwrap();
+    assert_eq!(reply, "Paris is the capital of France.");
+    let reply = send_to_ollama("Write a rust function that adds two numbers").await.unwrap();
+    assert_eq!(reply, "fn add(a: i32, b: i32) -> i32 { a + b }");
+
+    // every recorded interaction is used up, and nothing falls through to the network
+    match send_to_openai("What is the capital of France?").await {
+        Err(LlmError::Replay(_)) => {}
+        other => panic!("expected a replay miss, got {:?}", other),
+    }
+}
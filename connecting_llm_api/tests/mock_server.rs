@@ -0,0 +1,426 @@
+// drives every client against the mock server, over each route and each
+// kind of scripted reply
+
+use connecting_llm_api::mock_server::{MockConfig, MockReply, MockRule, MockServer};
+use connecting_llm_api::tokens::{context_window, counter_for};
+use connecting_llm_api::*;
+use futures::StreamExt;
+use serde_json::json;
+use std::{sync::Arc, time::Duration};
+
+async fn server() -> MockServer {
+    MockServer::start(MockConfig::default()).await.unwrap()
+}
+
+// one client per dialect and route the mock serves
+fn clients(server: &MockServer) -> Vec<(&'static str, Box<dyn LlmClient>)> {
+    vec![
+        (
+            "openai",
+            Box::new(OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap()),
+        ),
+        (
+            "azure",
+            Box::new(OpenAiClient::new(OpenAiConfig::azure(server.url(), "azure-key", "gpt-4o").with_deployment("gpt-4o", "chat")).unwrap()),
+        ),
+        (
+            "anthropic",
+            Box::new(AnthropicClient::new(AnthropicConfig::new("sk-ant-test", "claude-sonnet-4").with_base_url(server.openai_base_url())).unwrap()),
+        ),
+        (
+            "gemini",
+            Box::new(GeminiClient::new(GeminiConfig::new("gemini-key", "gemini-2.0-flash").with_base_url(format!("{}/v1beta", server.url()))).unwrap()),
+        ),
+        ("ollama", Box::new(OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap())),
+    ]
+}
+
+fn weather_tool() -> ToolDefinition {
+    ToolDefinition {
+        name: "get_weather".into(),
+        description: "current weather in a city".into(),
+        parameters: json!({"type": "object", "properties": {"city": {"type": "string"}}, "required": ["city"]}),
+    }
+}
+
+// the streamed text, the tool calls, and how many `Done` records arrived
+async fn collect(mut stream: ChatStream) -> LlmResult<(String, Vec<ToolCall>, usize)> {
+    let (mut text, mut calls, mut done) = (String::new(), vec![], 0);
+    while let Some(event) = stream.next().await {
+        match event? {
+            StreamEvent::Delta(delta) => text.push_str(&delta),
+            StreamEvent::ToolCalls(more) => calls.extend(more),
+            StreamEvent::Done { .. } => done += 1,
+        }
+    }
+    Ok((text, calls, done))
+}
+
+#[tokio::test]
+async fn text_replies() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::text("Paris is the capital of France.")).when_contains("capital"));
+    for (name, client) in clients(&server) {
+        let completion = client.chat(&[ChatMessage::user("What is the capital of France?")], &ChatOptions::default()).await.unwrap();
+        assert_eq!(completion.content(), "Paris is the capital of France.", "{}", name);
+        assert!(completion.usage.is_some_and(|usage| usage.completion_tokens > 0), "{}", name);
+
+        let (text, calls, done) = collect(client.chat_stream(&[ChatMessage::user("the capital?")], &ChatOptions::default()).await.unwrap())
+            .await
+            .unwrap();
+        assert_eq!(text, "Paris is the capital of France.", "{}", name);
+        assert!(calls.is_empty(), "{}", name);
+        assert_eq!(done, 1, "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn echo_fallback_and_recorded_requests() {
+    let server = server().await;
+    for (name, client) in clients(&server) {
+        let completion = client.chat(&[ChatMessage::user("ping")], &ChatOptions::default()).await.unwrap();
+        assert_eq!(completion.content(), "mock reply to: ping", "{}", name);
+    }
+    let paths: Vec<String> = server.received().into_iter().map(|request| request.path).collect();
+    assert_eq!(
+        paths,
+        [
+            "/v1/chat/completions",
+            "/openai/deployments/chat/chat/completions",
+            "/v1/messages",
+            "/v1beta/models/gemini-2.0-flash:generateContent",
+            "/api/chat",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn tool_calls() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::tool_call("get_weather", json!({"city": "Oslo"}))));
+    let options = ChatOptions::default().with_tools(vec![weather_tool()]);
+    for (name, client) in clients(&server) {
+        let completion = client.chat(&[ChatMessage::user("weather in Oslo?")], &options).await.unwrap();
+        let calls = completion.tool_calls();
+        assert_eq!(calls.len(), 1, "{}", name);
+        assert_eq!(calls[0].name, "get_weather", "{}", name);
+        assert_eq!(calls[0].arguments, json!({"city": "Oslo"}), "{}", name);
+
+        let (text, calls, done) = collect(client.chat_stream(&[ChatMessage::user("weather in Oslo?")], &options).await.unwrap())
+            .await
+            .unwrap();
+        assert!(text.is_empty(), "{}", name);
+        assert_eq!(calls.len(), 1, "{}", name);
+        assert_eq!(calls[0].arguments, json!({"city": "Oslo"}), "{}", name);
+        assert_eq!(done, 1, "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn error_replies() {
+    let server = server().await;
+    for (name, client) in clients(&server) {
+        server.push_reply(MockReply::rate_limited(7));
+        match client.chat(&[ChatMessage::user("hi")], &ChatOptions::default()).await {
+            Err(error @ LlmError::RateLimited { .. }) => assert_eq!(error.retry_after(), Some(Duration::from_secs(7)), "{}", name),
+            other => panic!("{}: expected a rate limit, got {:?}", name, other),
+        }
+
+        server.push_reply(MockReply::Error { status: 401, message: "bad key".into(), retry_after: None });
+        assert!(matches!(client.chat(&[ChatMessage::user("hi")], &ChatOptions::default()).await, Err(LlmError::Auth(_))), "{}", name);
+
+        server.push_reply(MockReply::Error { status: 500, message: "boom".into(), retry_after: None });
+        match client.chat_stream(&[ChatMessage::user("hi")], &ChatOptions::default()).await {
+            Err(LlmError::Api { status: 500, message }) => assert!(message.contains("boom"), "{}", name),
+            Err(other) => panic!("{}: expected an API error, got {:?}", name, other),
+            Ok(_) => panic!("{}: expected an API error, got a stream", name),
+        }
+    }
+}
+
+#[tokio::test]
+async fn malformed_replies() {
+    let server = server().await;
+    for (name, client) in clients(&server) {
+        server.push_reply(MockReply::Malformed { body: "{\"not\": \"a completion\"".into() });
+        let result = client.chat(&[ChatMessage::user("hi")], &ChatOptions::default()).await;
+        assert!(matches!(result, Err(LlmError::MalformedResponse(_))), "{}: {:?}", name, result);
+    }
+}
+
+#[tokio::test]
+async fn rules_match_on_model_and_run_out() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::text("only once")).times(1));
+    let client = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+    let reply = |prompt: &'static str| {
+        let client = &client;
+        async move { client.chat(&[ChatMessage::user(prompt)], &ChatOptions::default()).await.unwrap().message.content }
+    };
+    assert_eq!(reply("first").await, "only once");
+    assert_eq!(reply("second").await, "mock reply to: second");
+
+    let mut rule = MockRule::new(MockReply::text("from llama"));
+    rule.model = Some("llama3".into());
+    server.add_rule(rule);
+    let options = ChatOptions::default().with_model("llama3");
+    assert_eq!(client.chat(&[ChatMessage::user("x")], &options).await.unwrap().content(), "from llama");
+    assert_eq!(reply("y").await, "mock reply to: y");
+}
+
+#[tokio::test]
+async fn embeddings() {
+    let server = server().await;
+    let inputs = vec!["a cat".to_string(), "a dog".to_string(), "a cat".to_string()];
+    let clients: Vec<(&str, Box<dyn EmbeddingClient>)> = vec![
+        ("openai", Box::new(OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap())),
+        ("azure", Box::new(OpenAiClient::new(OpenAiConfig::azure(server.url(), "azure-key", "gpt-4o")).unwrap())),
+        ("ollama", Box::new(OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap())),
+    ];
+    for (name, client) in clients {
+        let embeddings = client.embed(&inputs, &EmbeddingOptions::default()).await.unwrap();
+        assert_eq!(embeddings.vectors.len(), 3, "{}", name);
+        assert_eq!(embeddings.vectors[0], embeddings.vectors[2], "{}", name);
+        assert_ne!(embeddings.vectors[0], embeddings.vectors[1], "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn ollama_admin_and_generate() {
+    let server = MockServer::start(MockConfig { models: vec!["llama3.2:3b".into()], ..MockConfig::default() }).await.unwrap();
+    let client = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+
+    assert_eq!(client.version().await.unwrap(), "0.0.0-mock");
+    assert!(client.has_model("llama3.2:3b").await.unwrap());
+    assert!(matches!(client.ensure_model().await, Err(LlmError::Config(_))));
+    assert!(matches!(client.show_model("mistral").await, Err(LlmError::Api { status: 404, .. })));
+
+    let mut progress = client.pull_model("mistral").await.unwrap();
+    let mut last = None;
+    while let Some(update) = progress.next().await {
+        last = Some(update.unwrap().status);
+    }
+    assert_eq!(last.as_deref(), Some("success"));
+    let failed: Vec<_> = client.pull_model("missing-model").await.unwrap().collect().await;
+    let error = failed.into_iter().find_map(Result::err).unwrap();
+    assert!(matches!(error, LlmError::InvalidRequest(_)) && !error.is_retryable(), "{}", error);
+    client.ensure_model().await.unwrap();
+    assert!(client.show_model("mistral").await.unwrap().capabilities.contains(&"tools".to_string()));
+    assert!(client.running_models().await.unwrap().is_empty());
+
+    client.delete_model("mistral").await.unwrap();
+    assert!(!client.has_model("mistral").await.unwrap());
+
+    let first = client.generate(&GenerateRequest::new("[INST] hi [/INST]").raw()).await.unwrap();
+    assert_eq!(first.response, "mock reply to: [INST] hi [/INST]");
+    let next = client.generate(&GenerateRequest::new("more").with_context(first.context.clone())).await.unwrap();
+    assert!(next.context.starts_with(&first.context) && next.context.len() > first.context.len());
+}
+
+#[tokio::test]
+async fn anthropic_history_is_sent_valid() {
+    let server = server().await;
+    let client = AnthropicClient::new(AnthropicConfig::new("sk-ant-test", "claude-sonnet-4").with_base_url(server.openai_base_url())).unwrap();
+
+    let history = [
+        ChatMessage::system("be brief"),
+        ChatMessage::user("hi"),
+        ChatMessage::assistant(""),
+        ChatMessage::user("still there?"),
+    ];
+    client.chat(&history, &ChatOptions::default()).await.unwrap();
+    let body = &server.received()[0].body;
+    assert_eq!(body["system"], "be brief");
+    // the empty assistant turn is dropped and the user turns around it merged
+    let messages = body["messages"].as_array().unwrap();
+    assert_eq!(messages.len(), 1);
+    assert_eq!(messages[0]["role"], "user");
+    assert_eq!(messages[0]["content"].as_array().unwrap().len(), 2);
+
+    let mut reply = ChatMessage::user("sunny");
+    reply.role = Role::Tool;
+    let result = client.chat(&[ChatMessage::user("weather?"), reply], &ChatOptions::default()).await;
+    assert!(matches!(result, Err(LlmError::InvalidRequest(_))), "{:?}", result);
+    assert_eq!(server.received().len(), 1, "the invalid request must not be sent");
+}
+
+#[tokio::test]
+async fn cut_off_streams_are_errors() {
+    let server = server().await;
+    let ollama = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+    let openai = OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap();
+    let bodies: [(&str, &dyn LlmClient, &str); 2] = [
+        ("ollama", &ollama, "{\"model\":\"mistral\",\"message\":{\"role\":\"assistant\",\"content\":\"Par\"},\"done\":false}\n"),
+        ("openai", &openai, "data: {\"model\":\"gpt-4o\",\"choices\":[{\"index\":0,\"delta\":{\"content\":\"Par\"},\"finish_reason\":null}]}\n\n"),
+    ];
+    for (name, client, body) in bodies {
+        server.push_reply(MockReply::Malformed { body: body.into() });
+        let mut stream = client.chat_stream(&[ChatMessage::user("capital?")], &ChatOptions::default()).await.unwrap();
+        assert!(matches!(stream.next().await, Some(Ok(StreamEvent::Delta(delta))) if delta == "Par"), "{}", name);
+        match stream.next().await {
+            Some(Err(LlmError::MalformedResponse(message))) => assert!(message.contains("before completion"), "{}", name),
+            other => panic!("{}: expected a truncation error, got {:?}", name, other),
+        }
+        assert!(stream.next().await.is_none(), "{}", name);
+    }
+}
+
+#[tokio::test]
+async fn streams_time_out_only_when_idle() {
+    let server = MockServer::start(MockConfig { chunk_delay_ms: 150, ..MockConfig::default() }).await.unwrap();
+    server.add_rule(MockRule::new(MockReply::text("one two three four five six")));
+    let mut config = OllamaConfig::new("mistral").with_host(server.url());
+    config.timeout = Duration::from_millis(400);
+    let client = OllamaClient::new(config).unwrap();
+
+    // the whole reply takes about a second, but chunks keep arriving
+    let (text, _, done) = collect(client.chat_stream(&[ChatMessage::user("count")], &ChatOptions::default()).await.unwrap())
+        .await
+        .unwrap();
+    assert_eq!(text, "one two three four five six");
+    assert_eq!(done, 1);
+
+    let server = MockServer::start(MockConfig { chunk_delay_ms: 800, ..MockConfig::default() }).await.unwrap();
+    let mut config = OllamaConfig::new("mistral").with_host(server.url());
+    config.timeout = Duration::from_millis(400);
+    let client = OllamaClient::new(config).unwrap();
+    let stream = client.chat_stream(&[ChatMessage::user("count")], &ChatOptions::default()).await.unwrap();
+    assert!(matches!(collect(stream).await, Err(LlmError::Timeout)));
+}
+
+#[tokio::test]
+async fn summaries_follow_the_callers_options() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::text("they talked about rust")).when_contains("user: question"));
+    let client = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+
+    let mut conversation = Conversation::new();
+    for turn in 0..4 {
+        conversation.push(ChatMessage::user(format!("question {}", turn)));
+        conversation.push(ChatMessage::assistant(format!("answer {}", turn)));
+    }
+    let options = ChatOptions::default().with_model("llama3").with_tools(vec![weather_tool()]);
+    let counter = counter_for("ollama", "llama3");
+    conversation
+        .trim_to(&TrimStrategy::Summarize { keep_last: 1 }, 60, counter.as_ref(), &client, &options)
+        .await
+        .unwrap();
+
+    assert!(conversation.messages()[0].content.contains("they talked about rust"), "{:?}", conversation.messages());
+    let summary_request = &server.received()[0].body;
+    assert_eq!(summary_request["model"], "llama3");
+    assert!(summary_request.get("tools").is_none());
+}
+
+#[tokio::test]
+async fn failed_sends_leave_the_history_alone() {
+    let server = server().await;
+    let client = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+    let mut conversation = Conversation::new().with_trim(TrimStrategy::KeepLast(1));
+    for turn in 0..3 {
+        conversation.push(ChatMessage::user(format!("question {}", turn)));
+        conversation.push(ChatMessage::assistant(format!("answer {}", turn)));
+    }
+    // leaves a prompt budget of a few tokens, so the history gets trimmed
+    let reserve = context_window("mistral") as u32 - 10;
+    let options = ChatOptions::default().with_max_tokens(reserve);
+
+    server.push_reply(MockReply::Error { status: 500, message: "boom".into(), retry_after: None });
+    assert!(conversation.send(&client, "again", &options).await.is_err());
+    assert_eq!(conversation.messages().len(), 6);
+
+    conversation.send(&client, "again", &options).await.unwrap();
+    assert_eq!(conversation.messages().len(), 2);
+    assert_eq!(conversation.messages()[1].content, "mock reply to: again");
+}
+
+#[tokio::test]
+async fn metered_streams_record_the_answering_model() {
+    let server = server().await;
+    for (name, client) in clients(&server) {
+        // the model the server says answered, e.g. an Azure deployment's
+        let model = client.chat(&[ChatMessage::user("hello")], &ChatOptions::default()).await.unwrap().model;
+        let tracker = Arc::new(UsageTracker::new(PriceTable::empty()));
+        let metered = MeteredClient::new(Arc::<dyn LlmClient>::from(client), tracker.clone(), "agent");
+        collect(metered.chat_stream(&[ChatMessage::user("hello")], &ChatOptions::default()).await.unwrap())
+            .await
+            .unwrap();
+        let records = tracker.records();
+        assert_eq!(records.len(), 1, "{}", name);
+        assert_eq!(records[0].model, model, "{}", name);
+        assert!(records[0].completion_tokens > 0, "{}", name);
+    }
+}
+
+// a token cancelled shortly after the call starts
+fn cancel_soon() -> CancellationToken {
+    let token = CancellationToken::new();
+    let later = token.clone();
+    tokio::spawn(async move {
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        later.cancel();
+    });
+    token
+}
+
+#[tokio::test]
+async fn cancelling_aborts_an_in_flight_chat() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::text("too late")).with_latency(Duration::from_secs(10)));
+    let client = OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap();
+
+    let started = std::time::Instant::now();
+    let result = client.chat(&[ChatMessage::user("hi")], &ChatOptions::default().with_cancellation(cancel_soon())).await;
+    assert!(matches!(result, Err(LlmError::Cancelled)), "{:?}", result.map(|c| c.message.content));
+    assert!(started.elapsed() < Duration::from_secs(2));
+}
+
+#[tokio::test]
+async fn cancelling_ends_a_stream() {
+    let server = MockServer::start(MockConfig { chunk_delay_ms: 200, ..MockConfig::default() }).await.unwrap();
+    server.push_reply(MockReply::text("one two three four five six seven eight nine ten"));
+    let client = OllamaClient::new(OllamaConfig::new("mistral").with_host(server.url())).unwrap();
+
+    let token = CancellationToken::new();
+    let mut stream = client.chat_stream(&[ChatMessage::user("count")], &ChatOptions::default().with_cancellation(token.clone())).await.unwrap();
+    assert!(matches!(stream.next().await, Some(Ok(StreamEvent::Delta(_)))));
+    token.cancel();
+    assert!(matches!(stream.next().await, Some(Err(LlmError::Cancelled))));
+    assert!(stream.next().await.is_none());
+}
+
+#[tokio::test]
+async fn cancelling_cuts_a_retry_backoff_short() {
+    let server = server().await;
+    server.add_rule(MockRule::new(MockReply::rate_limited(20)));
+    let client = RetryingClient::new(
+        OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap(),
+        RetryPolicy::default(),
+    );
+
+    let started = std::time::Instant::now();
+    let result = client.chat(&[ChatMessage::user("hi")], &ChatOptions::default().with_cancellation(cancel_soon())).await;
+    assert!(matches!(result, Err(LlmError::Cancelled)), "{:?}", result.map(|c| c.message.content));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    // the first attempt went out, the retry never did
+    assert_eq!(server.received().len(), 1);
+}
+
+#[tokio::test]
+async fn cancelling_leaves_the_rate_limit_queue() {
+    let server = server().await;
+    let limiter = Arc::new(RateLimiter::new(RateLimits::new(1, 100_000)));
+    let client = RateLimitedClient::new(
+        OpenAiClient::new(OpenAiConfig::new("sk-test", "gpt-4o").with_base_url(server.openai_base_url())).unwrap(),
+        limiter,
+    );
+    client.chat(&[ChatMessage::user("first")], &ChatOptions::default()).await.unwrap();
+
+    // the next slot is a minute away
+    let started = std::time::Instant::now();
+    let result = client.chat(&[ChatMessage::user("second")], &ChatOptions::default().with_cancellation(cancel_soon())).await;
+    assert!(matches!(result, Err(LlmError::Cancelled)), "{:?}", result.map(|c| c.message.content));
+    assert!(started.elapsed() < Duration::from_secs(2));
+    assert_eq!(client.headroom(&ChatOptions::default()).queued, 0);
+    assert_eq!(server.received().len(), 1);
+}
@@ -0,0 +1,164 @@
+use connecting_llm_api::{LlmClient, ProfileError, Profiles, ProviderKind};
+use std::env;
+
+// every test reads its own variables, so they can run in parallel
+
+#[test]
+fn toml_and_yaml_describe_the_same_profiles() {
+    let toml = r#"
+default = "fast"
+
+[profiles.fast]
+provider = "openai"
+model = "gpt-4o-mini"
+api_key = "sk-test"
+temperature = 0.2
+
+[profiles.local]
+provider = "ollama"
+model = "llama3.1"
+base_url = "localhost:11434"
+keep_alive = -1
+options = { seed = 7, num_ctx = 8192 }
+"#;
+    let yaml = r#"
+default: fast
+profiles:
+  fast:
+    provider: openai
+    model: gpt-4o-mini
+    api_key: sk-test
+    temperature: 0.2
+  local:
+    provider: ollama
+    model: llama3.1
+    base_url: localhost:11434
+    keep_alive: -1
+    options:
+      seed: 7
+      num_ctx: 8192
+"#;
+    for profiles in [Profiles::from_toml_str(toml).unwrap(), Profiles::from_yaml_str(yaml).unwrap()] {
+        assert_eq!(profiles.default_name(), Some("fast"));
+        let fast = profiles.get("fast").unwrap();
+        assert_eq!((fast.provider, fast.model.as_str(), fast.temperature), (ProviderKind::OpenAi, "gpt-4o-mini", Some(0.2)));
+        let local = profiles.get("local").unwrap();
+        assert_eq!(local.base_url.as_deref(), Some("http://localhost:11434"));
+        assert_eq!(local.options.as_ref().unwrap().seed, Some(7));
+
+        let client = profiles.default_client().unwrap();
+        assert_eq!((client.name(), client.default_model(), client.default_temperature()), ("fast", "gpt-4o-mini", Some(0.2)));
+    }
+}
+
+#[test]
+fn strings_take_variables_fallbacks_and_escapes() {
+    // SAFETY: the variables are only read by this test
+    unsafe {
+        env::set_var("PROFILE_FILES_MODEL", "llama3.1");
+        env::set_var("PROFILE_FILES_EMPTY", "");
+        env::remove_var("PROFILE_FILES_UNSET");
+    }
+    let profiles = Profiles::from_toml_str(
+        r#"
+[profiles.local]
+provider = "ollama"
+model = "${PROFILE_FILES_MODEL}"
+base_url = "${PROFILE_FILES_EMPTY:-http://localhost:11434}"
+system_prompt = "costs $$5, model ${PROFILE_FILES_MODEL}, ${PROFILE_FILES_UNSET:-no fallback needed}"
+
+[profiles.cloud]
+provider = "openai"
+model = "gpt-4o"
+api_key = "${PROFILE_FILES_UNSET}"
+"#,
+    )
+    .unwrap();
+    let local = profiles.get("local").unwrap();
+    assert_eq!(local.model, "llama3.1");
+    assert_eq!(local.base_url.as_deref(), Some("http://localhost:11434"));
+    assert_eq!(local.system_prompt.as_deref(), Some("costs $5, model llama3.1, no fallback needed"));
+
+    // the unset key only fails the profile that needs it
+    profiles.client("local").unwrap();
+    match profiles.client("cloud") {
+        Err(error) => assert!(error.to_string().contains("PROFILE_FILES_UNSET"), "{}", error),
+        Ok(_) => panic!("a profile with an unset key built a client"),
+    }
+}
+
+#[test]
+fn later_files_replace_profiles_and_the_default() {
+    let base = Profiles::from_toml_str(
+        r#"
+default = "a"
+[profiles.a]
+provider = "ollama"
+model = "mistral"
+[profiles.b]
+provider = "ollama"
+model = "llama3.1"
+"#,
+    )
+    .unwrap();
+    let file = env::temp_dir().join(format!("profile-files-{}.yaml", std::process::id()));
+    std::fs::write(&file, "default: b\nprofiles:\n  b:\n    provider: ollama\n    model: qwen2.5\n").unwrap();
+    let layered = base.merge(Profiles::from_file(&file).unwrap());
+    let _ = std::fs::remove_file(&file);
+
+    assert_eq!(layered.default_name(), Some("b"));
+    assert_eq!(layered.get("a").unwrap().model, "mistral");
+    assert_eq!(layered.get("b").unwrap().model, "qwen2.5");
+    assert!(matches!(layered.get("c"), Err(ProfileError::UnknownProfile(_))));
+}
+
+#[test]
+fn every_problem_is_reported_at_once() {
+    let error = Profiles::from_toml_str(
+        r#"
+default = "missing"
+
+[profiles.broken]
+provider = "anthropic"
+model = " "
+base_url = "ftp://example.com"
+headers = { x-team = "agents" }
+timeout_secs = 0
+max_attempts = 0
+temperature = 3.5
+
+[profiles.azure]
+provider = "azure-openai"
+model = "gpt-4o"
+api_key = "key"
+api_key_env = "AZURE_KEY"
+keep_alive = 60
+"#,
+    )
+    .unwrap_err();
+    let ProfileError::Invalid(problems) = error else {
+        panic!("expected validation problems, got {}", error);
+    };
+    let expected = [
+        "`model` is empty",
+        "anthropic needs `api_key` or `api_key_env`",
+        "`base_url` \"ftp://example.com\" is not an http(s) URL",
+        "`headers` is only supported by openai providers",
+        "`timeout_secs` must be positive",
+        "`max_attempts` must be at least 1",
+        "`temperature` 3.5 is outside 0.0..=2.0",
+        "set either `api_key` or `api_key_env`, not both",
+        "azure-openai needs `base_url`",
+        "`keep_alive` is only supported by ollama",
+        "`default` names the unknown profile `missing`",
+    ];
+    for message in expected {
+        assert!(problems.iter().any(|problem| problem.contains(message)), "{} not in {:?}", message, problems);
+    }
+    assert_eq!(problems.len(), expected.len(), "{:?}", problems);
+
+    // unknown fields and malformed files are parse errors
+    let typo = "[profiles.a]\nprovider = \"ollama\"\nmodel = \"mistral\"\ntemprature = 0.2\n";
+    assert!(matches!(Profiles::from_toml_str(typo), Err(ProfileError::Parse(message)) if message.contains("temprature")));
+    assert!(matches!(Profiles::from_yaml_str("profiles: [\n"), Err(ProfileError::Parse(_))));
+}
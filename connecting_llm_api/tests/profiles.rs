@@ -0,0 +1,49 @@
+use connecting_llm_api::Profiles;
+use std::env;
+
+// the built-in profiles read OLLAMA_HOST and OPENAI_BASE_URL, so the cases run
+// in one test to keep them from racing on the environment
+#[test]
+fn builtin_profiles_follow_the_environment() {
+    // SAFETY: no other test in this binary touches the environment
+    unsafe {
+        env::set_var("OLLAMA_HOST", "0.0.0.0:11434");
+        env::remove_var("OPENAI_BASE_URL");
+    }
+    let profiles = Profiles::builtin().expect("a bare host:port is accepted");
+    assert_eq!(profiles.get("ollama").unwrap().base_url.as_deref(), Some("http://0.0.0.0:11434"));
+    assert_eq!(profiles.get("openai").unwrap().base_url.as_deref(), Some("https://api.openai.com/v1"));
+
+    unsafe {
+        env::set_var("OLLAMA_HOST", "https://ollama.internal:443");
+    }
+    let profiles = Profiles::builtin().unwrap();
+    assert_eq!(profiles.get("ollama").unwrap().base_url.as_deref(), Some("https://ollama.internal:443"));
+
+    // a broken endpoint fails only the profile using it, and without a panic
+    unsafe {
+        env::set_var("OPENAI_BASE_URL", "api.openai.com/v1");
+    }
+    let profiles = Profiles::builtin().unwrap();
+    let error = profiles.client("openai").err().unwrap().to_string();
+    assert!(error.contains("profile `openai`"), "{}", error);
+    assert!(error.contains("not an http(s) URL"), "{}", error);
+    profiles.client("ollama").unwrap();
+
+    // LLM_PROFILES is layered over the built-in profiles
+    let file = env::temp_dir().join(format!("llm-profiles-{}.toml", std::process::id()));
+    std::fs::write(&file, "default = \"local\"\n[profiles.local]\nprovider = \"ollama\"\nmodel = \"llama3.1\"\n").unwrap();
+    unsafe {
+        env::remove_var("OPENAI_BASE_URL");
+        env::set_var("LLM_PROFILES", &file);
+    }
+    let profiles = Profiles::discover().unwrap();
+    assert_eq!(profiles.names().collect::<Vec<_>>(), ["local", "ollama", "openai"]);
+    assert_eq!(profiles.default_name(), Some("local"));
+    let _ = std::fs::remove_file(&file);
+
+    unsafe {
+        env::remove_var("OLLAMA_HOST");
+        env::remove_var("LLM_PROFILES");
+    }
+}
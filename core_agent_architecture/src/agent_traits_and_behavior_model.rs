@@ -82,7 +82,7 @@ where
         let result = agent.handle_input(input).await;
 
         if let AgentStatus::Success = result.status {
-            let output = agent.use_tool("notify", &[result.output.clone()]).await;
+            let output = agent.use_tool("notify", std::slice::from_ref(&result.output)).await;
             println!("Tool execution result: {:?}", output);
         }
     }
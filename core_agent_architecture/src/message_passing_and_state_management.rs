@@ -1,8 +1,10 @@
 #![allow(unused)]
 
-use std::{collections::HashMap, sync::{Arc}};
+use std::{collections::HashMap, future::Future, sync::{Arc}, time::Duration};
 use tokio::sync::{Mutex, RwLock};
 use tokio::sync::{mpsc, oneshot};
+use tokio::task::JoinHandle;
+use tokio_util::sync::CancellationToken;
 
 // asynchronous message passing with channels
 #[derive(Debug)]
@@ -97,14 +99,21 @@ pub async fn state_management() {
         }
     });
 
-    let _data = {
+    {
         let store = memory.read().await;
         let _ = store.get("key").cloned();
-    };
+    }
 }
 
-async fn agent_main(mut rx: mpsc::Receiver<AgentMessage>, state: Arc<Mutex<AgentState>>) {
-    while let Some(msg) = rx.recv().await {
+async fn agent_main(mut rx: mpsc::Receiver<AgentMessage>, state: Arc<Mutex<AgentState>>, shutdown: CancellationToken) {
+    loop {
+        let msg = tokio::select! {
+            _ = shutdown.cancelled() => break,
+            msg = rx.recv() => match msg {
+                Some(msg) => msg,
+                None => break,
+            },
+        };
         match msg {
             AgentMessage::Command(cmd) => {
                 {
@@ -113,7 +122,11 @@ async fn agent_main(mut rx: mpsc::Receiver<AgentMessage>, state: Arc<Mutex<Agent
                 }
                 println!("Executeing task: {}", cmd);
             }
-            AgentMessage::Shutdown => break,
+            AgentMessage::Shutdown => {
+                // also aborts the work started with child tokens
+                shutdown.cancel();
+                break;
+            }
             AgentMessage::StatusUpdate { task_id, status } if status == "done" => {
                 {
                     let mut s = state.lock().await;
@@ -125,4 +138,69 @@ async fn agent_main(mut rx: mpsc::Receiver<AgentMessage>, state: Arc<Mutex<Agent
             _ => {}
         }
     }
-}
\ No newline at end of file
+}
+
+// graceful shutdown with cancellation tokens
+// the agent loop gets a token that shutting down cancels. work the agent starts
+// (LLM calls, tool runs) takes a child token, e.g. via
+// `ChatOptions::with_cancellation`, so shutdown aborts it instead of waiting for
+// it. cancelling a child token never stops the agent itself.
+pub struct AgentHandle {
+    sender: mpsc::Sender<AgentMessage>,
+    shutdown: CancellationToken,
+    task: JoinHandle<()>,
+}
+
+impl AgentHandle {
+    pub fn spawn<F, Fut>(agent: F) -> Self
+    where
+        F: FnOnce(mpsc::Receiver<AgentMessage>, CancellationToken) -> Fut,
+        Fut: Future<Output = ()> + Send + 'static,
+    {
+        let (sender, receiver) = mpsc::channel(100);
+        let shutdown = CancellationToken::new();
+        let task = tokio::spawn(agent(receiver, shutdown.clone()));
+        AgentHandle { sender, shutdown, task }
+    }
+
+    pub async fn send(&self, message: AgentMessage) -> Result<(), mpsc::error::SendError<AgentMessage>> {
+        self.sender.send(message).await
+    }
+
+    // cancelled when the agent shuts down
+    pub fn cancellation_token(&self) -> CancellationToken {
+        self.shutdown.child_token()
+    }
+
+    // cancels the agent's in-flight work and waits up to `grace` for its loop
+    // to finish before aborting it
+    pub async fn shutdown(mut self, grace: Duration) {
+        self.shutdown.cancel();
+        if tokio::time::timeout(grace, &mut self.task).await.is_err() {
+            tracing::warn!(target: "agent::shutdown", ?grace, "agent did not stop within the grace period, aborting");
+            self.task.abort();
+        }
+    }
+}
+
+pub async fn cancellable_agent() -> Result<(), mpsc::error::SendError<AgentMessage>> {
+    let state = Arc::new(Mutex::new(AgentState { current_task: None, completed_tasks: vec![] }));
+    let agent = AgentHandle::spawn(|rx, shutdown| agent_main(rx, state, shutdown));
+
+    // a long running call tied to the agent's lifetime
+    let token = agent.cancellation_token();
+    let work = tokio::spawn(async move {
+        tokio::select! {
+            _ = token.cancelled() => tracing::info!(target: "agent::work", "work cancelled"),
+            _ = tokio::time::sleep(Duration::from_secs(60)) => tracing::info!(target: "agent::work", "work finished"),
+        }
+    });
+
+    // shut down whether or not the command got through
+    let sent = agent.send(AgentMessage::Command("summarize logs".into())).await;
+    agent.shutdown(Duration::from_secs(5)).await;
+    if let Err(e) = work.await {
+        tracing::warn!(target: "agent::work", error = %e, "work task did not complete");
+    }
+    sent
+}
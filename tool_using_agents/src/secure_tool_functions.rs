@@ -1,8 +1,8 @@
 use std::{collections::HashMap, sync::Arc};
 
 use async_trait::async_trait;
-use serde_json::Value;
-use serde::Deserialize;
+use serde_json::{json, Value};
+use serde::{Deserialize, Serialize};
 use tokio::sync::RwLock;
 
 // defining a trait for all tool functions.
@@ -12,7 +12,7 @@ pub struct ToolInput {
     pub args: Value, // JSON object with parameters
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize)]
 pub struct ToolOutput {
     pub result: Value, // JSON result
     pub success: bool,
@@ -24,6 +24,11 @@ pub trait Tool: Send + Sync {
     fn name(&self) -> &'static str;
     fn description(&self) -> &'static str;
 
+    // JSON schema of the arguments, advertised to LLMs that support function calling
+    fn parameters(&self) -> Value {
+        json!({"type": "object", "properties": {}})
+    }
+
     async fn execute(&self, input: ToolInput) -> ToolOutput;
 }
 
@@ -38,6 +43,15 @@ impl Tool for LengthTool {
     fn description(&self) -> &'static str {
         "Calculates the length of a given string"
     }
+    fn parameters(&self) -> Value {
+        json!({
+            "type": "object",
+            "properties": {
+                "text": {"type": "string", "description": "The string to measure"}
+            },
+            "required": ["text"]
+        })
+    }
 
     async fn execute(&self, input: ToolInput) -> ToolOutput {
         /*